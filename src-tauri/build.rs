@@ -1,3 +1,68 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// Second line of the stand-in written when the ffmpeg sidecar hasn't been downloaded, so a later build can tell it from the real thing
+const FFMPEG_STUB_MARKER: &str = "# chronolab ffmpeg stand-in";
+
 fn main() {
+    ensure_ffmpeg_sidecar();
     tauri_build::build()
 }
+
+/// tauri-build fails when a sidecar in the externalBin list is missing, which would stop a fresh clone from building at all until ffmpeg was
+/// downloaded. For debug builds a stand-in that exits with an error is written in its place instead, so everything but the overlay video
+/// export works, and a warning says so. Release builds bundle the sidecar, so they fail here until the real ffmpeg is in place.
+fn ensure_ffmpeg_sidecar() {
+    let target = env::var("TARGET").expect("Cargo sets TARGET for build scripts");
+    let extension = if target.contains("windows") { ".exe" } else { "" };
+    let sidecar_path = PathBuf::from(env::var("CARGO_MANIFEST_DIR").expect("Cargo sets CARGO_MANIFEST_DIR for build scripts"))
+        .join("binaries")
+        .join(format!("ffmpeg-{}{}", target, extension));
+
+    println!("cargo:rerun-if-changed={}", sidecar_path.display());
+
+    let is_stand_in = match fs::read(&sidecar_path) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).contains(FFMPEG_STUB_MARKER),
+        Err(_) => {
+            write_stand_in(&sidecar_path);
+            true
+        }
+    };
+
+    if !is_stand_in {
+        return;
+    }
+
+    if env::var("PROFILE").as_deref() == Ok("release") {
+        panic!(
+            "{} is a stand-in, not ffmpeg. Release builds bundle it, so download ffmpeg as described in the README's Developer Install first.",
+            sidecar_path.display()
+        );
+    }
+
+    println!(
+        "cargo:warning=The ffmpeg sidecar hasn't been downloaded, so {} is a stand-in and overlay videos can't be exported. See the README's \
+         Developer Install.",
+        sidecar_path.display()
+    );
+}
+
+fn write_stand_in(sidecar_path: &Path) {
+    let script = format!(
+        "#!/bin/sh\n{}\necho \"The ffmpeg sidecar isn't installed, so overlay videos can't be exported. See the README's Developer Install.\" >&2\nexit 1\n",
+        FFMPEG_STUB_MARKER
+    );
+
+    if let Some(parent) = sidecar_path.parent() {
+        fs::create_dir_all(parent).expect("Failed to create the binaries directory");
+    }
+    fs::write(sidecar_path, script).expect("Failed to write the ffmpeg stand-in");
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(sidecar_path, fs::Permissions::from_mode(0o755)).expect("Failed to make the ffmpeg stand-in executable");
+    }
+}
@@ -0,0 +1,373 @@
+use crate::dataframe_handlers::{column_samples, display_data_with_columns, load_csv_dataframe, median_sample_rate_hz};
+use crate::global_state::{snapshot_app_state, AppState, LoadCsvSettings, TimestampPolicy};
+use crate::job_handlers::{run_job, Job};
+use crate::spectral_handlers::resample_uniform;
+use chrono::{NaiveDateTime, TimeDelta};
+use rustfft::{num_complex::Complex, FftPlanner};
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+use tauri::{path::SafePathBuf, AppHandle, State};
+
+/// Fewest overlapping samples a lag needs before its correlation is trusted. Tiny overlaps can correlate perfectly by chance.
+const MIN_OVERLAP_SAMPLES: usize = 8;
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Structs
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+/// Where a signal to be aligned comes from.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum AlignmentSource {
+    /// A column of the CSV that is currently loaded
+    LoadedData { column: String },
+    /// A column of some other logger's CSV
+    Csv {
+        file_path: SafePathBuf,
+        datetime_col: String,
+        datetime_parsing_format_string: String,
+        column: String,
+        #[serde(default)]
+        time_zone: Option<String>,
+    },
+    /// An evenly sampled trace of the video, like the mean brightness of each frame or the audio envelope, worked out by the caller. Nothing
+    /// in the app extracts these yet. Times are seconds from the start of the video, placed in data time using the current video start time
+    /// and clock scale.
+    VideoTrace {
+        sample_rate_hz: f64,
+        values: Vec<f64>,
+        #[serde(default)]
+        start_seconds: f64,
+    },
+}
+
+impl AlignmentSource {
+    fn is_video(&self) -> bool {
+        matches!(self, AlignmentSource::VideoTrace { .. })
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct AlignmentResult {
+    /// Seconds to add to the target's timestamps so that it lines up with the reference. This is only reported, not stored anywhere.
+    offset_seconds: f64,
+    /// Pearson correlation between the two signals at the suggested offset. Anything much under 0.5 is worth a second look.
+    confidence: f64,
+    /// How much of the two signals overlapped at the suggested offset
+    overlap_seconds: f64,
+    /// If one of the sources was a video trace, the video start time that applies the offset
+    suggested_video_start_time: Option<NaiveDateTime>,
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Tauri Commands
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Find Alignment Offset
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Finds the offset of the target source relative to the reference source that maximizes their cross-correlation, searching up to
+/// search_window_seconds either way of how they line up now. Both signals are resampled to a common rate first, by default the slower of
+/// their two sample rates.
+///
+/// This only measures the offset and changes nothing. When one source is a video trace, the result has a suggested_video_start_time that
+/// the caller can set as the video_start_time to apply it. The AppState has no clock offset for a second CSV, so an offset between two CSVs
+/// is for the user to correct in that logger's data themselves.
+#[tauri::command]
+pub async fn find_alignment_offset(
+    app: AppHandle,
+    state: State<'_, RwLock<AppState>>,
+    reference: AlignmentSource,
+    target: AlignmentSource,
+    search_window_seconds: f64,
+    sample_rate_hz: Option<f64>,
+) -> Result<AlignmentResult, String> {
+    let state = snapshot_app_state(&state);
+    let job_app = app.clone();
+
+    run_job(&app, "find_alignment_offset", move |job| {
+        if search_window_seconds <= 0.0 || !search_window_seconds.is_finite() {
+            return Err(format!(
+                "The search window must be a positive number of seconds, got {}",
+                search_window_seconds
+            ));
+        }
+
+        job.progress("Loading reference", None, None);
+        let reference_samples = source_samples(&job_app, &state, &reference, job)?;
+
+        job.checkpoint()?;
+        job.progress("Loading target", Some(30.0), None);
+        let target_samples = source_samples(&job_app, &state, &target, job)?;
+
+        job.checkpoint()?;
+
+        let sample_rate_hz = match sample_rate_hz {
+            Some(sample_rate_hz) if sample_rate_hz > 0.0 && sample_rate_hz.is_finite() => sample_rate_hz,
+            Some(sample_rate_hz) => return Err(format!("Invalid sample rate: {} Hz", sample_rate_hz)),
+            None => {
+                let rate = |samples: &[(i64, f64)]| {
+                    median_sample_rate_hz(&samples.iter().map(|(time, _)| *time).collect::<Vec<_>>())
+                };
+                rate(&reference_samples)
+                    .zip(rate(&target_samples))
+                    .map(|(reference_rate, target_rate)| reference_rate.min(target_rate))
+                    .ok_or("Could not work out the sample rate of the sources.")?
+            }
+        };
+
+        job.progress("Correlating", Some(60.0), Some(reference_samples.len() + target_samples.len()));
+
+        let (offset_seconds, confidence, overlap_samples) =
+            best_offset(&reference_samples, &target_samples, sample_rate_hz, search_window_seconds)?;
+
+        // Moving the target later is the same as moving the reference earlier
+        let video_shift_seconds = if target.is_video() {
+            Some(offset_seconds)
+        } else if reference.is_video() {
+            Some(-offset_seconds)
+        } else {
+            None
+        };
+
+        let suggested_video_start_time = video_shift_seconds
+            .zip(state.video_start_time)
+            .map(|(shift_seconds, video_start_time)| {
+                video_start_time + TimeDelta::milliseconds((shift_seconds * 1000.0).round() as i64)
+            });
+
+        Ok(AlignmentResult {
+            offset_seconds,
+            confidence,
+            overlap_seconds: overlap_samples as f64 / sample_rate_hz,
+            suggested_video_start_time,
+        })
+    })
+    .await
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Utility Functions
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Source Samples
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Loads a source as (millisecond timestamp, value) pairs in display time. A LoadedData column comes from the same data as the plot, so it
+/// can be a filtered channel.
+fn source_samples(app: &AppHandle, state: &AppState, source: &AlignmentSource, job: &Job) -> Result<Vec<(i64, f64)>, String> {
+    let display_time_zone = state.display_time_zone.as_ref().map(|time_zone| time_zone.as_ref());
+
+    let samples = match source {
+        AlignmentSource::LoadedData { column } => {
+            let datetime_index_col = &state
+                .load_csv_settings
+                .as_ref()
+                .ok_or("CSV loading settings have not been set yet.")?
+                .datetime_index_col;
+
+            let df = display_data_with_columns(app, state, std::slice::from_ref(column), job)?;
+            column_samples(&df, datetime_index_col, column)?
+        }
+
+        AlignmentSource::Csv {
+            file_path,
+            datetime_col,
+            datetime_parsing_format_string,
+            column,
+            time_zone,
+        } => {
+            let load_csv_settings = LoadCsvSettings {
+                datetime_index_col: datetime_col.clone(),
+                datetime_parsing_format_string: datetime_parsing_format_string.clone(),
+                load_cols: vec![column.clone()],
+                time_bounds: None,
+                timestamp_policy: TimestampPolicy::Sort,
+                resample: None,
+                time_zone: time_zone.clone(),
+            };
+
+            let (df, _) = load_csv_dataframe(file_path.clone(), &load_csv_settings, display_time_zone)?;
+            column_samples(&df, datetime_col, column)?
+        }
+
+        AlignmentSource::VideoTrace {
+            sample_rate_hz,
+            values,
+            start_seconds,
+        } => {
+            if *sample_rate_hz <= 0.0 || !sample_rate_hz.is_finite() {
+                return Err(format!("Invalid video trace sample rate: {} Hz", sample_rate_hz));
+            }
+
+            let video_start_ms = state
+                .display_video_start_time()?
+                .ok_or("The video start time has to be set before a video trace can be aligned.")?
+                .and_utc()
+                .timestamp_millis();
+            let video_time_scale = state.video_time_scale();
+
+            values
+                .iter()
+                .enumerate()
+                .filter(|(_, value)| value.is_finite())
+                .map(|(i, value)| {
+                    let video_seconds = start_seconds + i as f64 / sample_rate_hz;
+                    let data_seconds = video_seconds * video_time_scale;
+                    (video_start_ms + (data_seconds * 1000.0).round() as i64, *value)
+                })
+                .collect()
+        }
+    };
+
+    if samples.len() < MIN_OVERLAP_SAMPLES {
+        return Err(format!(
+            "A source needs at least {} samples to be aligned, but only {} were found.",
+            MIN_OVERLAP_SAMPLES,
+            samples.len()
+        ));
+    }
+
+    Ok(samples)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Best Offset
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Returns the offset in seconds to add to the target, the correlation at that offset, and the number of samples that overlapped there.
+/// The correlation at every whole sample lag comes from one FFT, and each lag is then normalized using only the samples that overlap at
+/// that lag, so lags near the edges of the search aren't penalized for overlapping less. The peak is refined to a fraction of a sample
+/// by fitting a parabola through it and its neighbours.
+fn best_offset(
+    reference_samples: &[(i64, f64)],
+    target_samples: &[(i64, f64)],
+    sample_rate_hz: f64,
+    search_window_seconds: f64,
+) -> Result<(f64, f64, usize), String> {
+    let reference = demeaned(resample_uniform(reference_samples, sample_rate_hz)?);
+    let target = demeaned(resample_uniform(target_samples, sample_rate_hz)?);
+
+    let (reference_len, target_len) = (reference.len(), target.len());
+
+    // Reference sample i lines up with target sample i - lag once the target is shifted by this many seconds
+    let start_difference_seconds = (target_samples[0].0 - reference_samples[0].0) as f64 / 1000.0;
+    let offset_for_lag = |lag: f64| lag / sample_rate_hz - start_difference_seconds;
+
+    let lag_for_offset = |offset_seconds: f64| ((offset_seconds + start_difference_seconds) * sample_rate_hz).round() as i64;
+    let min_lag = lag_for_offset(-search_window_seconds).max(1 - target_len as i64);
+    let max_lag = lag_for_offset(search_window_seconds).min(reference_len as i64 - 1);
+
+    if min_lag > max_lag {
+        return Err("The sources don't overlap anywhere within the search window.".to_string());
+    }
+
+    let cross_products = cross_correlate(&reference, &target);
+
+    let prefix = |values: &[f64], square: bool| {
+        let mut sums = vec![0.0; values.len() + 1];
+        for (i, value) in values.iter().enumerate() {
+            sums[i + 1] = sums[i] + if square { value * value } else { *value };
+        }
+        sums
+    };
+    let (reference_sums, reference_squares) = (prefix(&reference, false), prefix(&reference, true));
+    let (target_sums, target_squares) = (prefix(&target, false), prefix(&target, true));
+
+    let min_overlap = MIN_OVERLAP_SAMPLES.max(reference_len.min(target_len) / 2);
+
+    // Pearson correlation using only the samples that overlap at this lag
+    let correlation_at = |lag: i64| -> Option<(f64, usize)> {
+        let reference_start = lag.max(0) as usize;
+        let reference_end = (target_len as i64 + lag).min(reference_len as i64) as usize;
+        let overlap = reference_end.checked_sub(reference_start)?;
+
+        if overlap < min_overlap {
+            return None;
+        }
+
+        let target_start = (reference_start as i64 - lag) as usize;
+        let target_end = target_start + overlap;
+        let n = overlap as f64;
+
+        let sum_reference = reference_sums[reference_end] - reference_sums[reference_start];
+        let sum_target = target_sums[target_end] - target_sums[target_start];
+        let reference_variance = n * (reference_squares[reference_end] - reference_squares[reference_start]) - sum_reference.powi(2);
+        let target_variance = n * (target_squares[target_end] - target_squares[target_start]) - sum_target.powi(2);
+
+        let denominator = (reference_variance * target_variance).sqrt();
+        if denominator <= f64::EPSILON {
+            return None;
+        }
+
+        let products = cross_products[lag.rem_euclid(cross_products.len() as i64) as usize];
+        Some(((n * products - sum_reference * sum_target) / denominator, overlap))
+    };
+
+    let (best_lag, (best_correlation, best_overlap)) = (min_lag..=max_lag)
+        .filter_map(|lag| correlation_at(lag).map(|result| (lag, result)))
+        .max_by(|(_, (a, _)), (_, (b, _))| a.total_cmp(b))
+        .ok_or("The sources don't overlap enough anywhere within the search window, or one of them is flat.")?;
+
+    let fraction = match (correlation_at(best_lag - 1), correlation_at(best_lag + 1)) {
+        (Some((before, _)), Some((after, _))) if best_lag > min_lag && best_lag < max_lag => {
+            let curvature = before - 2.0 * best_correlation + after;
+            if curvature < 0.0 {
+                (0.5 * (before - after) / curvature).clamp(-0.5, 0.5)
+            } else {
+                0.0
+            }
+        }
+        _ => 0.0,
+    };
+
+    Ok((offset_for_lag(best_lag as f64 + fraction), best_correlation, best_overlap))
+}
+
+fn demeaned(mut values: Vec<f64>) -> Vec<f64> {
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    values.iter_mut().for_each(|value| *value -= mean);
+    values
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Cross Correlate
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Σ reference[i] * target[i - lag] for every lag, computed with an FFT. The result is circular: a negative lag is stored at len + lag.
+/// It is zero padded enough that the ends never wrap onto each other.
+fn cross_correlate(reference: &[f64], target: &[f64]) -> Vec<f64> {
+    let fft_len = (reference.len() + target.len() - 1).next_power_of_two();
+
+    let to_buffer = |values: &[f64]| {
+        let mut buffer: Vec<Complex<f64>> = values.iter().map(|value| Complex::new(*value, 0.0)).collect();
+        buffer.resize(fft_len, Complex::new(0.0, 0.0));
+        buffer
+    };
+
+    let mut reference_buffer = to_buffer(reference);
+    let mut target_buffer = to_buffer(target);
+
+    let mut planner = FftPlanner::new();
+    let forward = planner.plan_fft_forward(fft_len);
+    forward.process(&mut reference_buffer);
+    forward.process(&mut target_buffer);
+
+    let mut products: Vec<Complex<f64>> = reference_buffer
+        .iter()
+        .zip(&target_buffer)
+        .map(|(r, t)| r * t.conj())
+        .collect();
+
+    planner.plan_fft_inverse(fft_len).process(&mut products);
+
+    products.iter().map(|product| product.re / fft_len as f64).collect()
+}
@@ -0,0 +1,145 @@
+use crate::dataframe_handlers::{naive_datetime_from_millis, parse_datetime_expr};
+use crate::global_state::{
+    read_app_state, update_app_state_field, write_app_state, Annotation, AppState, AppStateField,
+};
+use crate::job_handlers::run_job;
+use crate::time_zones::to_display_time_expr;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+use tauri::{path::SafePathBuf, AppHandle, State};
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Structs
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+/// Describes how to read an event log CSV (valve commands, alarms, operator notes, etc) written by some other system.
+#[derive(Deserialize, Debug)]
+pub struct EventLogImportSettings {
+    pub file_path: SafePathBuf,
+    pub datetime_col: String,
+    pub datetime_parsing_format_string: String,
+    /// Column holding the event message, which becomes the annotation title
+    pub text_col: String,
+    #[serde(default)]
+    pub category_col: Option<String>,
+    /// IANA name of the time zone the event log was recorded in, if it isn't naive
+    #[serde(default)]
+    pub time_zone: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct EventLogImportSummary {
+    imported: usize,
+    /// Events that already had a matching annotation, usually from importing the same file twice
+    skipped_duplicates: usize,
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Tauri Commands
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Import Event Log
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Reads an event log CSV and adds each event to the AppState as a point in time annotation. Timestamps are parsed the same way as the
+/// datetime index in get_csv_data. An event is skipped if there is already an annotation with the same time, title, and category, so
+/// importing the same log twice doesn't double up.
+#[tauri::command]
+pub async fn import_event_log(
+    app: AppHandle,
+    state: State<'_, RwLock<AppState>>,
+    settings: EventLogImportSettings,
+) -> Result<EventLogImportSummary, String> {
+    let display_time_zone: Option<String> = read_app_state(&state).display_time_zone.clone().map(String::from);
+
+    let new_annotations = run_job(&app, "import_event_log", move |job| {
+        let mut columns = vec![
+            to_display_time_expr(
+                parse_datetime_expr(&settings.datetime_col, &settings.datetime_parsing_format_string),
+                settings.time_zone.as_deref(),
+                display_time_zone.as_deref(),
+            )?
+            .alias("time"),
+            col(&settings.text_col).cast(DataType::String).alias("text"),
+        ];
+
+        if let Some(ref category_col) = settings.category_col {
+            columns.push(col(category_col).cast(DataType::String).alias("category"));
+        }
+
+        job.progress("Reading event log", None, None);
+
+        let df = LazyCsvReader::new(&settings.file_path)
+            .with_infer_schema_length(Some(10000))
+            .finish()
+            .map_err(|e| format!("Error opening file: {}", e.to_string()))?
+            .select(columns)
+            .collect()
+            .map_err(|e| format!("Error reading event log: {}", e.to_string()))?;
+
+        let times = df
+            .column("time")
+            .and_then(|column| column.datetime())
+            .map_err(|e| format!("Error reading event log timestamps: {}", e.to_string()))?;
+
+        let texts = df
+            .column("text")
+            .and_then(|column| column.str())
+            .map_err(|e| format!("Error reading event log messages: {}", e.to_string()))?;
+
+        let categories: Vec<Option<String>> = match df.column("category") {
+            Ok(column) => column
+                .str()
+                .map_err(|e| format!("Error reading event log categories: {}", e.to_string()))?
+                .into_iter()
+                .map(|category| category.map(|category| category.to_string()))
+                .collect(),
+            Err(_) => vec![None; df.height()],
+        };
+
+        job.checkpoint()?;
+
+        let new_annotations: Vec<Annotation> = times
+            .into_iter()
+            .zip(texts)
+            .zip(categories)
+            // Rows without a timestamp can't be placed anywhere
+            .filter_map(|((time, text), category)| {
+                Some(Annotation {
+                    id: 0, // Assigned by merge_annotations
+                    start_time: time.and_then(naive_datetime_from_millis)?,
+                    end_time: None,
+                    title: text.unwrap_or_default().to_string(),
+                    description: String::new(),
+                    category,
+                })
+            })
+            .collect();
+
+        Ok(new_annotations)
+    })
+    .await?;
+
+    // The file is read without holding the lock, so merge into the annotations as they are now rather than as they were when the import
+    // started
+    let app_state = write_app_state(&state);
+
+    let new_count = new_annotations.len();
+    let (annotations, skipped_duplicates) = app_state.merge_annotations(new_annotations);
+    let imported = new_count - skipped_duplicates;
+
+    if imported > 0 {
+        update_app_state_field(&app, app_state, AppStateField::Annotations { value: annotations })?;
+    }
+
+    Ok(EventLogImportSummary {
+        imported,
+        skipped_duplicates,
+    })
+}
@@ -0,0 +1,806 @@
+use crate::dataframe_handlers::median_sample_rate_hz;
+use crate::global_state::{ChannelFilter, FilterKind};
+use polars::prelude::*;
+use std::f64::consts::PI;
+
+/// Highest Butterworth order accepted. Past this the sections get numerically touchy and it's almost never what someone actually wants.
+const MAX_BUTTERWORTH_ORDER: usize = 10;
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Channel Filters
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// All of the filters assume the data is evenly sampled. The sample rate used to design the IIR filters is the median interval of the
+// datetime index, so a handful of gaps or jittery timestamps are fine, but data with wildly uneven sampling should be resampled first.
+// Null values are skipped over, and stay null in the output.
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Apply Channel Filters
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Runs each enabled filter over its column and appends the result as a new column. Filters are run in order, so a filter can use the output
+/// of an earlier filter as its input. Filters whose input column isn't in the DataFrame are skipped.
+pub fn apply_channel_filters(
+    mut df: DataFrame,
+    datetime_index_col: &str,
+    filters: &[ChannelFilter],
+) -> Result<DataFrame, String> {
+    let filters: Vec<&ChannelFilter> = filters.iter().filter(|filter| filter.enabled).collect();
+
+    if filters.is_empty() {
+        return Ok(df);
+    }
+
+    let times: Vec<i64> = df
+        .column(datetime_index_col)
+        .and_then(|column| column.datetime())
+        .map_err(|e| format!("Error reading the datetime index column: {}", e.to_string()))?
+        .into_no_null_iter()
+        .collect();
+
+    let sample_rate_hz = median_sample_rate_hz(&times);
+
+    for filter in filters {
+        let Ok(column) = df.column(&filter.column) else {
+            continue;
+        };
+
+        let output_column = filter_output_column(filter);
+
+        if df.get_column_index(&output_column).is_some() {
+            return Err(format!(
+                "Can't add filtered channel \"{}\" because there is already a column with that name.",
+                output_column
+            ));
+        }
+
+        let values: Vec<Option<f64>> = column
+            .f64()
+            .map_err(|e| format!("Error reading column \"{}\": {}", filter.column, e.to_string()))?
+            .into_iter()
+            .collect();
+
+        // Filter the non-null values as one continuous signal, then put them back where they came from
+        let present: Vec<f64> = values.iter().flatten().copied().collect();
+        let mut filtered = filter_values(&present, filter, sample_rate_hz)
+            .map_err(|e| format!("Error filtering \"{}\": {}", filter.column, e))?
+            .into_iter();
+
+        let output: Float64Chunked = values
+            .iter()
+            .map(|value| value.and_then(|_| filtered.next()))
+            .collect();
+
+        df.with_column(output.with_name(output_column.as_str().into()).into_series())
+            .map_err(|e| format!("Error adding filtered channel \"{}\": {}", output_column, e.to_string()))?;
+    }
+
+    Ok(df)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Filter Output Column
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Name of the column a filter adds, which is what the user picked or else the input column with a description of the filter.
+pub fn filter_output_column(filter: &ChannelFilter) -> String {
+    filter
+        .output_column
+        .clone()
+        .unwrap_or_else(|| format!("{} ({})", filter.column, filter_label(&filter.kind)))
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Filter Label
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Short description of a filter, used to name its output channel when the user hasn't.
+fn filter_label(kind: &FilterKind) -> String {
+    match *kind {
+        FilterKind::ButterworthLowPass { cutoff_hz, .. } => format!("low-pass {} Hz", cutoff_hz),
+        FilterKind::ButterworthHighPass { cutoff_hz, .. } => format!("high-pass {} Hz", cutoff_hz),
+        FilterKind::ButterworthBandPass {
+            low_cutoff_hz,
+            high_cutoff_hz,
+            ..
+        } => format!("band-pass {}-{} Hz", low_cutoff_hz, high_cutoff_hz),
+        FilterKind::Notch { center_hz, .. } => format!("notch {} Hz", center_hz),
+        FilterKind::MovingAverage { window_size } => format!("moving average {}", window_size),
+        FilterKind::Median { window_size } => format!("median {}", window_size),
+        FilterKind::SavitzkyGolay {
+            window_size,
+            polynomial_order,
+        } => format!("Savitzky-Golay {}/{}", window_size, polynomial_order),
+    }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Check Filter Settings
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Checks the parts of a filter's settings that don't depend on the data, so a bad filter can be refused when it's set rather than the next
+/// time the data is loaded. Whether the frequencies are below half the sample rate can only be checked once the data is there.
+pub fn check_filter_settings(kind: &FilterKind) -> Result<(), String> {
+    match *kind {
+        FilterKind::ButterworthLowPass { cutoff_hz, order } | FilterKind::ButterworthHighPass { cutoff_hz, order } => {
+            check_butterworth_order(order)?;
+            check_positive_frequency(cutoff_hz)
+        }
+        FilterKind::ButterworthBandPass {
+            low_cutoff_hz,
+            high_cutoff_hz,
+            order,
+        } => {
+            check_butterworth_order(order)?;
+            check_positive_frequency(low_cutoff_hz)?;
+            check_positive_frequency(high_cutoff_hz)?;
+            check_band(low_cutoff_hz, high_cutoff_hz)
+        }
+        FilterKind::Notch {
+            center_hz,
+            quality_factor,
+        } => {
+            check_positive_frequency(center_hz)?;
+            check_quality_factor(quality_factor)
+        }
+        FilterKind::MovingAverage { window_size } | FilterKind::Median { window_size } => check_window_size(window_size),
+        FilterKind::SavitzkyGolay {
+            window_size,
+            polynomial_order,
+        } => check_savitzky_golay(window_size, polynomial_order),
+    }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Filter Values
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+fn filter_values(values: &[f64], filter: &ChannelFilter, sample_rate_hz: Option<f64>) -> Result<Vec<f64>, String> {
+    if values.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // The lowest frequency the IIR filter was designed around sets how long its start up transient lasts
+    let (sections, lowest_frequency_hz, sample_rate_hz) = match filter.kind {
+        FilterKind::ButterworthLowPass { cutoff_hz, order } => {
+            let sample_rate_hz = require_sample_rate(sample_rate_hz)?;
+            check_butterworth(order, &[cutoff_hz], sample_rate_hz)?;
+            (
+                butterworth_sections(cutoff_hz, order, sample_rate_hz, PassType::LowPass),
+                cutoff_hz,
+                sample_rate_hz,
+            )
+        }
+        FilterKind::ButterworthHighPass { cutoff_hz, order } => {
+            let sample_rate_hz = require_sample_rate(sample_rate_hz)?;
+            check_butterworth(order, &[cutoff_hz], sample_rate_hz)?;
+            (
+                butterworth_sections(cutoff_hz, order, sample_rate_hz, PassType::HighPass),
+                cutoff_hz,
+                sample_rate_hz,
+            )
+        }
+        FilterKind::ButterworthBandPass {
+            low_cutoff_hz,
+            high_cutoff_hz,
+            order,
+        } => {
+            let sample_rate_hz = require_sample_rate(sample_rate_hz)?;
+            check_butterworth(order, &[low_cutoff_hz, high_cutoff_hz], sample_rate_hz)?;
+            check_band(low_cutoff_hz, high_cutoff_hz)?;
+
+            // A high-pass at the low cutoff followed by a low-pass at the high cutoff
+            let mut sections = butterworth_sections(low_cutoff_hz, order, sample_rate_hz, PassType::HighPass);
+            sections.extend(butterworth_sections(high_cutoff_hz, order, sample_rate_hz, PassType::LowPass));
+            (sections, low_cutoff_hz, sample_rate_hz)
+        }
+        FilterKind::Notch {
+            center_hz,
+            quality_factor,
+        } => {
+            let sample_rate_hz = require_sample_rate(sample_rate_hz)?;
+            check_frequency(center_hz, sample_rate_hz)?;
+            check_quality_factor(quality_factor)?;
+            (
+                vec![Biquad::notch(center_hz, quality_factor, sample_rate_hz)],
+                center_hz / quality_factor.max(1.0),
+                sample_rate_hz,
+            )
+        }
+        FilterKind::MovingAverage { window_size } => {
+            check_window_size(window_size)?;
+            return Ok(moving_average(values, window_size, filter.zero_phase));
+        }
+        FilterKind::Median { window_size } => {
+            check_window_size(window_size)?;
+            return Ok(moving_median(values, window_size, filter.zero_phase));
+        }
+        FilterKind::SavitzkyGolay {
+            window_size,
+            polynomial_order,
+        } => {
+            return savitzky_golay(values, window_size, polynomial_order, filter.zero_phase);
+        }
+    };
+
+    Ok(if filter.zero_phase {
+        let transient_samples = (3.0 * sample_rate_hz / lowest_frequency_hz).ceil() as usize;
+        filtfilt(&sections, values, transient_samples)
+    } else {
+        lfilter(&sections, values)
+    })
+}
+
+fn require_sample_rate(sample_rate_hz: Option<f64>) -> Result<f64, String> {
+    sample_rate_hz.ok_or_else(|| "Could not work out the sample rate of the data.".to_string())
+}
+
+fn check_frequency(frequency_hz: f64, sample_rate_hz: f64) -> Result<(), String> {
+    let nyquist_hz = sample_rate_hz / 2.0;
+
+    if frequency_hz <= 0.0 || frequency_hz >= nyquist_hz {
+        return Err(format!(
+            "{} Hz must be between 0 Hz and half the sample rate ({} Hz).",
+            frequency_hz, nyquist_hz
+        ));
+    }
+
+    Ok(())
+}
+
+fn check_positive_frequency(frequency_hz: f64) -> Result<(), String> {
+    if !(frequency_hz > 0.0 && frequency_hz.is_finite()) {
+        return Err(format!("{} Hz must be above 0 Hz.", frequency_hz));
+    }
+
+    Ok(())
+}
+
+fn check_butterworth(order: usize, frequencies_hz: &[f64], sample_rate_hz: f64) -> Result<(), String> {
+    check_butterworth_order(order)?;
+
+    frequencies_hz
+        .iter()
+        .try_for_each(|frequency_hz| check_frequency(*frequency_hz, sample_rate_hz))
+}
+
+fn check_butterworth_order(order: usize) -> Result<(), String> {
+    if order == 0 || order > MAX_BUTTERWORTH_ORDER {
+        return Err(format!(
+            "Butterworth order must be between 1 and {}, got {}.",
+            MAX_BUTTERWORTH_ORDER, order
+        ));
+    }
+
+    Ok(())
+}
+
+fn check_band(low_cutoff_hz: f64, high_cutoff_hz: f64) -> Result<(), String> {
+    if low_cutoff_hz >= high_cutoff_hz {
+        return Err("The low cutoff of a band-pass filter must be below the high cutoff.".to_string());
+    }
+
+    Ok(())
+}
+
+fn check_quality_factor(quality_factor: f64) -> Result<(), String> {
+    if !(quality_factor > 0.0 && quality_factor.is_finite()) {
+        return Err("The quality factor of a notch filter must be greater than 0.".to_string());
+    }
+
+    Ok(())
+}
+
+fn check_window_size(window_size: usize) -> Result<(), String> {
+    if window_size == 0 {
+        return Err("Window size must be at least 1 sample.".to_string());
+    }
+
+    Ok(())
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// IIR Filters
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+#[derive(Clone, Copy, PartialEq)]
+enum PassType {
+    LowPass,
+    HighPass,
+}
+
+/// One second order section, normalized so a0 is 1. First order sections just have b2 and a2 set to 0.
+#[derive(Clone, Copy, Debug)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl Biquad {
+    /// Low or high pass section from the Audio EQ Cookbook (bilinear transform with the cutoff prewarped).
+    fn pass(cutoff_hz: f64, q: f64, sample_rate_hz: f64, pass_type: PassType) -> Self {
+        let w0 = 2.0 * PI * cutoff_hz / sample_rate_hz;
+        let (sin_w0, cos_w0) = w0.sin_cos();
+        let alpha = sin_w0 / (2.0 * q);
+        let a0 = 1.0 + alpha;
+
+        let (b0, b1) = match pass_type {
+            PassType::LowPass => ((1.0 - cos_w0) / 2.0, 1.0 - cos_w0),
+            PassType::HighPass => ((1.0 + cos_w0) / 2.0, -(1.0 + cos_w0)),
+        };
+
+        Biquad {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b0 / a0,
+            a1: -2.0 * cos_w0 / a0,
+            a2: (1.0 - alpha) / a0,
+        }
+    }
+
+    /// First order low or high pass section, needed for odd Butterworth orders.
+    fn first_order(cutoff_hz: f64, sample_rate_hz: f64, pass_type: PassType) -> Self {
+        let k = (PI * cutoff_hz / sample_rate_hz).tan();
+
+        let (b0, b1) = match pass_type {
+            PassType::LowPass => (k / (1.0 + k), k / (1.0 + k)),
+            PassType::HighPass => (1.0 / (1.0 + k), -1.0 / (1.0 + k)),
+        };
+
+        Biquad {
+            b0,
+            b1,
+            b2: 0.0,
+            a1: (k - 1.0) / (k + 1.0),
+            a2: 0.0,
+        }
+    }
+
+    fn notch(center_hz: f64, quality_factor: f64, sample_rate_hz: f64) -> Self {
+        let w0 = 2.0 * PI * center_hz / sample_rate_hz;
+        let (sin_w0, cos_w0) = w0.sin_cos();
+        let alpha = sin_w0 / (2.0 * quality_factor);
+        let a0 = 1.0 + alpha;
+
+        Biquad {
+            b0: 1.0 / a0,
+            b1: -2.0 * cos_w0 / a0,
+            b2: 1.0 / a0,
+            a1: -2.0 * cos_w0 / a0,
+            a2: (1.0 - alpha) / a0,
+        }
+    }
+
+    /// Runs the section over the values using the transposed direct form II. The state starts out as if the first value had been going on
+    /// forever, which avoids a big startup transient when the signal doesn't start at 0.
+    fn process(&self, values: &mut [f64]) {
+        let Some(&first) = values.first() else {
+            return;
+        };
+
+        let dc_gain = (self.b0 + self.b1 + self.b2) / (1.0 + self.a1 + self.a2);
+        let steady_output = dc_gain * first;
+        let mut z2 = self.b2 * first - self.a2 * steady_output;
+        let mut z1 = self.b1 * first - self.a1 * steady_output + z2;
+
+        for value in values.iter_mut() {
+            let input = *value;
+            let output = self.b0 * input + z1;
+            z1 = self.b1 * input - self.a1 * output + z2;
+            z2 = self.b2 * input - self.a2 * output;
+            *value = output;
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Butterworth Sections
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Splits a Butterworth filter into second order sections, each with the Q of one conjugate pole pair, plus a first order section if the
+/// order is odd.
+fn butterworth_sections(cutoff_hz: f64, order: usize, sample_rate_hz: f64, pass_type: PassType) -> Vec<Biquad> {
+    let mut sections: Vec<Biquad> = (0..order / 2)
+        .map(|k| {
+            let q = 1.0 / (2.0 * (PI * (2 * k + 1) as f64 / (2 * order) as f64).sin());
+            Biquad::pass(cutoff_hz, q, sample_rate_hz, pass_type)
+        })
+        .collect();
+
+    if order % 2 == 1 {
+        sections.push(Biquad::first_order(cutoff_hz, sample_rate_hz, pass_type));
+    }
+
+    sections
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// LFilter
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Causal filtering, named after the scipy function that does the same thing.
+fn lfilter(sections: &[Biquad], values: &[f64]) -> Vec<f64> {
+    let mut output = values.to_vec();
+    sections.iter().for_each(|section| section.process(&mut output));
+    output
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// FiltFilt
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Zero phase filtering: forwards, then backwards over the reversed output. Like scipy's filtfilt, the ends are padded with a point
+/// reflection of the signal so the start up transients land in the padding instead of the data. The padding is a few periods of the lowest
+/// design frequency long, which is quite a bit more than scipy uses, but it keeps the ends clean.
+fn filtfilt(sections: &[Biquad], values: &[f64], transient_samples: usize) -> Vec<f64> {
+    let n = values.len();
+    let pad = transient_samples.max(6 * sections.len()).min(n - 1);
+
+    let first = values[0];
+    let last = values[n - 1];
+
+    let mut padded: Vec<f64> = Vec::with_capacity(n + 2 * pad);
+    padded.extend(values[1..=pad].iter().rev().map(|value| 2.0 * first - value));
+    padded.extend_from_slice(values);
+    padded.extend(values[n - 1 - pad..n - 1].iter().rev().map(|value| 2.0 * last - value));
+
+    sections.iter().for_each(|section| section.process(&mut padded));
+    padded.reverse();
+    sections.iter().for_each(|section| section.process(&mut padded));
+    padded.reverse();
+
+    padded[pad..pad + n].to_vec()
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Window Filters
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+/// The samples either side of index i that a window covers. Centered windows put any extra sample on the trailing side. Windows are cut
+/// short at the ends of the data rather than padded.
+fn window_bounds(i: usize, window_size: usize, centered: bool, len: usize) -> (usize, usize) {
+    let (before, after) = if centered {
+        (window_size / 2, (window_size - 1) / 2)
+    } else {
+        (window_size - 1, 0)
+    };
+
+    (i.saturating_sub(before), (i + after).min(len - 1))
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Moving Average
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+fn moving_average(values: &[f64], window_size: usize, centered: bool) -> Vec<f64> {
+    let mut prefix_sums = Vec::with_capacity(values.len() + 1);
+    prefix_sums.push(0.0);
+    for value in values {
+        prefix_sums.push(prefix_sums[prefix_sums.len() - 1] + value);
+    }
+
+    (0..values.len())
+        .map(|i| {
+            let (start, end) = window_bounds(i, window_size, centered, values.len());
+            (prefix_sums[end + 1] - prefix_sums[start]) / (end + 1 - start) as f64
+        })
+        .collect()
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Moving Median
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Good for knocking out single sample spikes without smearing steps like a moving average would.
+fn moving_median(values: &[f64], window_size: usize, centered: bool) -> Vec<f64> {
+    let mut window = Vec::with_capacity(window_size);
+
+    (0..values.len())
+        .map(|i| {
+            let (start, end) = window_bounds(i, window_size, centered, values.len());
+
+            window.clear();
+            window.extend_from_slice(&values[start..=end]);
+            window.sort_by(|a, b| a.total_cmp(b));
+
+            let middle = window.len() / 2;
+            if window.len() % 2 == 1 {
+                window[middle]
+            } else {
+                (window[middle - 1] + window[middle]) / 2.0
+            }
+        })
+        .collect()
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Savitzky Golay
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Fits a polynomial to each window by least squares and takes its value at the sample. Near the ends of the data the window is slid inward
+/// and the polynomial is evaluated off center, so the output stays the same length without padding. When not zero phase, the window
+/// trails the sample instead of being centered on it.
+fn savitzky_golay(
+    values: &[f64],
+    window_size: usize,
+    polynomial_order: usize,
+    zero_phase: bool,
+) -> Result<Vec<f64>, String> {
+    check_savitzky_golay(window_size, polynomial_order)?;
+    if window_size > values.len() {
+        return Err(format!(
+            "Savitzky-Golay window size ({}) is longer than the data ({} samples).",
+            window_size,
+            values.len()
+        ));
+    }
+
+    let half_window = window_size / 2;
+    let offset = if zero_phase { half_window } else { window_size - 1 };
+    let max_start = values.len() - window_size;
+
+    let gram_inverse = savitzky_golay_gram_inverse(half_window, polynomial_order)?;
+
+    // Weights only depend on where in the window the sample sits, so there are at most window_size distinct sets
+    let mut weights_by_position: Vec<Option<Vec<f64>>> = vec![None; window_size];
+
+    Ok((0..values.len())
+        .map(|i| {
+            let start = i.saturating_sub(offset).min(max_start);
+            let position = i - start;
+
+            let weights = weights_by_position[position].get_or_insert_with(|| {
+                savitzky_golay_weights(&gram_inverse, half_window, position as f64 - half_window as f64)
+            });
+
+            weights
+                .iter()
+                .zip(&values[start..start + window_size])
+                .map(|(weight, value)| weight * value)
+                .sum()
+        })
+        .collect())
+}
+
+fn check_savitzky_golay(window_size: usize, polynomial_order: usize) -> Result<(), String> {
+    if window_size % 2 != 1 {
+        return Err(format!("Savitzky-Golay window size must be odd, got {}.", window_size));
+    }
+    if polynomial_order >= window_size {
+        return Err("Savitzky-Golay polynomial order must be less than the window size.".to_string());
+    }
+
+    Ok(())
+}
+
+/// Inverse of AᵀA, where row j of A is [1, x, x², ...] for x from -half_window to half_window.
+fn savitzky_golay_gram_inverse(half_window: usize, polynomial_order: usize) -> Result<Vec<Vec<f64>>, String> {
+    let size = polynomial_order + 1;
+    let half_window = half_window as i64;
+
+    let gram: Vec<Vec<f64>> = (0..size)
+        .map(|row| {
+            (0..size)
+                .map(|col| {
+                    (-half_window..=half_window)
+                        .map(|x| (x as f64).powi((row + col) as i32))
+                        .sum()
+                })
+                .collect()
+        })
+        .collect();
+
+    invert_matrix(gram).ok_or_else(|| "Savitzky-Golay fit is singular, try a lower polynomial order.".to_string())
+}
+
+/// Weights that give the fitted polynomial's value at x when dotted with the window.
+fn savitzky_golay_weights(gram_inverse: &[Vec<f64>], half_window: usize, x: f64) -> Vec<f64> {
+    let powers_of_x: Vec<f64> = (0..gram_inverse.len()).map(|k| x.powi(k as i32)).collect();
+
+    let coefficients: Vec<f64> = gram_inverse
+        .iter()
+        .map(|row| row.iter().zip(&powers_of_x).map(|(a, b)| a * b).sum())
+        .collect();
+
+    let half_window = half_window as i64;
+
+    (-half_window..=half_window)
+        .map(|j| {
+            coefficients
+                .iter()
+                .enumerate()
+                .map(|(k, coefficient)| coefficient * (j as f64).powi(k as i32))
+                .sum()
+        })
+        .collect()
+}
+
+/// Gauss-Jordan elimination with partial pivoting. The matrices here are tiny (polynomial order + 1 square).
+fn invert_matrix(mut matrix: Vec<Vec<f64>>) -> Option<Vec<Vec<f64>>> {
+    let size = matrix.len();
+    let mut inverse: Vec<Vec<f64>> = (0..size)
+        .map(|row| (0..size).map(|col| if row == col { 1.0 } else { 0.0 }).collect())
+        .collect();
+
+    for col in 0..size {
+        let pivot_row = (col..size).max_by(|a, b| matrix[*a][col].abs().total_cmp(&matrix[*b][col].abs()))?;
+
+        if matrix[pivot_row][col].abs() < 1e-12 {
+            return None;
+        }
+
+        matrix.swap(col, pivot_row);
+        inverse.swap(col, pivot_row);
+
+        let pivot = matrix[col][col];
+        for k in 0..size {
+            matrix[col][k] /= pivot;
+            inverse[col][k] /= pivot;
+        }
+
+        for row in 0..size {
+            if row == col {
+                continue;
+            }
+            let factor = matrix[row][col];
+            for k in 0..size {
+                matrix[row][k] -= factor * matrix[col][k];
+                inverse[row][k] -= factor * inverse[col][k];
+            }
+        }
+    }
+
+    Some(inverse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE_HZ: f64 = 1000.0;
+
+    fn sine(frequency_hz: f64, len: usize) -> Vec<f64> {
+        (0..len)
+            .map(|i| (2.0 * PI * frequency_hz * i as f64 / SAMPLE_RATE_HZ).sin())
+            .collect()
+    }
+
+    /// Peak of the second half of the output, after any start up transient has died down
+    fn settled_amplitude(values: &[f64]) -> f64 {
+        values[values.len() / 2..].iter().fold(0.0, |peak, value| value.abs().max(peak))
+    }
+
+    fn assert_close(actual: &[f64], expected: &[f64], tolerance: f64) {
+        assert_eq!(actual.len(), expected.len());
+        for (i, (actual, expected)) in actual.iter().zip(expected).enumerate() {
+            assert!((actual - expected).abs() < tolerance, "sample {}: {} is not {}", i, actual, expected);
+        }
+    }
+
+    #[test]
+    fn low_pass_passes_dc_unchanged() {
+        let sections = butterworth_sections(10.0, 4, SAMPLE_RATE_HZ, PassType::LowPass);
+
+        assert_close(&lfilter(&sections, &[3.0; 200]), &[3.0; 200], 1e-9);
+    }
+
+    #[test]
+    fn high_pass_removes_dc() {
+        let sections = butterworth_sections(10.0, 3, SAMPLE_RATE_HZ, PassType::HighPass);
+
+        assert_close(&lfilter(&sections, &[3.0; 200]), &[0.0; 200], 1e-9);
+    }
+
+    #[test]
+    fn butterworth_is_3_db_down_at_the_cutoff() {
+        let values = sine(10.0, 2000);
+
+        for order in [1, 2, 5] {
+            for pass_type in [PassType::LowPass, PassType::HighPass] {
+                let sections = butterworth_sections(10.0, order, SAMPLE_RATE_HZ, pass_type);
+                let amplitude = settled_amplitude(&lfilter(&sections, &values));
+
+                assert!((amplitude - 0.5_f64.sqrt()).abs() < 0.01, "order {} gave {}", order, amplitude);
+            }
+        }
+    }
+
+    #[test]
+    fn filtfilt_squares_the_gain_without_shifting_the_signal() {
+        let sections = butterworth_sections(10.0, 2, SAMPLE_RATE_HZ, PassType::LowPass);
+
+        // At the cutoff the gain is applied twice
+        let at_cutoff = filtfilt(&sections, &sine(10.0, 2000), 300);
+        assert!((settled_amplitude(&at_cutoff) - 0.5).abs() < 0.01);
+
+        // Well inside the pass band the output lands right on the input, where a causal filter would lag it
+        let in_band = sine(1.0, 2000);
+        assert_close(&filtfilt(&sections, &in_band, 300), &in_band, 1e-3);
+    }
+
+    #[test]
+    fn notch_removes_its_center_frequency_and_passes_dc() {
+        let notch = [Biquad::notch(50.0, 10.0, SAMPLE_RATE_HZ)];
+
+        assert!(settled_amplitude(&lfilter(&notch, &sine(50.0, 2000))) < 0.01);
+        assert_close(&lfilter(&notch, &[2.0; 100]), &[2.0; 100], 1e-9);
+    }
+
+    #[test]
+    fn moving_average_cuts_windows_short_at_the_ends() {
+        let values = [1.0, 2.0, 3.0, 4.0, 5.0];
+
+        assert_eq!(moving_average(&values, 3, false), vec![1.0, 1.5, 2.0, 3.0, 4.0]);
+        assert_eq!(moving_average(&values, 3, true), vec![1.5, 2.0, 3.0, 4.0, 4.5]);
+    }
+
+    #[test]
+    fn moving_median_removes_a_spike() {
+        assert_eq!(moving_median(&[1.0, 1.0, 9.0, 1.0, 1.0], 3, true), vec![1.0, 1.0, 1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn savitzky_golay_reproduces_a_polynomial_of_its_order() {
+        let quadratic: Vec<f64> = (0..20).map(|i| 0.5 * (i * i) as f64 - 3.0 * i as f64 + 7.0).collect();
+
+        for zero_phase in [false, true] {
+            assert_close(&savitzky_golay(&quadratic, 7, 2, zero_phase).unwrap(), &quadratic, 1e-9);
+        }
+    }
+
+    #[test]
+    fn check_filter_settings_rejects_impossible_filters() {
+        assert!(check_filter_settings(&FilterKind::ButterworthLowPass { cutoff_hz: 10.0, order: 0 }).is_err());
+        assert!(check_filter_settings(&FilterKind::ButterworthHighPass { cutoff_hz: -1.0, order: 2 }).is_err());
+        assert!(check_filter_settings(&FilterKind::ButterworthBandPass {
+            low_cutoff_hz: 20.0,
+            high_cutoff_hz: 10.0,
+            order: 2
+        })
+        .is_err());
+        assert!(check_filter_settings(&FilterKind::SavitzkyGolay {
+            window_size: 6,
+            polynomial_order: 2
+        })
+        .is_err());
+        assert!(check_filter_settings(&FilterKind::Notch {
+            center_hz: 50.0,
+            quality_factor: 10.0
+        })
+        .is_ok());
+    }
+
+    #[test]
+    fn apply_channel_filters_keeps_nulls_in_place() {
+        let mut df = df!(
+            "time" => (0..6).map(|i| i * 10).collect::<Vec<i64>>(),
+            "a" => [Some(1.0), None, Some(3.0), Some(5.0), None, Some(7.0)],
+        )
+        .unwrap();
+        let time = df.column("time").unwrap().cast(&DataType::Datetime(TimeUnit::Milliseconds, None)).unwrap();
+        df.with_column(time).unwrap();
+
+        let filter = ChannelFilter {
+            column: "a".to_string(),
+            output_column: Some("a smoothed".to_string()),
+            enabled: true,
+            zero_phase: false,
+            kind: FilterKind::MovingAverage { window_size: 2 },
+        };
+
+        let df = apply_channel_filters(df, "time", &[filter]).unwrap();
+        let smoothed: Vec<Option<f64>> = df.column("a smoothed").unwrap().f64().unwrap().into_iter().collect();
+
+        // The nulls are skipped over, so the window after each one reaches back to the value before it
+        assert_eq!(smoothed, vec![Some(1.0), None, Some(2.0), Some(4.0), None, Some(6.0)]);
+    }
+}
@@ -1,9 +1,26 @@
-use crate::global_state::{AppState, LoadCsvSettings};
-use polars::io::ipc::IpcWriter;
+use crate::global_state::{
+    snapshot_app_state, AppState, GapFillStrategy, LoadCsvSettings, ResampleAggregation, ResampleSettings, TimeBounds, TimestampPolicy,
+};
+use crate::channel_filters::{apply_channel_filters, filter_output_column};
+use crate::detection_handlers::detect_events;
+use crate::job_handlers::{run_job, Job};
+use crate::time_zones::to_display_time_expr;
+use crate::transfer_encoding::{write_transfer_ipc, write_transfer_ipc_stream};
 use polars::prelude::*;
+use polars::series::ops::NullBehavior;
 use serde::Serialize;
-use std::sync::Mutex;
-use tauri::{ipc::Response, State};
+use std::{
+    path::{Path, PathBuf},
+    sync::RwLock,
+    time::SystemTime,
+};
+use tauri::{
+    ipc::{Channel, Response},
+    AppHandle, Emitter, Manager, State,
+};
+
+/// Rows per chunk sent by stream_csv_data when the frontend doesn't ask for a size. About 1 MB per Float64 column.
+const STREAM_CHUNK_ROWS: usize = 131_072;
 
 // #############################################################################################################################################
 // #############################################################################################################################################
@@ -12,10 +29,42 @@ use tauri::{ipc::Response, State};
 // #############################################################################################################################################
 
 /// A custom struct to put a schema into, because we need it to be serializeable to send it to our JS frontend.
-#[derive(Serialize)]
+#[derive(Serialize, Clone)]
 pub struct SchemaField {
-    name: String,
-    field_type: String,
+    pub name: String,
+    pub field_type: String,
+}
+
+/// The schema of the last CSV scanned, kept so that checking load settings against the file's columns doesn't scan it every time. Managed
+/// by Tauri alongside the AppState. The file's modified time is kept with it, so a file that has been written to since gets scanned again.
+#[derive(Default)]
+pub struct CsvSchemaCache {
+    scanned: RwLock<Option<(PathBuf, SystemTime, Vec<SchemaField>)>>,
+}
+
+/// The DataFrame from the last load, kept so that get_viewport_data can slice it without reading the CSV again. Managed by Tauri alongside
+/// the AppState. The key records which file and version of it, settings and filters it was loaded with, so a stale DataFrame is never
+/// handed out.
+#[derive(Default)]
+pub struct LoadedDataCache {
+    loaded: RwLock<Option<(String, DataFrame)>>,
+}
+
+/// Describes any problems found with the datetime index column when loading the CSV, and which TimestampPolicy was applied to fix them.
+/// This gets emitted to the frontend so that the user can be warned that their data was reordered or deduplicated.
+#[derive(Serialize, Clone, Debug)]
+pub struct TimestampReport {
+    /// Number of rows whose timestamp is earlier than the timestamp of the row before it
+    out_of_order_count: usize,
+    /// Number of rows whose timestamp is shared with at least one other row
+    duplicate_count: usize,
+    policy: TimestampPolicy,
+}
+
+impl TimestampReport {
+    fn has_issues(&self) -> bool {
+        self.out_of_order_count > 0 || self.duplicate_count > 0
+    }
 }
 
 // #############################################################################################################################################
@@ -30,13 +79,8 @@ pub struct SchemaField {
 
 #[tauri::command]
 /// Scan the CSV to get some information about it. Frontend uses this to let the user choose what columns they want to load from the .csv
-pub async fn get_csv_schema(state: State<'_, Mutex<AppState>>) -> Result<Vec<SchemaField>, String> {
-    let state = state.lock().map_err(|e| {
-        format!(
-            "Error locking app state when loading data: {}",
-            e.to_string()
-        )
-    })?;
+pub async fn get_csv_schema(app: AppHandle, state: State<'_, RwLock<AppState>>) -> Result<Vec<SchemaField>, String> {
+    let state = snapshot_app_state(&state);
 
     let file_path: tauri::path::SafePathBuf = state
         .csv_file_path
@@ -44,6 +88,121 @@ pub async fn get_csv_schema(state: State<'_, Mutex<AppState>>) -> Result<Vec<Sch
         .ok_or("CSV file path has not been set yet.")?
         .into();
 
+    cached_csv_schema(&app, file_path.as_ref())
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Get CSV Data
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Loads the DataFrame per the load_csv_settings in the AppStateSerializes the Polars DataFrame to Apache Arrow format and then sends that binary response in a Tauri array buffer via IPC
+/// In theory that is faster than using JSON. The datetime column will always be the first column. 
+/// If the datetime index is out of order or has repeated timestamps, a "csv-timestamp-report" event is emitted describing what was done about it.
+/// Enabled channel filters are run after loading and their outputs are sent as extra columns after the raw ones.
+/// Enabled detection rules whose column was loaded are re-run against the new data and their hits are emitted as a "detection-results" event.
+/// The load runs as a job (see run_job), so it reports "job-progress" events and can be stopped with cancel_job.
+/// Columns are encoded and compressed per the transfer_settings in the AppState (see write_transfer_ipc).
+/// The whole DataFrame is sent as one buffer, so for large files use stream_csv_data instead.
+#[tauri::command]
+pub async fn get_csv_data(app: AppHandle, state: State<'_, RwLock<AppState>>) -> Result<Response, String> {
+    let state = snapshot_app_state(&state);
+    let job_app = app.clone();
+
+    run_job(&app, "get_csv_data", move |job| {
+        let df = load_display_data(&job_app, &state, job)?;
+
+        job.progress("Serializing data", Some(90.0), Some(df.height()));
+
+        Ok(Response::new(write_transfer_ipc(&df, &state.transfer_settings)?))
+    })
+    .await
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Stream CSV Data
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Loads the same data as get_csv_data, but sends it through the on_chunk channel in chunks of chunk_rows rows (STREAM_CHUNK_ROWS by
+/// default) instead of returning it. Each chunk is a complete Arrow IPC stream with its own schema, so the frontend can parse and plot it with
+/// tableFromIPC as soon as it arrives, and only one chunk is ever serialized at a time. Chunks are sent in time order.
+/// Returns the total number of rows sent once the last chunk has gone out.
+///
+/// Only the serializing and sending is chunked. The whole CSV is still read, sorted, filtered, and run through the detection rules before the
+/// first chunk goes out, the same as for get_csv_data, since sorting by the datetime index and the zero-phase channel filters both need every
+/// row first. So this saves the memory of one big IPC buffer and lets the plot fill in while the rest is sent, but the wait for the first
+/// chunk is as long as the wait for get_csv_data.
+#[tauri::command]
+pub async fn stream_csv_data(
+    app: AppHandle,
+    state: State<'_, RwLock<AppState>>,
+    on_chunk: Channel<Response>,
+    chunk_rows: Option<usize>,
+) -> Result<usize, String> {
+    let state = snapshot_app_state(&state);
+    let job_app = app.clone();
+    let chunk_rows = chunk_rows.unwrap_or(STREAM_CHUNK_ROWS).max(1);
+
+    run_job(&app, "stream_csv_data", move |job| {
+        let df = load_display_data(&job_app, &state, job)?;
+        let total_rows = df.height();
+
+        // An empty DataFrame still gets one chunk so the frontend receives the schema
+        let mut offset = 0;
+        loop {
+            let chunk = df.slice(offset as i64, chunk_rows);
+            offset += chunk.height();
+
+            on_chunk
+                .send(Response::new(write_transfer_ipc_stream(&chunk, &state.transfer_settings)?))
+                .map_err(|e| format!("Failed to send data chunk: {:?}", e))?;
+
+            if offset >= total_rows {
+                break;
+            }
+
+            job.checkpoint()?;
+            job.progress(
+                "Sending data",
+                Some(90.0 + 10.0 * offset as f64 / total_rows as f64),
+                Some(offset),
+            );
+        }
+
+        Ok(total_rows)
+    })
+    .await
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Utility Functions
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Cached CSV Schema
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Returns the schema of a CSV from the CsvSchemaCache, scanning the file if it isn't the one cached or has changed since.
+pub fn cached_csv_schema(app: &AppHandle, file_path: &Path) -> Result<Vec<SchemaField>, String> {
+    let modified = std::fs::metadata(file_path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(|e| format!("Error opening file: {}", e.to_string()))?;
+
+    let cache = app.state::<CsvSchemaCache>();
+
+    let cached = cache
+        .scanned
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .as_ref()
+        .filter(|(scanned_path, scanned_modified, _)| scanned_path == file_path && *scanned_modified == modified)
+        .map(|(_, _, schema)| schema.clone());
+
+    if let Some(schema) = cached {
+        return Ok(schema);
+    }
+
     let mut lf = LazyCsvReader::new(file_path)
         .with_infer_schema_length(Some(10000))
         .finish()
@@ -61,55 +220,282 @@ pub async fn get_csv_schema(state: State<'_, Mutex<AppState>>) -> Result<Vec<Sch
         })
         .collect();
 
+    *cache.scanned.write().unwrap_or_else(|poisoned| poisoned.into_inner()) =
+        Some((file_path.to_path_buf(), modified, schema_vec.clone()));
+
     Ok(schema_vec)
 }
 
 // ---------------------------------------------------------------------------------------------------------------------------------------------
-// Get CSV Data
+// Load Display Data
 // ---------------------------------------------------------------------------------------------------------------------------------------------
 
-/// Loads the DataFrame per the load_csv_settings in the AppStateSerializes the Polars DataFrame to Apache Arrow format and then sends that binary response in a Tauri array buffer via IPC
-/// In theory that is faster than using JSON. The datetime column will always be the first column. 
-#[tauri::command]
-pub async fn get_csv_data(state: State<'_, Mutex<AppState>>) -> Result<Response, String> {
-    let state = state.lock().map_err(|e| {
-        format!(
-            "Error getting app state when requesting data: {}",
-            e.to_string()
-        )
-    })?;
+/// Everything get_csv_data and stream_csv_data do before sending the data: reads the display data (see read_display_data), emits the
+/// "csv-timestamp-report" event if needed, and runs the enabled detection rules and emits their hits. The result replaces whatever was in
+/// the LoadedDataCache.
+fn load_display_data(app: &AppHandle, state: &AppState, job: &Job) -> Result<DataFrame, String> {
+    // Taken before reading, so a file written to partway through the read isn't cached as its new version
+    let key = loaded_data_key(state)?;
+    let (df, timestamp_report) = read_display_data(state, job)?;
+
+    let datetime_index_col = state
+        .load_csv_settings
+        .as_ref()
+        .ok_or("CSV loading settings have not been set yet.")?
+        .datetime_index_col
+        .as_str();
+
+    if timestamp_report.has_issues() {
+        app.emit("csv-timestamp-report", timestamp_report)
+            .map_err(|e| format!("Failed to emit event: {:?}", e))?;
+    }
+
+    job.checkpoint()?;
+
+    let detection_rules = state
+        .detection_rules
+        .iter()
+        .filter(|rule| rule.enabled && df.get_column_index(&rule.column).is_some());
 
+    if detection_rules.clone().next().is_some() {
+        job.progress("Running detection rules", Some(75.0), Some(df.height()));
+
+        let detection_hits = detect_events(&df, datetime_index_col, detection_rules)?;
+
+        app.emit("detection-results", detection_hits)
+            .map_err(|e| format!("Failed to emit event: {:?}", e))?;
+
+        job.checkpoint()?;
+    }
+
+    store_display_data(app, key, &df);
+
+    Ok(df)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Read Display Data
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Loads the CSV per the load_csv_settings and runs the enabled channel filters, which gives the data exactly as it is plotted. Unlike
+/// load_display_data this has no side effects: no events are emitted and the LoadedDataCache is left alone.
+fn read_display_data(state: &AppState, job: &Job) -> Result<(DataFrame, TimestampReport), String> {
     let file_path: tauri::path::SafePathBuf = state
         .csv_file_path
         .clone()
         .ok_or("CSV file path has not been set yet")?
         .into();
 
-    let load_csv_settings: LoadCsvSettings = state
+    let load_csv_settings: &LoadCsvSettings = state
         .load_csv_settings
-        .clone()
+        .as_ref()
         .ok_or("CSV loading settings have not been set yet.")?;
 
-    let datetime_formatter = StrptimeOptions {
-        format: Some(load_csv_settings.datetime_parsing_format_string.into()),
-        ..Default::default()
+    let display_time_zone = state.display_time_zone.as_ref().map(|time_zone| time_zone.as_ref());
+
+    job.progress("Reading CSV", None, None);
+
+    let (df, timestamp_report) = load_csv_dataframe(file_path, load_csv_settings, display_time_zone)?;
+
+    job.checkpoint()?;
+    job.progress("Applying channel filters", Some(60.0), Some(df.height()));
+
+    let df = apply_channel_filters(df, &load_csv_settings.datetime_index_col, &state.channel_filters)?;
+
+    job.checkpoint()?;
+
+    Ok((df, timestamp_report))
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Cached Display Data
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Returns the DataFrame from the last load if it was loaded with the same settings as the given state, and otherwise reads it again with
+/// read_display_data and caches it. A cache miss doesn't emit the events get_csv_data does, so panning the plot or running an analysis
+/// never re-sends the timestamp report or detection results.
+pub fn cached_display_data(app: &AppHandle, state: &AppState, job: &Job) -> Result<DataFrame, String> {
+    let key = loaded_data_key(state)?;
+
+    if let Some(df) = lookup_display_data(app, &key) {
+        return Ok(df);
+    }
+
+    let (df, _) = read_display_data(state, job)?;
+    store_display_data(app, key, &df);
+
+    Ok(df)
+}
+
+/// Returns the DataFrame in the LoadedDataCache if it was loaded with the same settings as the given state, without reading anything if
+/// it wasn't.
+pub fn loaded_display_data(app: &AppHandle, state: &AppState) -> Result<Option<DataFrame>, String> {
+    Ok(lookup_display_data(app, &loaded_data_key(state)?))
+}
+
+fn lookup_display_data(app: &AppHandle, key: &str) -> Option<DataFrame> {
+    app.state::<LoadedDataCache>()
+        .loaded
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .as_ref()
+        .filter(|(loaded_key, _)| loaded_key == key)
+        .map(|(_, df)| df.clone())
+}
+
+/// Replaces whatever is in the LoadedDataCache. Cloning a DataFrame only clones the references to its columns.
+fn store_display_data(app: &AppHandle, key: String, df: &DataFrame) {
+    *app.state::<LoadedDataCache>()
+        .loaded
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some((key, df.clone()));
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Display Data With Columns
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The same data as cached_display_data, making sure it has the given columns as well. Filtered channels are in the display data like any
+/// other column. CSV columns that aren't in the load_cols are read along with them, in which case the data is read again without caching
+/// it, so the plot's cached data isn't pushed out by a one off analysis. Every command that works on the loaded data goes through this, so
+/// they all see the same timestamp policy, resampling, time zones and filtered channels as the plot.
+pub fn display_data_with_columns(app: &AppHandle, state: &AppState, columns: &[String], job: &Job) -> Result<DataFrame, String> {
+    let load_csv_settings = state
+        .load_csv_settings
+        .as_ref()
+        .ok_or("CSV loading settings have not been set yet.")?;
+
+    let filtered_channels: Vec<String> = state
+        .channel_filters
+        .iter()
+        .filter(|filter| filter.enabled)
+        .map(filter_output_column)
+        .collect();
+
+    let unloaded_columns: Vec<String> = columns
+        .iter()
+        .filter(|column| {
+            **column != load_csv_settings.datetime_index_col
+                && !load_csv_settings.load_cols.contains(column)
+                && !filtered_channels.contains(column)
+        })
+        .cloned()
+        .collect();
+
+    if unloaded_columns.is_empty() {
+        return cached_display_data(app, state, job);
+    }
+
+    let mut state = state.clone();
+    if let Some(ref mut load_csv_settings) = state.load_csv_settings {
+        for column in unloaded_columns {
+            if !load_csv_settings.load_cols.contains(&column) {
+                load_csv_settings.load_cols.push(column);
+            }
+        }
+    }
+
+    let (df, _) = read_display_data(&state, job)?;
+
+    Ok(df)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Select Display Data
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Cuts display data down to the datetime index and the given columns, between the time bounds if there are any. Either end of the time
+/// bounds can be left open.
+pub fn select_display_data(
+    df: &DataFrame,
+    datetime_index_col: &str,
+    columns: &[String],
+    time_bounds: Option<&TimeBounds>,
+) -> Result<DataFrame, String> {
+    if let Some(column) = columns.iter().find(|column| df.get_column_index(column).is_none()) {
+        return Err(format!("Column \"{}\" has not been loaded.", column));
+    }
+
+    let lf = df.clone().lazy().select(
+        [&[datetime_index_col.to_string()], columns]
+            .concat()
+            .into_iter()
+            .map(|name| col(name))
+            .collect::<Vec<_>>(),
+    );
+
+    filter_time_bounds(lf, datetime_index_col, time_bounds)
+        .collect()
+        .map_err(|e| format!("Error selecting data: {}", e.to_string()))
+}
+
+/// Everything that changes what load_display_data returns, serialized so it can be compared. Like the CsvSchemaCache, this includes the
+/// file's modified time, along with its size, so a CSV that has been written to since gets read again.
+fn loaded_data_key(state: &AppState) -> Result<String, String> {
+    let file_version = match state.csv_file_path.clone() {
+        Some(file_path) => {
+            let file_path: tauri::path::SafePathBuf = file_path.into();
+            let metadata = std::fs::metadata(file_path).map_err(|e| format!("Error opening file: {}", e.to_string()))?;
+            let modified = metadata
+                .modified()
+                .map_err(|e| format!("Error opening file: {}", e.to_string()))?;
+
+            Some((modified, metadata.len()))
+        }
+        None => None,
     };
 
+    serde_json::to_string(&(
+        &state.csv_file_path,
+        file_version,
+        &state.load_csv_settings,
+        &state.display_time_zone,
+        &state.channel_filters,
+    ))
+    .map_err(|e| format!("Error serializing the load settings: {}", e.to_string()))
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Filter Time Bounds
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Keeps the rows of a lazy query between the time bounds, if there are any. Either end of the time bounds can be left open.
+pub fn filter_time_bounds(mut lf: LazyFrame, datetime_index_col: &str, time_bounds: Option<&TimeBounds>) -> LazyFrame {
+    if let Some(time_bounds) = time_bounds {
+        if let Some(start_time) = time_bounds.start_time {
+            lf = lf.filter(col(datetime_index_col).gt_eq(lit(start_time)));
+        }
+
+        if let Some(end_time) = time_bounds.end_time {
+            lf = lf.filter(col(datetime_index_col).lt_eq(lit(end_time)));
+        }
+    }
+
+    lf
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Scan CSV Data
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Builds the lazy query that parses the datetime index column, converts it to the display time zone if the CSV has a time zone, selects
+/// the columns to load, casts them to Float64, and filters them to the time bounds. Nothing is read from the file until the returned LazyFrame
+/// is collected.
+pub fn scan_csv_data(
+    file_path: impl AsRef<std::path::Path>,
+    load_csv_settings: &LoadCsvSettings,
+    display_time_zone: Option<&str>,
+) -> Result<LazyFrame, String> {
     let mut lf = LazyCsvReader::new(file_path)
         .with_infer_schema_length(Some(10000))
         .finish()
         .map_err(|e| format!("Error opening file: {}", e.to_string()))?
         // Parse the datetime_index_col into a datetime.
         .with_columns(
-            [col(&load_csv_settings.datetime_index_col)
-                .str()
-                .to_datetime(
-                    Some(TimeUnit::Milliseconds),
-                    None,
-                    datetime_formatter,
-                    lit("raise"),
-                )
-                .alias(&load_csv_settings.datetime_index_col)], // Select the datetime index col (first) and then the rest of the desired columns
+            [parse_datetime_expr(
+                &load_csv_settings.datetime_index_col,
+                &load_csv_settings.datetime_parsing_format_string,
+            )], // Select the datetime index col (first) and then the rest of the desired columns
         )
         .select(
             [
@@ -122,6 +508,16 @@ pub async fn get_csv_data(state: State<'_, Mutex<AppState>>) -> Result<Response,
             .collect::<Vec<_>>(),
         );
 
+    // Time bounds are chosen in the display time zone, so this needs to happen before filtering
+    lf = lf.with_column(
+        to_display_time_expr(
+            col(&load_csv_settings.datetime_index_col),
+            load_csv_settings.time_zone.as_deref(),
+            display_time_zone,
+        )?
+        .alias(&load_csv_settings.datetime_index_col),
+    );
+
     // Cast all columns (except the datetime index col) to Float64
     lf = lf.with_columns(
         load_csv_settings
@@ -132,28 +528,475 @@ pub async fn get_csv_data(state: State<'_, Mutex<AppState>>) -> Result<Response,
     );
 
     // Filter the time to fit within the bounds, if supplied
-    if let Some(time_bounds) = load_csv_settings.time_bounds {
-        if let Some(start_time) = time_bounds.start_time {
-            lf = lf.filter(col(&load_csv_settings.datetime_index_col).gt_eq(lit(start_time)));
-        }
+    Ok(filter_time_bounds(
+        lf,
+        &load_csv_settings.datetime_index_col,
+        load_csv_settings.time_bounds.as_ref(),
+    ))
+}
 
-        if let Some(end_time) = time_bounds.end_time {
-            lf = lf.filter(col(&load_csv_settings.datetime_index_col).lt_eq(lit(end_time)));
+/// Whether scan_csv_data gives the same rows as the display data, apart from their order. That is the case when the timestamp policy only
+/// sorts, nothing is resampled, and no channel filters are enabled, so anything that doesn't depend on the order of the rows can be computed
+/// straight from the scan without loading the whole file.
+pub fn scan_matches_display_data(state: &AppState) -> bool {
+    state.load_csv_settings.as_ref().is_some_and(|load_csv_settings| {
+        load_csv_settings.timestamp_policy == TimestampPolicy::Sort && load_csv_settings.resample.is_none()
+    }) && !state.channel_filters.iter().any(|filter| filter.enabled)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Parse Datetime Expression
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Parses a string column into a millisecond Datetime column of the same name using a strftime format string. Any value that doesn't match
+/// the format is an error. Use this for every datetime read out of a CSV so that they are all parsed the same way.
+pub fn parse_datetime_expr(col_name: &str, datetime_parsing_format_string: &str) -> Expr {
+    let datetime_formatter = StrptimeOptions {
+        format: Some(datetime_parsing_format_string.into()),
+        ..Default::default()
+    };
+
+    col(col_name)
+        .str()
+        .to_datetime(
+            Some(TimeUnit::Milliseconds),
+            None,
+            datetime_formatter,
+            lit("raise"),
+        )
+        .alias(col_name)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Load CSV DataFrame
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Collects the scanned CSV data, checks the datetime index for out of order and repeated timestamps, and then applies the TimestampPolicy
+/// from the load_csv_settings. Use this anywhere the data needs to match exactly what get_csv_data sends to the plot.
+pub fn load_csv_dataframe(
+    file_path: impl AsRef<std::path::Path>,
+    load_csv_settings: &LoadCsvSettings,
+    display_time_zone: Option<&str>,
+) -> Result<(DataFrame, TimestampReport), String> {
+    let df = scan_csv_data(file_path, load_csv_settings, display_time_zone)?
+        .collect()
+        .map_err(|e| format!("Error collecting CSV data: {}", e.to_string()))?;
+
+    let timestamp_report = inspect_timestamps(&df, load_csv_settings)?;
+
+    // Skip the extra sort when the data is already clean, which should be the usual case
+    let df = if timestamp_report.has_issues() {
+        apply_timestamp_policy(df, load_csv_settings, &timestamp_report)?
+    } else {
+        df
+    };
+
+    let df = match load_csv_settings.resample {
+        Some(ref resample_settings) => resample_dataframe(df, load_csv_settings, resample_settings)?,
+        None => df,
+    };
+
+    Ok((df, timestamp_report))
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Apply Timestamp Policy
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Sorts and/or deduplicates the datetime index column of a DataFrame per the TimestampPolicy in the load_csv_settings.
+fn apply_timestamp_policy(
+    df: DataFrame,
+    load_csv_settings: &LoadCsvSettings,
+    timestamp_report: &TimestampReport,
+) -> Result<DataFrame, String> {
+    let index_col = load_csv_settings.datetime_index_col.as_str();
+    let sort_options = SortMultipleOptions::default().with_maintain_order(true);
+    let lf = df.lazy();
+
+    let lf = match load_csv_settings.timestamp_policy {
+        TimestampPolicy::Sort => lf.sort([index_col], sort_options),
+        TimestampPolicy::KeepFirst => lf
+            .unique_stable(Some(vec![index_col.into()]), UniqueKeepStrategy::First)
+            .sort([index_col], sort_options),
+        TimestampPolicy::KeepLast => lf
+            .unique_stable(Some(vec![index_col.into()]), UniqueKeepStrategy::Last)
+            .sort([index_col], sort_options),
+        TimestampPolicy::AverageDuplicates => lf
+            .group_by_stable([col(index_col)])
+            .agg(
+                load_csv_settings
+                    .load_cols
+                    .iter()
+                    .map(|col_name| col(col_name).mean())
+                    .collect::<Vec<_>>(),
+            )
+            .sort([index_col], sort_options),
+        TimestampPolicy::Fail => {
+            return Err(format!(
+                "The datetime index column \"{}\" has {} out of order timestamps and {} rows with repeated timestamps.",
+                index_col, timestamp_report.out_of_order_count, timestamp_report.duplicate_count
+            ))
         }
+    };
+
+    lf.collect()
+        .map_err(|e| format!("Error applying the timestamp policy: {}", e.to_string()))
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Resample DataFrame
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Bins a DataFrame that is sorted by its datetime index onto a uniform time grid, inserts null rows for any empty bins, and then fills those
+/// gaps per the GapFillStrategy. The datetime index of the result holds the start time of each bin.
+fn resample_dataframe(
+    df: DataFrame,
+    load_csv_settings: &LoadCsvSettings,
+    resample_settings: &ResampleSettings,
+) -> Result<DataFrame, String> {
+    if resample_settings.period_ms == 0 {
+        return Err("The resampling period must be greater than zero.".to_string());
+    }
+
+    let index_col = load_csv_settings.datetime_index_col.as_str();
+    let every = Duration::parse(&format!("{}ms", resample_settings.period_ms));
+
+    let aggregations = load_csv_settings
+        .load_cols
+        .iter()
+        .map(|col_name| {
+            let aggregation = resample_settings
+                .column_aggregations
+                .get(col_name)
+                .copied()
+                .unwrap_or(resample_settings.default_aggregation);
+
+            match aggregation {
+                ResampleAggregation::Mean => col(col_name).mean(),
+                ResampleAggregation::Min => col(col_name).min(),
+                ResampleAggregation::Max => col(col_name).max(),
+                ResampleAggregation::First => col(col_name).first(),
+                ResampleAggregation::Last => col(col_name).last(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let binned = df
+        .lazy()
+        .group_by_dynamic(
+            col(index_col),
+            [],
+            DynamicGroupOptions {
+                every,
+                period: every,
+                offset: Duration::parse("0ms"),
+                ..Default::default()
+            },
+        )
+        .agg(aggregations)
+        .collect()
+        .map_err(|e| format!("Error resampling CSV data: {}", e.to_string()))?;
+
+    // group_by_dynamic skips bins with no data in them, so upsample to put them back in as nulls
+    let gridded = binned
+        .upsample(Vec::<PlSmallStr>::new(), index_col, every)
+        .map_err(|e| format!("Error resampling CSV data: {}", e.to_string()))?;
+
+    if resample_settings.gap_fill == GapFillStrategy::None {
+        return Ok(gridded);
     }
 
-    let mut df = lf
+    // Express the maximum gap as a number of consecutive empty bins
+    let max_gap_bins = resample_settings
+        .max_gap_ms
+        .map(|max_gap_ms| (max_gap_ms / resample_settings.period_ms) as IdxSize);
+
+    let fill_exprs = load_csv_settings
+        .load_cols
+        .iter()
+        .map(|col_name| {
+            let filled = match resample_settings.gap_fill {
+                GapFillStrategy::ForwardFill => col(col_name).forward_fill(None),
+                GapFillStrategy::Linear => col(col_name).interpolate(InterpolationMethod::Linear),
+                GapFillStrategy::None => col(col_name),
+            };
+
+            match max_gap_bins {
+                Some(max_gap_bins) => {
+                    // Each run of consecutive nulls (or non-nulls) gets its own id, so counting the rows per id gives the length of each gap
+                    let gap_length = len().over([col(col_name).is_null().rle_id()]);
+                    when(col(col_name).is_null().and(gap_length.gt(lit(max_gap_bins))))
+                        .then(lit(NULL).cast(DataType::Float64))
+                        .otherwise(filled)
+                        .alias(col_name)
+                }
+                None => filled.alias(col_name),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    gridded
+        .lazy()
+        .with_columns(fill_exprs)
         .collect()
-        .map_err(|e| format!("Error collecting CSV data: {}", e.to_string()))?;
+        .map_err(|e| format!("Error filling gaps in resampled CSV data: {}", e.to_string()))
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Column Samples
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Pulls the non-null values of a column out of a loaded DataFrame, paired with their timestamps in milliseconds since the epoch.
+/// Rows where either the timestamp or the value is null are skipped.
+pub fn column_samples(
+    df: &DataFrame,
+    datetime_index_col: &str,
+    col_name: &str,
+) -> Result<Vec<(i64, f64)>, String> {
+    let times = df
+        .column(datetime_index_col)
+        .and_then(|column| column.datetime())
+        .map_err(|e| format!("Error reading the datetime index column: {}", e.to_string()))?;
+
+    let values = df
+        .column(col_name)
+        .and_then(|column| column.f64())
+        .map_err(|e| format!("Error reading column \"{}\": {}", col_name, e.to_string()))?;
+
+    Ok(times
+        .into_iter()
+        .zip(values)
+        .filter_map(|(time, value)| Some((time?, value?)))
+        .collect())
+}
 
-    // Create a cursor to store the serialized data
-    let mut buffer = Vec::new();
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Median Sample Rate
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Sample rate in Hz from a sorted list of millisecond timestamps. Uses the median rather than the mean interval so that a few gaps in the
+/// data don't drag the rate down.
+pub fn median_sample_rate_hz(times: &[i64]) -> Option<f64> {
+    let mut intervals: Vec<i64> = times
+        .windows(2)
+        .map(|pair| pair[1] - pair[0])
+        .filter(|interval| *interval > 0)
+        .collect();
+
+    if intervals.is_empty() {
+        return None;
+    }
+
+    let middle = intervals.len() / 2;
+    let (_, median_interval_ms, _) = intervals.select_nth_unstable(middle);
+
+    Some(1000.0 / *median_interval_ms as f64)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Naive Datetime From Millis
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Converts a timestamp from a millisecond Datetime column back into a NaiveDateTime.
+pub fn naive_datetime_from_millis(milliseconds: i64) -> Option<chrono::NaiveDateTime> {
+    chrono::DateTime::from_timestamp_millis(milliseconds).map(|time| time.naive_utc())
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Inspect Timestamps
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Counts the out of order and repeated timestamps in the datetime index column of an already loaded DataFrame.
+fn inspect_timestamps(
+    df: &DataFrame,
+    load_csv_settings: &LoadCsvSettings,
+) -> Result<TimestampReport, String> {
+    let index_col = load_csv_settings.datetime_index_col.as_str();
+
+    let counts = df
+        .clone()
+        .lazy()
+        .select([
+            col(index_col)
+                .cast(DataType::Int64)
+                .diff(1, NullBehavior::Ignore)
+                .lt(lit(0))
+                .sum()
+                .alias("out_of_order_count"),
+            col(index_col)
+                .is_duplicated()
+                .sum()
+                .alias("duplicate_count"),
+        ])
+        .collect()
+        .map_err(|e| format!("Error checking the datetime index column: {}", e.to_string()))?;
+
+    let get_count = |name: &str| -> Result<usize, String> {
+        counts
+            .column(name)
+            .and_then(|column| column.get(0))
+            .map(|value| value.extract::<usize>().unwrap_or(0))
+            .map_err(|e| format!("Error checking the datetime index column: {}", e.to_string()))
+    };
+
+    Ok(TimestampReport {
+        out_of_order_count: get_count("out_of_order_count")?,
+        duplicate_count: get_count("duplicate_count")?,
+        policy: load_csv_settings.timestamp_policy,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_csv_settings(timestamp_policy: TimestampPolicy, resample: Option<ResampleSettings>) -> LoadCsvSettings {
+        LoadCsvSettings {
+            datetime_index_col: "time".to_string(),
+            datetime_parsing_format_string: String::new(),
+            load_cols: vec!["value".to_string()],
+            time_bounds: None,
+            timestamp_policy,
+            resample,
+            time_zone: None,
+        }
+    }
+
+    /// A DataFrame shaped like a loaded CSV, with the times in milliseconds
+    fn loaded_dataframe(times: &[i64], values: &[Option<f64>]) -> DataFrame {
+        let mut df = df!("time" => times, "value" => values).unwrap();
+        let time = df
+            .column("time")
+            .unwrap()
+            .cast(&DataType::Datetime(TimeUnit::Milliseconds, None))
+            .unwrap();
+        df.with_column(time).unwrap();
+        df
+    }
+
+    fn times(df: &DataFrame) -> Vec<Option<i64>> {
+        df.column("time").unwrap().datetime().unwrap().into_iter().collect()
+    }
+
+    fn values(df: &DataFrame) -> Vec<Option<f64>> {
+        df.column("value").unwrap().f64().unwrap().into_iter().collect()
+    }
+
+    fn apply_policy(timestamp_policy: TimestampPolicy, df: DataFrame) -> Result<DataFrame, String> {
+        let load_csv_settings = load_csv_settings(timestamp_policy, None);
+        let timestamp_report = inspect_timestamps(&df, &load_csv_settings)?;
+        apply_timestamp_policy(df, &load_csv_settings, &timestamp_report)
+    }
+
+    #[test]
+    fn inspect_timestamps_counts_out_of_order_and_repeated_rows() {
+        let df = loaded_dataframe(&[0, 20, 10, 10, 30], &[Some(1.0); 5]);
+        let timestamp_report = inspect_timestamps(&df, &load_csv_settings(TimestampPolicy::Sort, None)).unwrap();
+
+        assert_eq!(timestamp_report.out_of_order_count, 1);
+        assert_eq!(timestamp_report.duplicate_count, 2);
+        assert!(timestamp_report.has_issues());
+    }
+
+    #[test]
+    fn sort_keeps_every_row_in_file_order_within_a_timestamp() {
+        let df = apply_policy(
+            TimestampPolicy::Sort,
+            loaded_dataframe(&[20, 10, 0, 10], &[Some(4.0), Some(2.0), Some(1.0), Some(3.0)]),
+        )
+        .unwrap();
+
+        assert_eq!(times(&df), vec![Some(0), Some(10), Some(10), Some(20)]);
+        assert_eq!(values(&df), vec![Some(1.0), Some(2.0), Some(3.0), Some(4.0)]);
+    }
+
+    #[test]
+    fn repeated_timestamps_are_resolved_per_the_policy() {
+        let duplicated = || loaded_dataframe(&[0, 10, 10, 20], &[Some(1.0), Some(2.0), Some(3.0), Some(4.0)]);
+
+        for (timestamp_policy, expected) in [
+            (TimestampPolicy::KeepFirst, 2.0),
+            (TimestampPolicy::KeepLast, 3.0),
+            (TimestampPolicy::AverageDuplicates, 2.5),
+        ] {
+            let df = apply_policy(timestamp_policy, duplicated()).unwrap();
+
+            assert_eq!(times(&df), vec![Some(0), Some(10), Some(20)]);
+            assert_eq!(values(&df), vec![Some(1.0), Some(expected), Some(4.0)], "{:?}", timestamp_policy);
+        }
+    }
+
+    #[test]
+    fn fail_refuses_data_with_timestamp_issues() {
+        assert!(apply_policy(TimestampPolicy::Fail, loaded_dataframe(&[10, 0], &[Some(1.0), Some(2.0)])).is_err());
+    }
+
+    fn resample_settings(gap_fill: GapFillStrategy, max_gap_ms: Option<u64>) -> ResampleSettings {
+        ResampleSettings {
+            period_ms: 100,
+            default_aggregation: ResampleAggregation::Mean,
+            column_aggregations: Default::default(),
+            gap_fill,
+            max_gap_ms,
+        }
+    }
 
-    // https://docs.rs/polars/latest/polars/prelude/struct.IpcWriter.html
-    // TODO: Check if this should be an IPC stream writer instead
-    IpcWriter::new(&mut buffer)
-        .finish(&mut df)
-        .map_err(|e| format!("Error serializing DataFrame: {}", e.to_string()))?;
+    fn resample(df: DataFrame, resample_settings: ResampleSettings) -> DataFrame {
+        let load_csv_settings = load_csv_settings(TimestampPolicy::Sort, Some(resample_settings.clone()));
+        resample_dataframe(df, &load_csv_settings, &resample_settings).unwrap()
+    }
+
+    /// Samples on a 100 ms grid with a 2 bin gap after 100 ms and a 5 bin gap after 500 ms. Each value is its time in tenths of a second.
+    fn gappy_dataframe() -> DataFrame {
+        loaded_dataframe(
+            &[0, 100, 400, 500, 1100],
+            &[Some(0.0), Some(1.0), Some(4.0), Some(5.0), Some(11.0)],
+        )
+    }
+
+    #[test]
+    fn resampling_aggregates_each_bin_and_labels_it_with_its_start() {
+        let df = loaded_dataframe(&[0, 50, 100, 150, 199], &[Some(1.0), Some(3.0), Some(5.0), Some(7.0), Some(9.0)]);
+
+        let mean = resample(df.clone(), resample_settings(GapFillStrategy::None, None));
+        assert_eq!(times(&mean), vec![Some(0), Some(100)]);
+        assert_eq!(values(&mean), vec![Some(2.0), Some(7.0)]);
+
+        let mut last_settings = resample_settings(GapFillStrategy::None, None);
+        last_settings
+            .column_aggregations
+            .insert("value".to_string(), ResampleAggregation::Last);
+        assert_eq!(values(&resample(df, last_settings)), vec![Some(3.0), Some(9.0)]);
+    }
+
+    #[test]
+    fn empty_bins_are_left_null_without_a_gap_fill() {
+        let df = resample(gappy_dataframe(), resample_settings(GapFillStrategy::None, None));
 
-    Ok(Response::new(buffer))
+        assert_eq!(times(&df), (0..12).map(|i| Some(i * 100)).collect::<Vec<_>>());
+        assert_eq!(values(&df).iter().filter(|value| value.is_none()).count(), 7);
+    }
+
+    #[test]
+    fn gap_fill_covers_every_gap_without_a_max_gap() {
+        let forward_filled = resample(gappy_dataframe(), resample_settings(GapFillStrategy::ForwardFill, None));
+        assert_eq!(
+            values(&forward_filled),
+            [0.0, 1.0, 1.0, 1.0, 4.0, 5.0, 5.0, 5.0, 5.0, 5.0, 5.0, 11.0].map(Some).to_vec()
+        );
+
+        let interpolated = resample(gappy_dataframe(), resample_settings(GapFillStrategy::Linear, None));
+        assert_eq!(values(&interpolated), (0..12).map(|i| Some(i as f64)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn gaps_longer_than_the_max_gap_are_left_null() {
+        // 300 ms is 3 bins, so the 2 bin gap is filled and the 5 bin gap isn't
+        let df = resample(gappy_dataframe(), resample_settings(GapFillStrategy::Linear, Some(300)));
+
+        assert_eq!(
+            values(&df),
+            vec![Some(0.0), Some(1.0), Some(2.0), Some(3.0), Some(4.0), Some(5.0), None, None, None, None, None, Some(11.0)]
+        );
+    }
 }
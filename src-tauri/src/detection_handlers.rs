@@ -0,0 +1,261 @@
+use crate::dataframe_handlers::{column_samples, display_data_with_columns, naive_datetime_from_millis};
+use crate::global_state::{
+    snapshot_app_state, update_app_state_field, write_app_state, Annotation, AppState, AppStateField, CrossingDirection,
+    DetectionRule, DetectionRuleKind, ThresholdComparison,
+};
+use crate::job_handlers::run_job;
+use chrono::NaiveDateTime;
+use polars::prelude::*;
+use serde::Serialize;
+use std::sync::RwLock;
+use tauri::{AppHandle, State};
+
+/// Category given to annotations created from detection hits, so they can be told apart from the user's own annotations.
+const DETECTION_ANNOTATION_CATEGORY: &str = "detection";
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Structs
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+/// One span of time where a detection rule fired.
+#[derive(Serialize, Clone, Debug)]
+pub struct DetectionHit {
+    pub rule_id: u64,
+    pub rule_name: String,
+    pub column: String,
+    pub start_time: NaiveDateTime,
+    pub end_time: NaiveDateTime,
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Tauri Commands
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Run Detection Rules
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Runs every enabled detection rule in the AppState over the loaded data, the same data the rules run on after get_csv_data, so rules can
+/// watch filtered channels too. CSV columns used by the rules are loaded even if they aren't in load_cols.
+/// If add_as_annotations is true, each hit is also added as an annotation titled with the rule name. Hits that match an existing annotation
+/// are not added again, so running the rules twice doesn't double up.
+#[tauri::command]
+pub async fn run_detection_rules(
+    app: AppHandle,
+    state: State<'_, RwLock<AppState>>,
+    add_as_annotations: bool,
+) -> Result<Vec<DetectionHit>, String> {
+    let app_state = snapshot_app_state(&state);
+    let job_app = app.clone();
+
+    let hits = run_job(&app, "run_detection_rules", move |job| {
+        let datetime_index_col = app_state
+            .load_csv_settings
+            .as_ref()
+            .ok_or("CSV loading settings have not been set yet.")?
+            .datetime_index_col
+            .as_str();
+
+        let rules: Vec<&DetectionRule> = app_state.detection_rules.iter().filter(|rule| rule.enabled).collect();
+
+        if rules.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let columns: Vec<String> = rules.iter().map(|rule| rule.column.clone()).collect();
+        let df = display_data_with_columns(&job_app, &app_state, &columns, job)?;
+
+        job.checkpoint()?;
+        job.progress("Running detection rules", Some(70.0), Some(df.height()));
+
+        detect_events(&df, datetime_index_col, rules)
+    })
+    .await?;
+
+    if add_as_annotations && !hits.is_empty() {
+        let new_annotations = hits.iter().map(|hit| Annotation {
+            id: 0, // Assigned by merge_annotations
+            start_time: hit.start_time,
+            end_time: Some(hit.end_time).filter(|end_time| *end_time != hit.start_time),
+            title: hit.rule_name.clone(),
+            description: format!("Detected in {}", hit.column),
+            category: Some(DETECTION_ANNOTATION_CATEGORY.to_string()),
+        });
+
+        // The rules ran on a snapshot, so merge into the annotations as they are now in case they were edited in the meantime
+        let app_state = write_app_state(&state);
+        let (annotations, skipped_duplicates) = app_state.merge_annotations(new_annotations);
+
+        if skipped_duplicates < hits.len() {
+            update_app_state_field(&app, app_state, AppStateField::Annotations { value: annotations })?;
+        }
+    }
+
+    Ok(hits)
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Utility Functions
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Detect Events
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Runs each rule over its column of an already loaded DataFrame. Hits come back sorted by start time.
+pub fn detect_events<'a>(
+    df: &DataFrame,
+    datetime_index_col: &str,
+    rules: impl IntoIterator<Item = &'a DetectionRule>,
+) -> Result<Vec<DetectionHit>, String> {
+    let mut hits = Vec::new();
+
+    for rule in rules {
+        let samples = column_samples(df, datetime_index_col, &rule.column)?;
+
+        for (start, end) in detect_rule_ranges(&rule.kind, &samples) {
+            hits.push(DetectionHit {
+                rule_id: rule.id,
+                rule_name: rule.name.clone(),
+                column: rule.column.clone(),
+                start_time: naive_datetime_from_millis(start).ok_or("Detection hit start time is out of range.")?,
+                end_time: naive_datetime_from_millis(end).ok_or("Detection hit end time is out of range.")?,
+            });
+        }
+    }
+
+    hits.sort_by_key(|hit| (hit.start_time, hit.rule_id));
+
+    Ok(hits)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Detect Rule Ranges
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Returns the (start, end) millisecond timestamps of every span where the rule fired. Samples must be sorted by time.
+fn detect_rule_ranges(kind: &DetectionRuleKind, samples: &[(i64, f64)]) -> Vec<(i64, i64)> {
+    match *kind {
+        DetectionRuleKind::ThresholdCrossing {
+            threshold,
+            direction,
+            hysteresis,
+        } => {
+            let hysteresis = hysteresis.abs();
+
+            // A falling crossing is just a rising crossing of the negated signal
+            let negated: Vec<(i64, f64)> = samples.iter().map(|&(time, value)| (time, -value)).collect();
+
+            let mut ranges = match direction {
+                CrossingDirection::Rising => rising_crossings(samples, threshold, hysteresis),
+                CrossingDirection::Falling => rising_crossings(&negated, -threshold, hysteresis),
+                CrossingDirection::Either => {
+                    let mut ranges = rising_crossings(samples, threshold, hysteresis);
+                    ranges.extend(rising_crossings(&negated, -threshold, hysteresis));
+                    ranges
+                }
+            };
+
+            ranges.sort();
+            ranges
+        }
+
+        DetectionRuleKind::RateOfChange { max_rate_per_second } => {
+            let mut ranges: Vec<(i64, i64)> = Vec::new();
+
+            for pair in samples.windows(2) {
+                let ((t0, v0), (t1, v1)) = (pair[0], pair[1]);
+                let dt_seconds = (t1 - t0) as f64 / 1000.0;
+
+                if dt_seconds <= 0.0 || ((v1 - v0) / dt_seconds).abs() <= max_rate_per_second.abs() {
+                    continue;
+                }
+
+                // Back to back fast segments are one event, not one per sample
+                match ranges.last_mut() {
+                    Some((_, end)) if *end == t0 => *end = t1,
+                    _ => ranges.push((t0, t1)),
+                }
+            }
+
+            ranges
+        }
+
+        DetectionRuleKind::HeldForDuration {
+            threshold,
+            comparison,
+            min_duration_seconds,
+        } => {
+            let min_duration_ms = (min_duration_seconds * 1000.0).round() as i64;
+
+            let mut ranges: Vec<(i64, i64)> = Vec::new();
+            let mut run: Option<(i64, i64)> = None;
+
+            for &(time, value) in samples {
+                let holds = match comparison {
+                    ThresholdComparison::Above => value > threshold,
+                    ThresholdComparison::Below => value < threshold,
+                };
+
+                run = match (run, holds) {
+                    (Some((start, _)), true) => Some((start, time)),
+                    (None, true) => Some((time, time)),
+                    (Some(finished), false) => {
+                        ranges.push(finished);
+                        None
+                    }
+                    (None, false) => None,
+                };
+            }
+
+            ranges.extend(run);
+            ranges.retain(|(start, end)| end - start >= min_duration_ms);
+            ranges
+        }
+
+        DetectionRuleKind::StepChange { min_step } => samples
+            .windows(2)
+            .filter(|pair| (pair[1].1 - pair[0].1).abs() >= min_step.abs())
+            .map(|pair| (pair[0].0, pair[1].0))
+            .collect(),
+    }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Rising Crossings
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Finds each time the signal rises from below the threshold to at or above it. A hit lasts until the signal falls back below
+/// threshold - hysteresis, so noise sitting right on the threshold doesn't produce a burst of tiny hits. If the signal starts above the
+/// threshold that doesn't count as a crossing.
+fn rising_crossings(samples: &[(i64, f64)], threshold: f64, hysteresis: f64) -> Vec<(i64, i64)> {
+    let mut ranges = Vec::new();
+    let mut armed = false;
+    let mut start: Option<i64> = None;
+
+    for &(time, value) in samples {
+        match start {
+            None if armed && value >= threshold => start = Some(time),
+            None if value < threshold => armed = true,
+            None => {}
+            Some(hit_start) if value < threshold - hysteresis => {
+                ranges.push((hit_start, time));
+                start = None;
+            }
+            Some(_) => {}
+        }
+    }
+
+    // Still above the threshold when the data ends
+    if let (Some(hit_start), Some(&(last_time, _))) = (start, samples.last()) {
+        ranges.push((hit_start, last_time));
+    }
+
+    ranges
+}
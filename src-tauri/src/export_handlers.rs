@@ -0,0 +1,406 @@
+use crate::dataframe_handlers::{column_samples, display_data_with_columns, select_display_data};
+use crate::global_state::{snapshot_app_state, AppState, TimeBounds, TimeWindow};
+use crate::job_handlers::run_job;
+use chrono::NaiveDateTime;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{fs::File, io::Write, sync::RwLock};
+use tauri::{path::SafePathBuf, AppHandle, State};
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Structs
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+/// Name of the optional column holding the number of seconds since the video started
+const VIDEO_SECONDS_COL: &str = "Video Time [s]";
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DataExportFormat {
+    Csv,
+    Parquet,
+    ArrowIpc,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DataExportOptions {
+    pub file_path: SafePathBuf,
+    pub format: DataExportFormat,
+    /// A strftime format string to write the datetime index column as text. None keeps it as a datetime, which CSV files write as ISO 8601.
+    #[serde(default)]
+    pub timestamp_format: Option<String>,
+    /// Add a column with the number of seconds since the start of the video
+    #[serde(default)]
+    pub include_video_seconds: bool,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportFormat {
+    Csv,
+    Json,
+    Markdown,
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportEntryKind {
+    Annotation,
+    Chapter,
+}
+
+/// One line of an annotation report, describing either an annotation or a video chapter.
+#[derive(Serialize, Debug)]
+pub struct ReportEntry {
+    kind: ReportEntryKind,
+    id: u64,
+    title: String,
+    description: String,
+    start_time: NaiveDateTime,
+    end_time: Option<NaiveDateTime>,
+    /// Seconds from the start of the video to the start_time
+    video_offset_seconds: Option<f64>,
+    /// Seconds from the first timestamp of the loaded data to the start_time
+    data_offset_seconds: Option<f64>,
+    /// The value of each requested channel at the start_time
+    channel_values: Vec<ChannelValue>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ChannelValue {
+    name: String,
+    value: Option<f64>,
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Tauri Commands
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Export Data
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Writes a slice of the loaded data out to a file. The slice covers the window (a time range or an annotation) if one is given, otherwise
+/// everything loaded within the time bounds in the load_csv_settings. If no columns are given, every loaded column and filtered channel is
+/// exported. The data is the display data from display_data_with_columns, with the timestamp policy, resampling, time zones and channel
+/// filters applied, so the file matches what is plotted. Returns the number of rows written.
+#[tauri::command]
+pub async fn export_data(
+    app: AppHandle,
+    state: State<'_, RwLock<AppState>>,
+    window: Option<TimeWindow>,
+    columns: Vec<String>,
+    options: DataExportOptions,
+) -> Result<usize, String> {
+    let state = snapshot_app_state(&state);
+    let job_app = app.clone();
+
+    run_job(&app, "export_data", move |job| {
+        let index_col = state
+            .load_csv_settings
+            .as_ref()
+            .ok_or("CSV loading settings have not been set yet.")?
+            .datetime_index_col
+            .as_str();
+
+        let time_bounds = match window {
+            Some(ref window) => {
+                let (start_time, end_time) = state.resolve_time_window(window)?;
+                Some(TimeBounds {
+                    start_time: Some(start_time),
+                    end_time: Some(end_time),
+                })
+            }
+            None => None,
+        };
+
+        let df = display_data_with_columns(&job_app, &state, &columns, job)?;
+
+        let columns: Vec<String> = if columns.is_empty() {
+            df.get_column_names()
+                .into_iter()
+                .filter(|name| name.as_str() != index_col)
+                .map(|name| name.to_string())
+                .collect()
+        } else {
+            columns
+        };
+
+        let df = select_display_data(&df, index_col, &columns, time_bounds.as_ref())?;
+
+        job.checkpoint()?;
+        job.progress("Preparing export", Some(50.0), Some(df.height()));
+
+        let mut lf = df.lazy();
+
+        // This has to come before the timestamps get formatted as text
+        if options.include_video_seconds {
+            let video_start_time = state
+                .display_video_start_time()?
+                .ok_or("The video start time has not been set yet.")?;
+
+            lf = lf.with_column(
+                ((col(index_col).cast(DataType::Int64)
+                    - lit(video_start_time.and_utc().timestamp_millis()))
+                .cast(DataType::Float64)
+                    / lit(1000.0 * state.video_time_scale()))
+                .alias(VIDEO_SECONDS_COL),
+            );
+        }
+
+        if let Some(ref timestamp_format) = options.timestamp_format {
+            lf = lf.with_column(col(index_col).dt().to_string(timestamp_format).alias(index_col));
+        }
+
+        let mut df = lf
+            .collect()
+            .map_err(|e| format!("Error preparing data for export: {}", e.to_string()))?;
+
+        job.checkpoint()?;
+        job.progress("Writing file", Some(75.0), Some(df.height()));
+
+        write_dataframe(&mut df, &options.file_path, options.format)?;
+
+        Ok(df.height())
+    })
+    .await
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Export Annotation Report
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Writes every annotation and video chapter, in time order, to a CSV, JSON, or Markdown report. If any channels are given, each entry also gets
+/// the value of those channels at its start time, taken from the data as get_csv_data would load it. Returns the number of entries written.
+#[tauri::command]
+pub async fn export_annotation_report(
+    app: AppHandle,
+    state: State<'_, RwLock<AppState>>,
+    file_path: SafePathBuf,
+    format: ReportFormat,
+    channels: Vec<String>,
+) -> Result<usize, String> {
+    let state = snapshot_app_state(&state);
+    let job_app = app.clone();
+
+    run_job(&app, "export_annotation_report", move |job| {
+        let video_start_time = state.display_video_start_time()?;
+        let video_time_scale = state.video_time_scale();
+
+        // Reports can be made before any data has been loaded, so only read the CSV if there is one
+        let mut data_start_time = None;
+        let mut channel_samples = Vec::new();
+
+        if let (Some(_), Some(ref load_csv_settings)) = (&state.csv_file_path, &state.load_csv_settings) {
+            let df = display_data_with_columns(&job_app, &state, &channels, job)?;
+
+            job.checkpoint()?;
+
+            data_start_time = df
+                .column(&load_csv_settings.datetime_index_col)
+                .and_then(|column| column.datetime().map(|times| times.min()))
+                .map_err(|e| format!("Error reading the datetime index column: {}", e.to_string()))?;
+
+            for channel in &channels {
+                channel_samples.push((
+                    channel.clone(),
+                    column_samples(&df, &load_csv_settings.datetime_index_col, channel)?,
+                ));
+            }
+        } else if !channels.is_empty() {
+            return Err("Channel values were requested, but no CSV data has been loaded.".to_string());
+        }
+
+        let make_entry = |kind, id, title: &str, description: &str, start_time: NaiveDateTime, end_time| {
+            let start_millis = start_time.and_utc().timestamp_millis();
+
+            ReportEntry {
+                kind,
+                id,
+                title: title.to_string(),
+                description: description.to_string(),
+                start_time,
+                end_time,
+                video_offset_seconds: video_start_time
+                    .map(|video_start_time| {
+                        (start_time - video_start_time).num_milliseconds() as f64 / 1000.0 / video_time_scale
+                    }),
+                data_offset_seconds: data_start_time
+                    .map(|data_start_time| (start_millis - data_start_time) as f64 / 1000.0),
+                channel_values: channel_samples
+                    .iter()
+                    .map(|(name, samples)| ChannelValue {
+                        name: name.clone(),
+                        value: value_at_time(samples, start_millis),
+                    })
+                    .collect(),
+            }
+        };
+
+        let mut entries: Vec<ReportEntry> = state
+            .annotations
+            .iter()
+            .map(|annotation| {
+                make_entry(
+                    ReportEntryKind::Annotation,
+                    annotation.id,
+                    &annotation.title,
+                    &annotation.description,
+                    annotation.start_time,
+                    annotation.end_time,
+                )
+            })
+            .chain(state.video_chapters.iter().map(|chapter| {
+                make_entry(ReportEntryKind::Chapter, chapter.id, &chapter.title, "", chapter.start_time, None)
+            }))
+            .collect();
+
+        entries.sort_by_key(|entry| entry.start_time);
+
+        let contents = match format {
+            ReportFormat::Json => serde_json::to_string_pretty(&entries)
+                .map_err(|e| format!("Serialization error: {}", e))?,
+            ReportFormat::Csv => format_report_table(&entries, &channels, ReportFormat::Csv),
+            ReportFormat::Markdown => format_report_table(&entries, &channels, ReportFormat::Markdown),
+        };
+
+        job.progress("Writing report", Some(90.0), Some(entries.len()));
+
+        let mut file = File::create(&file_path).map_err(|e| format!("Failed to create file: {}", e))?;
+        file.write_all(contents.as_bytes())
+            .map_err(|e| format!("Failed to write to file: {}", e))?;
+
+        Ok(entries.len())
+    })
+    .await
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Utility Functions
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Write DataFrame
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Writes a DataFrame to a new file (overwriting any existing file) with the Polars writer for the chosen format.
+fn write_dataframe(
+    df: &mut DataFrame,
+    file_path: &SafePathBuf,
+    format: DataExportFormat,
+) -> Result<(), String> {
+    let mut file = File::create(file_path).map_err(|e| format!("Failed to create file: {}", e))?;
+
+    match format {
+        DataExportFormat::Csv => CsvWriter::new(&mut file).finish(df),
+        DataExportFormat::Parquet => ParquetWriter::new(&mut file).finish(df).map(|_| ()),
+        DataExportFormat::ArrowIpc => IpcWriter::new(&mut file).finish(df),
+    }
+    .map_err(|e| format!("Error writing {:?} file: {}", format, e.to_string()))
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Value At Time
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The value of a channel at a point in time is the most recent sample at or before that time, which is what the plot shows at that moment.
+/// Times outside of the loaded data have no value.
+pub fn value_at_time(samples: &[(i64, f64)], time_millis: i64) -> Option<f64> {
+    let (last_time, _) = samples.last()?;
+    if time_millis > *last_time {
+        return None;
+    }
+
+    let index = samples.partition_point(|(time, _)| *time <= time_millis);
+    index.checked_sub(1).map(|index| samples[index].1)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Format Report Table
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Lays the report entries out as a table, with one column per channel, and renders it as either CSV or a Markdown table.
+fn format_report_table(entries: &[ReportEntry], channels: &[String], format: ReportFormat) -> String {
+    let format_time = |time: NaiveDateTime| time.format("%Y-%m-%d %H:%M:%S%.3f").to_string();
+    let format_number = |number: Option<f64>| number.map(|number| number.to_string()).unwrap_or_default();
+
+    let header: Vec<String> = [
+        "Kind",
+        "ID",
+        "Title",
+        "Description",
+        "Start Time",
+        "End Time",
+        "Video Offset [s]",
+        "Data Offset [s]",
+    ]
+    .iter()
+    .map(|name| name.to_string())
+    .chain(channels.iter().cloned())
+    .collect();
+
+    let rows: Vec<Vec<String>> = entries
+        .iter()
+        .map(|entry| {
+            [
+                match entry.kind {
+                    ReportEntryKind::Annotation => "annotation".to_string(),
+                    ReportEntryKind::Chapter => "chapter".to_string(),
+                },
+                entry.id.to_string(),
+                entry.title.clone(),
+                entry.description.clone(),
+                format_time(entry.start_time),
+                entry.end_time.map(format_time).unwrap_or_default(),
+                format_number(entry.video_offset_seconds),
+                format_number(entry.data_offset_seconds),
+            ]
+            .into_iter()
+            .chain(entry.channel_values.iter().map(|channel| format_number(channel.value)))
+            .collect()
+        })
+        .collect();
+
+    match format {
+        ReportFormat::Markdown => {
+            // Pipes would split a cell and newlines would end the row, so neither can appear inside a cell
+            let format_row = |cells: &[String]| {
+                let cells: Vec<String> = cells
+                    .iter()
+                    .map(|cell| cell.replace('|', "\\|").replace(['\r', '\n'], " "))
+                    .collect();
+                format!("| {} |\n", cells.join(" | "))
+            };
+
+            let mut table = format_row(&header);
+            table.push_str(&format!("|{}\n", " --- |".repeat(header.len())));
+            for row in &rows {
+                table.push_str(&format_row(row));
+            }
+            table
+        }
+        _ => {
+            let format_cell = |cell: &String| {
+                if cell.contains([',', '"', '\n', '\r']) {
+                    format!("\"{}\"", cell.replace('"', "\"\""))
+                } else {
+                    cell.clone()
+                }
+            };
+            let format_row =
+                |cells: &[String]| format!("{}\n", cells.iter().map(format_cell).collect::<Vec<_>>().join(","));
+
+            let mut table = format_row(&header);
+            for row in &rows {
+                table.push_str(&format_row(row));
+            }
+            table
+        }
+    }
+}
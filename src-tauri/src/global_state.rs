@@ -1,16 +1,33 @@
-use chrono::NaiveDateTime;
+use crate::state_validation::{validate_app_state, validate_app_state_fields, ValidationError};
+use crate::time_zones::to_display_time;
+use crate::user_preferences::{remember_recent_file, RecentFileKind};
+use chrono::{NaiveDateTime, TimeDelta};
 use derive_more::derive::{From, Into};
 use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
 use std::{
+    collections::HashMap,
     fs::File,
+    hash::{DefaultHasher, Hash, Hasher},
     io::{BufReader, Write},
-    path::Path,
-    sync::{Mutex, MutexGuard},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
 };
 use strum::{EnumIter, IntoEnumIterator};
 use tauri::{path::SafePathBuf, AppHandle, Emitter, State};
 
+/// Goes up by one for every change to the AppState, and is sent with every "state-change--*" event. Only ever bumped with the write lock
+/// held, so whoever holds either lock sees the revision that matches the state. Kept outside the AppState so that replacing the whole state
+/// (clear_app_state, load_app_state_from_file) can't send it backwards.
+static STATE_REVISION: AtomicU64 = AtomicU64::new(0);
+
+/// Hash of the persistent state as it was last saved, loaded or cleared, which is_modified_since_last_save is worked out against. None until
+/// one of those happens, which compares against a default AppState. Only touched with the write lock held, the same as STATE_REVISION.
+static SAVED_STATE_HASH: Mutex<Option<u64>> = Mutex::new(None);
+
 // #############################################################################################################################################
 // #############################################################################################################################################
 // Newtypes
@@ -27,6 +44,14 @@ pub struct CsvFilePath(SafePathBuf);
 #[derive(From, Into, Clone, Deserialize, Serialize)]
 pub struct VideoFilePath(SafePathBuf);
 
+/// IANA name of the time zone that the video start time was recorded in
+#[derive(From, Into, Clone, Deserialize, Serialize)]
+pub struct VideoTimeZone(String);
+
+/// IANA name of the time zone that all zoned sources are converted to
+#[derive(From, Into, Clone, Deserialize, Serialize)]
+pub struct DisplayTimeZone(String);
+
 #[derive(Default, From, Into, Clone, Deserialize, Serialize)]
 pub struct IsMultiwindow(bool);
 
@@ -43,6 +68,30 @@ impl AsRef<Path> for SaveFilePath {
     }
 }
 
+impl AsRef<Path> for CsvFilePath {
+    fn as_ref(&self) -> &Path {
+        self.0.as_ref()
+    }
+}
+
+impl AsRef<Path> for VideoFilePath {
+    fn as_ref(&self) -> &Path {
+        self.0.as_ref()
+    }
+}
+
+impl AsRef<str> for VideoTimeZone {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for DisplayTimeZone {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
 // #############################################################################################################################################
 // #############################################################################################################################################
 // Secondary Structs
@@ -56,6 +105,13 @@ pub struct LoadCsvSettings {
     pub datetime_parsing_format_string: String,
     pub load_cols: Vec<String>,
     pub time_bounds: Option<TimeBounds>,
+    #[serde(default)]
+    pub timestamp_policy: TimestampPolicy,
+    #[serde(default)]
+    pub resample: Option<ResampleSettings>,
+    /// IANA name of the time zone the datetime index was recorded in. Leave as None to treat the timestamps as naive.
+    #[serde(default)]
+    pub time_zone: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -68,6 +124,235 @@ pub struct TimeBounds {
     pub end_time: Option<NaiveDateTime>,
 }
 
+/// What to do when the datetime index column of a CSV is out of order or contains repeated timestamps (DST repeats, clock jumps, interleaved
+/// DAQ buffers, etc). Every policy other than Fail leaves the loaded data sorted by the datetime index.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampPolicy {
+    /// Sort by the datetime index, keeping every row
+    #[default]
+    Sort,
+    /// Sort, and keep only the first row in the file for each repeated timestamp
+    KeepFirst,
+    /// Sort, and keep only the last row in the file for each repeated timestamp
+    KeepLast,
+    /// Sort, and replace the rows for each repeated timestamp with the mean of each column
+    AverageDuplicates,
+    /// Refuse to load the data if the index is out of order or has repeated timestamps
+    Fail,
+}
+
+/// Settings for binning the loaded data onto a uniform time grid, so that data from loggers recording at different (or irregular) rates lines up.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ResampleSettings {
+    /// Width of each bin on the time grid, in milliseconds
+    pub period_ms: u64,
+    /// Used for any of the load_cols that do not have an entry in column_aggregations
+    #[serde(default)]
+    pub default_aggregation: ResampleAggregation,
+    #[serde(default)]
+    pub column_aggregations: HashMap<String, ResampleAggregation>,
+    /// How to fill bins that had no data in them
+    #[serde(default)]
+    pub gap_fill: GapFillStrategy,
+    /// Runs of empty bins spanning more than this many milliseconds are left empty instead of being filled. None fills every gap.
+    #[serde(default)]
+    pub max_gap_ms: Option<u64>,
+}
+
+/// How all of the samples that fall within one resampling bin get combined into one value
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResampleAggregation {
+    #[default]
+    Mean,
+    Min,
+    Max,
+    First,
+    Last,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GapFillStrategy {
+    /// Leave empty bins as nulls
+    #[default]
+    None,
+    /// Repeat the last value before the gap
+    ForwardFill,
+    /// Draw a straight line between the values on either side of the gap
+    Linear,
+}
+
+/// An item of interest (IOI) that has been noted in the data or video. Annotations without an end_time mark a single point in time.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Annotation {
+    pub id: u64,
+    #[serde(deserialize_with = "naive_datetime")]
+    pub start_time: NaiveDateTime,
+    #[serde(default, deserialize_with = "nullable_naive_datetime")]
+    pub end_time: Option<NaiveDateTime>,
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+/// A named section of the video, like the chapters shown when hovering over a YouTube progress bar. Each chapter runs until the next one starts.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct VideoChapter {
+    pub id: u64,
+    #[serde(deserialize_with = "naive_datetime")]
+    pub start_time: NaiveDateTime,
+    pub title: String,
+}
+
+/// Pairs a position in the video with the data timestamp it is known to line up with, like "the valve clunk at 1:32.4 in the video is
+/// the pressure spike at 10:11:32.1". The video start time and clock scale are solved from these.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct VideoSyncPoint {
+    pub video_seconds: f64,
+    /// In the display time zone, the same as the plotted data
+    #[serde(deserialize_with = "naive_datetime")]
+    pub data_time: NaiveDateTime,
+}
+
+/// A rule that scans one column of the loaded data for events, like "pressure went over 30 psia". Rules are saved with the rest of the state
+/// so that they are run again whenever the data is reloaded.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct DetectionRule {
+    pub id: u64,
+    pub name: String,
+    pub column: String,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    pub kind: DetectionRuleKind,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum DetectionRuleKind {
+    /// Fires when the column crosses the threshold, and lasts until it comes back past the threshold by more than the hysteresis
+    ThresholdCrossing {
+        threshold: f64,
+        direction: CrossingDirection,
+        #[serde(default)]
+        hysteresis: f64,
+    },
+    /// Fires while the column changes faster than the limit, in column units per second
+    RateOfChange { max_rate_per_second: f64 },
+    /// Fires when the column stays above (or below) the threshold for at least the minimum duration
+    HeldForDuration {
+        threshold: f64,
+        comparison: ThresholdComparison,
+        min_duration_seconds: f64,
+    },
+    /// Fires when the column jumps by at least the minimum step from one sample to the next
+    StepChange { min_step: f64 },
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CrossingDirection {
+    Rising,
+    Falling,
+    Either,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ThresholdComparison {
+    Above,
+    Below,
+}
+
+/// A filter that is run over one channel when the data is loaded. The output is added as a new channel and the raw channel is left alone.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ChannelFilter {
+    pub column: String,
+    /// Name of the new channel. Defaults to the input column name followed by a short description of the filter.
+    #[serde(default)]
+    pub output_column: Option<String>,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Run the filter forwards and then backwards so the output isn't delayed relative to the input. For the window filters this means
+    /// centering the window instead of only looking at past samples.
+    #[serde(default)]
+    pub zero_phase: bool,
+    pub kind: FilterKind,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum FilterKind {
+    ButterworthLowPass { cutoff_hz: f64, order: usize },
+    ButterworthHighPass { cutoff_hz: f64, order: usize },
+    ButterworthBandPass {
+        low_cutoff_hz: f64,
+        high_cutoff_hz: f64,
+        order: usize,
+    },
+    Notch { center_hz: f64, quality_factor: f64 },
+    /// Window sizes are in samples
+    MovingAverage { window_size: usize },
+    Median { window_size: usize },
+    SavitzkyGolay {
+        window_size: usize,
+        polynomial_order: usize,
+    },
+}
+
+/// How the loaded data is encoded when it is sent to the frontend. Nothing here changes the data held in the backend, only what goes over IPC.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct TransferSettings {
+    /// Columns not listed here are sent as Float64
+    #[serde(default)]
+    pub column_precision: HashMap<String, ColumnPrecision>,
+}
+
+/// The type a column is sent to the frontend as. Both of the smaller types halve the payload of a Float64 column.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnPrecision {
+    #[default]
+    Float64,
+    /// About 7 significant digits
+    Float32,
+    /// Sent as Int32 holding the value times 10^decimal_places, rounded. NaN and infinite values are sent as nulls, and a column with any
+    /// other value that doesn't fit in an Int32 can't be sent at all.
+    ScaledInteger { decimal_places: u8 },
+}
+
+/// A span of time that a command should work on, given either directly or as the time range of an annotation.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum TimeWindow {
+    Range {
+        #[serde(deserialize_with = "naive_datetime")]
+        start_time: NaiveDateTime,
+        #[serde(deserialize_with = "naive_datetime")]
+        end_time: NaiveDateTime,
+    },
+    Annotation {
+        id: u64,
+    },
+}
+
+/// Payload of every "state-change--*" event. A window that sees the revision jump by more than one has missed a change and should fetch a
+/// get_app_state_snapshot. Every event sent for the same change carries the same revision.
+#[derive(Serialize, Clone)]
+pub struct StateChange<T> {
+    revision: u64,
+    value: T,
+}
+
+/// The whole AppState along with the revision it is at, from get_app_state_snapshot
+#[derive(Serialize)]
+pub struct AppStateSnapshot {
+    revision: u64,
+    state: AppState,
+}
 
 // #############################################################################################################################################
 // #############################################################################################################################################
@@ -82,7 +367,7 @@ pub struct TimeBounds {
 /// When adding fields to this struct, ensure you also add them to the AppStateField enum.
 /// Anywhere that there are two attributes with the same type, create a new type to enforce code correctness at compile time. 
 /// Add #[serde(default)] for traits that we do not care if they are missing when loading from a file.
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct AppState {
     // Danger: Ensure these are all captured in the AppStateField enum
     pub save_file_path: Option<SaveFilePath>,
@@ -90,6 +375,25 @@ pub struct AppState {
     pub load_csv_settings: Option<LoadCsvSettings>,
     pub video_file_path: Option<VideoFilePath>,
     pub video_start_time: Option<NaiveDateTime>,
+    /// Seconds of data time that pass per second of video. None means 1, which is the case unless the camera clock drifts.
+    #[serde(default)]
+    pub video_time_scale: Option<f64>,
+    #[serde(default)]
+    pub video_sync_points: Vec<VideoSyncPoint>,
+    #[serde(default)]
+    pub video_time_zone: Option<VideoTimeZone>,
+    #[serde(default)]
+    pub display_time_zone: Option<DisplayTimeZone>,
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+    #[serde(default)]
+    pub video_chapters: Vec<VideoChapter>,
+    #[serde(default)]
+    pub detection_rules: Vec<DetectionRule>,
+    #[serde(default)]
+    pub channel_filters: Vec<ChannelFilter>,
+    #[serde(default)]
+    pub transfer_settings: TransferSettings,
     #[serde(default)]
     pub is_multiwindow: IsMultiwindow,
     #[serde(default)]
@@ -125,6 +429,33 @@ pub enum AppStateField {
         #[serde(deserialize_with = "nullable_naive_datetime")]
         value: Option<NaiveDateTime>,
     },
+    VideoTimeScale {
+        value: Option<f64>,
+    },
+    VideoSyncPoints {
+        value: Vec<VideoSyncPoint>,
+    },
+    VideoTimeZone {
+        value: Option<VideoTimeZone>,
+    },
+    DisplayTimeZone {
+        value: Option<DisplayTimeZone>,
+    },
+    Annotations {
+        value: Vec<Annotation>,
+    },
+    VideoChapters {
+        value: Vec<VideoChapter>,
+    },
+    DetectionRules {
+        value: Vec<DetectionRule>,
+    },
+    ChannelFilters {
+        value: Vec<ChannelFilter>,
+    },
+    TransferSettings {
+        value: TransferSettings,
+    },
     IsMultiwindow {
         value: IsMultiwindow,
     },
@@ -145,13 +476,22 @@ impl AppState {
             AppStateField::LoadCsvSettings { value } => self.load_csv_settings = value,
             AppStateField::VideoFilePath { value } => self.video_file_path = value,
             AppStateField::VideoStartTime { value } => self.video_start_time = value,
+            AppStateField::VideoTimeScale { value } => self.video_time_scale = value,
+            AppStateField::VideoSyncPoints { value } => self.video_sync_points = value,
+            AppStateField::VideoTimeZone { value } => self.video_time_zone = value,
+            AppStateField::DisplayTimeZone { value } => self.display_time_zone = value,
+            AppStateField::Annotations { value } => self.annotations = value,
+            AppStateField::VideoChapters { value } => self.video_chapters = value,
+            AppStateField::DetectionRules { value } => self.detection_rules = value,
+            AppStateField::ChannelFilters { value } => self.channel_filters = value,
+            AppStateField::TransferSettings { value } => self.transfer_settings = value,
             AppStateField::IsMultiwindow { value } => self.is_multiwindow = value,
             AppStateField::IsModifiedSinceLastSave { value } => self.is_modified_since_last_save = value,
         }
     }
 
     /// Given an AppStateField variant, returns the same AppStateField variant with the value taken from the current AppState.
-    fn get_field(&self, field: AppStateField) -> AppStateField {
+    pub fn get_field(&self, field: AppStateField) -> AppStateField {
         match field {
             AppStateField::SaveFilePath { .. } => AppStateField::SaveFilePath {
                 value: self.save_file_path.clone(),
@@ -168,6 +508,33 @@ impl AppState {
             AppStateField::VideoStartTime { .. } => AppStateField::VideoStartTime {
                 value: self.video_start_time.clone(),
             },
+            AppStateField::VideoTimeScale { .. } => AppStateField::VideoTimeScale {
+                value: self.video_time_scale,
+            },
+            AppStateField::VideoSyncPoints { .. } => AppStateField::VideoSyncPoints {
+                value: self.video_sync_points.clone(),
+            },
+            AppStateField::VideoTimeZone { .. } => AppStateField::VideoTimeZone {
+                value: self.video_time_zone.clone(),
+            },
+            AppStateField::DisplayTimeZone { .. } => AppStateField::DisplayTimeZone {
+                value: self.display_time_zone.clone(),
+            },
+            AppStateField::Annotations { .. } => AppStateField::Annotations {
+                value: self.annotations.clone(),
+            },
+            AppStateField::VideoChapters { .. } => AppStateField::VideoChapters {
+                value: self.video_chapters.clone(),
+            },
+            AppStateField::DetectionRules { .. } => AppStateField::DetectionRules {
+                value: self.detection_rules.clone(),
+            },
+            AppStateField::ChannelFilters { .. } => AppStateField::ChannelFilters {
+                value: self.channel_filters.clone(),
+            },
+            AppStateField::TransferSettings { .. } => AppStateField::TransferSettings {
+                value: self.transfer_settings.clone(),
+            },
             AppStateField::IsMultiwindow { .. } => AppStateField::IsMultiwindow {
                 value: self.is_multiwindow.clone(),
             },
@@ -177,6 +544,107 @@ impl AppState {
         }
     }
 
+    /// Annotation ids just count up, so the next id is one more than the largest one in use.
+    pub fn next_annotation_id(&self) -> u64 {
+        self.annotations
+            .iter()
+            .map(|annotation| annotation.id + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Appends annotations that were created in bulk (imported, detected, etc) to a copy of the current annotations, giving each one a new id.
+    /// Any that cover the same time with the same title and category as an annotation already in the list are skipped, so that repeating the
+    /// same import doesn't double up. Returns the combined list and the number of annotations that were skipped.
+    pub fn merge_annotations(&self, new_annotations: impl IntoIterator<Item = Annotation>) -> (Vec<Annotation>, usize) {
+        let mut annotations = self.annotations.clone();
+        let mut next_id = self.next_annotation_id();
+        let mut skipped = 0;
+
+        for mut new_annotation in new_annotations {
+            let is_duplicate = annotations.iter().any(|annotation| {
+                annotation.start_time == new_annotation.start_time
+                    && annotation.end_time == new_annotation.end_time
+                    && annotation.title == new_annotation.title
+                    && annotation.category == new_annotation.category
+            });
+
+            if is_duplicate {
+                skipped += 1;
+                continue;
+            }
+
+            new_annotation.id = next_id;
+            next_id += 1;
+            annotations.push(new_annotation);
+        }
+
+        (annotations, skipped)
+    }
+
+    /// Turns a TimeWindow into a start and end time, looking up the annotation if needed. Annotations that are a single point in time have no
+    /// window to speak of, so they are an error.
+    pub fn resolve_time_window(&self, window: &TimeWindow) -> Result<(NaiveDateTime, NaiveDateTime), String> {
+        let (start_time, end_time) = match window {
+            TimeWindow::Range { start_time, end_time } => (*start_time, *end_time),
+            TimeWindow::Annotation { id } => {
+                let annotation = self
+                    .annotations
+                    .iter()
+                    .find(|annotation| annotation.id == *id)
+                    .ok_or(format!("There is no annotation with id {}.", id))?;
+
+                let end_time = annotation.end_time.ok_or(format!(
+                    "Annotation \"{}\" marks a single point in time, so it has no time range.",
+                    annotation.title
+                ))?;
+
+                (annotation.start_time, end_time)
+            }
+        };
+
+        if start_time > end_time {
+            return Err(format!("The window start time {} is after its end time {}.", start_time, end_time));
+        }
+
+        Ok((start_time, end_time))
+    }
+
+    /// The video start time converted to the display time zone, which is what needs to be lined up against the loaded data.
+    /// If the video has no time zone this is just the video start time.
+    pub fn display_video_start_time(&self) -> Result<Option<NaiveDateTime>, String> {
+        self.video_start_time
+            .map(|video_start_time| {
+                to_display_time(
+                    video_start_time,
+                    self.video_time_zone.as_ref().map(|time_zone| time_zone.as_ref()),
+                    self.display_time_zone.as_ref().map(|time_zone| time_zone.as_ref()),
+                )
+            })
+            .transpose()
+    }
+
+    /// Seconds of data time per second of video. Anything converting between video time and data time needs to use this.
+    pub fn video_time_scale(&self) -> f64 {
+        self.video_time_scale.unwrap_or(1.0)
+    }
+
+    /// The display time that a position in the video lines up with, or None if the video start time hasn't been set.
+    pub fn video_seconds_to_display_time(&self, video_seconds: f64) -> Result<Option<NaiveDateTime>, String> {
+        let data_milliseconds = (video_seconds * self.video_time_scale() * 1000.0).round() as i64;
+
+        Ok(self
+            .display_video_start_time()?
+            .map(|video_start_time| video_start_time + TimeDelta::milliseconds(data_milliseconds)))
+    }
+
+    /// The position in the video, in seconds, that a display time lines up with, or None if the video start time hasn't been set.
+    pub fn display_time_to_video_seconds(&self, time: NaiveDateTime) -> Result<Option<f64>, String> {
+        Ok(self.display_video_start_time()?.map(|video_start_time| {
+            (time - video_start_time).num_milliseconds() as f64 / 1000.0 / self.video_time_scale()
+        }))
+    }
+
     pub fn save_to_file(&self) -> Result<(), String> {
         if let Some(ref path) = self.save_file_path {
             let mut file =
@@ -214,19 +682,13 @@ impl AppState {
 
 /// This function sets the global app state when invoked from tauri
 /// app_state_field is a serialized variant of the AppStateField enum.
+/// The value is checked first (see validate_app_state_fields), and nothing is stored if it fails.
 #[tauri::command]
 pub async fn set_app_state_field<'a>(
     app: AppHandle,
-    state: State<'a, Mutex<AppState>>,
+    state: State<'a, RwLock<AppState>>,
     app_state_field: Value,
-) -> Result<(), String> {
-    let mut app_state = state.lock().map_err(|e| {
-        format!(
-            "Error locking app state in set_app_state: {}",
-            e.to_string()
-        )
-    })?;
-
+) -> Result<(), ValidationError> {
     let app_state_field: AppStateField =
         serde_json::from_value(app_state_field.clone()).map_err(|err| {
             format!(
@@ -235,24 +697,35 @@ pub async fn set_app_state_field<'a>(
             )
         })?;
 
-    let field_name = app_state_field.to_string();
-    app_state.set_field(app_state_field.clone());
-
-    // Note that the state is now modified
-    set_is_modified_since_last_save(&app, app_state, true)?;
+    validate_and_update_app_state_fields(&app, &state, vec![app_state_field])
+}
 
-    // We have no choice but to match on all variants to extract the value. Such is Rust...
-    match app_state_field {
-        AppStateField::SaveFilePath { value } => emit_app_state_update(&app, field_name, value)?,
-        AppStateField::CsvFilePath { value } => emit_app_state_update(&app, field_name, value)?,
-        AppStateField::LoadCsvSettings { value } => emit_app_state_update(&app, field_name, value)?,
-        AppStateField::VideoFilePath { value } => emit_app_state_update(&app, field_name, value)?,
-        AppStateField::VideoStartTime { value } => emit_app_state_update(&app, field_name, value)?,
-        AppStateField::IsMultiwindow { value } => emit_app_state_update(&app, field_name, value)?,
-        AppStateField::IsModifiedSinceLastSave { value } => emit_app_state_update(&app, field_name, value)?,
-    };
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Set App State Fields
+// ---------------------------------------------------------------------------------------------------------------------------------------------
 
-    Ok(())
+/// Sets several fields at once, for changes like a new CSV file along with its load settings where the in-between states aren't valid.
+/// Every field is deserialized and the resulting state is validated as a whole before anything is stored, so if any of them fails the state
+/// is left exactly as it was. Otherwise all the fields are stored under one lock and their events are emitted once, with one revision.
+#[tauri::command]
+pub async fn set_app_state_fields<'a>(
+    app: AppHandle,
+    state: State<'a, RwLock<AppState>>,
+    app_state_fields: Vec<Value>,
+) -> Result<(), ValidationError> {
+    let app_state_fields = app_state_fields
+        .into_iter()
+        .map(|app_state_field| {
+            serde_json::from_value::<AppStateField>(app_state_field.clone()).map_err(|err| {
+                format!(
+                    "Failed to deserialize field_value: {}\n\n Err: {}",
+                    app_state_field, err
+                )
+            })
+        })
+        .collect::<Result<Vec<AppStateField>, String>>()?;
+
+    validate_and_update_app_state_fields(&app, &state, app_state_fields)
 }
 
 // ---------------------------------------------------------------------------------------------------------------------------------------------
@@ -262,16 +735,9 @@ pub async fn set_app_state_field<'a>(
 /// This function retrieves a specific global app state field when invoked from Tauri
 #[tauri::command]
 pub async fn get_app_state_field<'a>(
-    state: State<'a, Mutex<AppState>>,
+    state: State<'a, RwLock<AppState>>,
     app_state_field: Value,
 ) -> Result<Value, String> {
-    let app_state = state.lock().map_err(|e| {
-        format!(
-            "Error locking app state in get_app_state: {}",
-            e.to_string()
-        )
-    })?;
-
     let app_state_field: AppStateField =
         serde_json::from_value(app_state_field.clone()).map_err(|err| {
             format!(
@@ -281,7 +747,7 @@ pub async fn get_app_state_field<'a>(
         })?;
 
     // Remember to actually update the app_state_field with the correct value from the AppState
-    let app_state_field = app_state.get_field(app_state_field);
+    let app_state_field = read_app_state(&state).get_field(app_state_field);
 
     let field_name = app_state_field.to_string();
 
@@ -291,27 +757,48 @@ pub async fn get_app_state_field<'a>(
         AppStateField::LoadCsvSettings { value } => Ok(to_json(value, field_name)?),
         AppStateField::VideoFilePath { value } => Ok(to_json(value, field_name)?),
         AppStateField::VideoStartTime { value } => Ok(to_json(value, field_name)?),
+        AppStateField::VideoTimeScale { value } => Ok(to_json(value, field_name)?),
+        AppStateField::VideoSyncPoints { value } => Ok(to_json(value, field_name)?),
+        AppStateField::VideoTimeZone { value } => Ok(to_json(value, field_name)?),
+        AppStateField::DisplayTimeZone { value } => Ok(to_json(value, field_name)?),
+        AppStateField::Annotations { value } => Ok(to_json(value, field_name)?),
+        AppStateField::VideoChapters { value } => Ok(to_json(value, field_name)?),
+        AppStateField::DetectionRules { value } => Ok(to_json(value, field_name)?),
+        AppStateField::ChannelFilters { value } => Ok(to_json(value, field_name)?),
+        AppStateField::TransferSettings { value } => Ok(to_json(value, field_name)?),
         AppStateField::IsMultiwindow { value } => Ok(to_json(value, field_name)?),
         AppStateField::IsModifiedSinceLastSave { value } => Ok(to_json(value, field_name)?),
     }
 }
 
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Get App State Snapshot
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Returns the whole AppState in one round trip, which is how a new window builds its state, along with its revision. Events with a
+/// revision at or below the snapshot's are already reflected in it.
+#[tauri::command]
+pub async fn get_app_state_snapshot<'a>(state: State<'a, RwLock<AppState>>) -> Result<AppStateSnapshot, String> {
+    let app_state = read_app_state(&state);
+
+    Ok(AppStateSnapshot {
+        revision: STATE_REVISION.load(Ordering::SeqCst),
+        state: app_state.clone(),
+    })
+}
+
 // ---------------------------------------------------------------------------------------------------------------------------------------------
 // Clear App State
 // ---------------------------------------------------------------------------------------------------------------------------------------------
 
 /// Clear the current settings and create a new one.
 #[tauri::command]
-pub async fn clear_app_state<'a>(app: AppHandle, state: State<'a, Mutex<AppState>>) -> Result<(), String> {
-    let mut app_state = state.lock().map_err(|e| {
-        format!(
-            "Error locking app state in clear_app_state: {}",
-            e.to_string()
-        )
-    })?;
+pub async fn clear_app_state<'a>(app: AppHandle, state: State<'a, RwLock<AppState>>) -> Result<(), String> {
+    let mut app_state = write_app_state(&state);
 
     // This automatically notes that the state is not modified
     *app_state = AppState::default();
+    mark_state_saved(&app_state)?;
 
     broadcast_complete_global_state_change(&app, app_state)?;
 
@@ -324,59 +811,296 @@ pub async fn clear_app_state<'a>(app: AppHandle, state: State<'a, Mutex<AppState
 
 /// If you want to save to a different file, update the file name in the global state first from a frontend set_app_state invocation.
 #[tauri::command]
-pub async fn save_app_state_to_file<'a>(app: AppHandle, state: State<'a, Mutex<AppState>>) -> Result<(), String> {
-    let app_state = state.lock().map_err(|e| {
-        format!(
-            "Error locking app state in save_app_state_to_file: {}",
-            e.to_string()
-        )
-    })?;
-
-    app_state.save_to_file()?;
-
-    // Note that the state has not been modified
-    set_is_modified_since_last_save(&app, app_state, false)?;
-
-    Ok(())
+pub async fn save_app_state_to_file<'a>(app: AppHandle, state: State<'a, RwLock<AppState>>) -> Result<(), String> {
+    save_app_state(&app, write_app_state(&state))
 }
 
 // ---------------------------------------------------------------------------------------------------------------------------------------------
 // Load App State From File
 // ---------------------------------------------------------------------------------------------------------------------------------------------
 
-/// Loads the app state and then emits to the front-end all the different events that happen.
+/// Loads the app state and then emits to the front-end all the different events that happen. The file is checked the same as the fields
+/// would be by set_app_state_fields, and nothing is loaded if anything in it is invalid.
 #[tauri::command]
 pub async fn load_app_state_from_file<'a>(
     app: AppHandle,
-    state: State<'a, Mutex<AppState>>,
+    state: State<'a, RwLock<AppState>>,
     file: SafePathBuf,
-) -> Result<(), String> {
-    let mut app_state: std::sync::MutexGuard<'_, AppState> = state.lock().map_err(|e| {
-        format!(
-            "Error locking app state in load_app_state_from_file: {}",
-            e.to_string()
-        )
-    })?;
+) -> Result<(), ValidationError> {
+    load_app_state(&app, &state, file)
+}
 
-    *app_state = AppState::load_from_file(file.as_ref())?;
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Utility Functions
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Load App State
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Replaces the AppState with the one in a .crm file and adds the file to the recent files, after validating every field in it. Shared by
+/// load_app_state_from_file and open_recent_session.
+pub fn load_app_state(app: &AppHandle, state: &RwLock<AppState>, file: SafePathBuf) -> Result<(), ValidationError> {
+    // Read and check the file before taking the lock, so a slow or broken file doesn't hold up every other command
+    let mut loaded_app_state = AppState::load_from_file(file.as_ref())?;
 
     // Overwrite the file path just in case the user loaded a .crm file that had an out-of-date save file path on it.
-    app_state.save_file_path = Some(file.into());
+    loaded_app_state.save_file_path = Some(file.clone().into());
+
+    validate_app_state(app, &loaded_app_state)?;
+
+    let mut app_state = write_app_state(state);
+
+    *app_state = loaded_app_state;
 
     // Note that the state has not been modified
     app_state.set_field(AppStateField::IsModifiedSinceLastSave { value: IsModifiedSinceLastSave::from(false) });
+    mark_state_saved(&app_state)?;
 
-    broadcast_complete_global_state_change(&app, app_state)?;
+    broadcast_complete_global_state_change(app, app_state)?;
+
+    remember_recent_file(app, RecentFileKind::Session, file.as_ref());
 
     Ok(())
 }
 
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Read and Write App State
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Locks the AppState for reading. Any number of commands can read at once, so only hold this for as long as it takes to look at the state.
+/// Anything slow (loading a CSV, writing a file) should work from a snapshot_app_state instead.
+///
+/// If a command panicked while holding the lock it is poisoned. The fields of the AppState are only ever replaced whole, so the state is
+/// still usable and the poison is cleared instead of failing every command from then on.
+pub fn read_app_state(state: &RwLock<AppState>) -> RwLockReadGuard<'_, AppState> {
+    state.read().unwrap_or_else(|poisoned| {
+        state.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+/// Locks the AppState for writing, blocking every other command until the guard is dropped. Poisoning is recovered from the same as in
+/// read_app_state.
+pub fn write_app_state(state: &RwLock<AppState>) -> RwLockWriteGuard<'_, AppState> {
+    state.write().unwrap_or_else(|poisoned| {
+        state.clear_poison();
+        poisoned.into_inner()
+    })
+}
 
-// #############################################################################################################################################
-// #############################################################################################################################################
-// Utility Functions
-// #############################################################################################################################################
-// #############################################################################################################################################
+/// A copy of the AppState, taken under a read lock that is released straight away. Long running commands work from this so that
+/// set_app_state_field and get_app_state_field from other windows aren't stuck behind them. Nothing written to the copy is kept, changes
+/// have to go back through write_app_state.
+pub fn snapshot_app_state(state: &RwLock<AppState>) -> AppState {
+    read_app_state(state).clone()
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Update App State Field
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Stores a field in the AppState, works out whether the state now differs from the last save, and emits the change to every window.
+/// Backend commands that change the state should go through this, the same as set_app_state_field does.
+pub fn update_app_state_field(
+    app: &AppHandle,
+    app_state: RwLockWriteGuard<'_, AppState>,
+    app_state_field: AppStateField,
+) -> Result<(), String> {
+    update_app_state_fields(app, app_state, vec![app_state_field])
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Update App State Fields
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The same as update_app_state_field, for fields that have to change together. All of the fields are stored before any are emitted, and
+/// they are all emitted with the same revision. The events go out before the lock is released so that they are sent in revision order.
+pub fn update_app_state_fields(
+    app: &AppHandle,
+    mut app_state: RwLockWriteGuard<'_, AppState>,
+    app_state_fields: Vec<AppStateField>,
+) -> Result<(), String> {
+    for app_state_field in &app_state_fields {
+        app_state.set_field(app_state_field.clone());
+    }
+
+    // Setting the flag directly overrides the comparison, and setting it to false accepts the current state as saved
+    let is_modified = match app_state_fields.iter().find_map(|field| match field {
+        AppStateField::IsModifiedSinceLastSave { value } => Some(bool::from(value.clone())),
+        _ => None,
+    }) {
+        Some(false) => {
+            mark_state_saved(&app_state)?;
+            false
+        }
+        Some(true) => true,
+        None => persistent_state_hash(&app_state)? != saved_state_hash()?,
+    };
+
+    let revision = next_state_revision();
+
+    // Picked up before the fields are emitted, and added to the recent files once the lock has been released
+    let recent_files: Vec<(RecentFileKind, PathBuf)> = app_state_fields
+        .iter()
+        .filter_map(|field| match field {
+            AppStateField::CsvFilePath { value: Some(path) } => Some((RecentFileKind::Csv, path.as_ref().to_path_buf())),
+            AppStateField::VideoFilePath { value: Some(path) } => Some((RecentFileKind::Video, path.as_ref().to_path_buf())),
+            _ => None,
+        })
+        .collect();
+
+    for app_state_field in app_state_fields {
+        // The flag is emitted below with whatever it ends up as
+        if !matches!(app_state_field, AppStateField::IsModifiedSinceLastSave { .. }) {
+            emit_app_state_field(app, app_state_field, revision)?;
+        }
+    }
+
+    set_is_modified_since_last_save(app, app_state, is_modified, revision)?;
+
+    for (kind, path) in recent_files {
+        remember_recent_file(app, kind, &path);
+    }
+
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Validate And Update App State Fields
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Validates fields against a copy of the AppState with all of them applied, so nothing has to be undone if the combination doesn't hold
+/// together, and then stores them with update_app_state_fields. Validation can read the file system, so it's first done on a copy taken
+/// under the read lock. If another window changed the state between then and taking the write lock, the fields are validated again against
+/// the state as it is under the write lock, so what gets stored is always exactly what was validated.
+fn validate_and_update_app_state_fields(
+    app: &AppHandle,
+    state: &RwLock<AppState>,
+    app_state_fields: Vec<AppStateField>,
+) -> Result<(), ValidationError> {
+    let with_fields = |app_state: &AppState| {
+        let mut candidate = app_state.clone();
+        for app_state_field in &app_state_fields {
+            candidate.set_field(app_state_field.clone());
+        }
+        candidate
+    };
+
+    let (candidate, revision) = {
+        let app_state = read_app_state(state);
+        (with_fields(&app_state), STATE_REVISION.load(Ordering::SeqCst))
+    };
+    validate_app_state_fields(app, &candidate, &app_state_fields)?;
+
+    let app_state = write_app_state(state);
+
+    // The schema and file checks are usually cached by now, so checking again under the lock is quick
+    if STATE_REVISION.load(Ordering::SeqCst) != revision {
+        validate_app_state_fields(app, &with_fields(&app_state), &app_state_fields)?;
+    }
+
+    Ok(update_app_state_fields(app, app_state, app_state_fields)?)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Save App State
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Writes the AppState to its save file, notes that it's no longer modified, and adds the save file to the recent files. Shared by
+/// save_app_state_to_file and the prompt shown when closing the main window with unsaved changes.
+pub fn save_app_state(app: &AppHandle, app_state: RwLockWriteGuard<'_, AppState>) -> Result<(), String> {
+    app_state.save_to_file()?;
+    mark_state_saved(&app_state)?;
+
+    let save_file_path = app_state.save_file_path.clone();
+
+    // Note that the state has not been modified
+    let revision = next_state_revision();
+    set_is_modified_since_last_save(app, app_state, false, revision)?;
+
+    if let Some(save_file_path) = save_file_path {
+        remember_recent_file(app, RecentFileKind::Session, save_file_path.as_ref());
+    }
+
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Persistent State Hash
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Hash of everything in the AppState that gets saved, so the state can be compared with the last save without keeping a copy of it.
+/// is_modified_since_last_save is left out since it's what the comparison decides. Keys are sorted first because the HashMap fields
+/// serialize in no particular order.
+fn persistent_state_hash(app_state: &AppState) -> Result<u64, String> {
+    let mut value = serde_json::to_value(app_state).map_err(|err| format!("Failed to serialize the app state: {}", err))?;
+
+    if let Value::Object(ref mut fields) = value {
+        fields.remove("is_modified_since_last_save");
+    }
+    value.sort_all_objects();
+
+    let mut hasher = DefaultHasher::new();
+    value.to_string().hash(&mut hasher);
+    Ok(hasher.finish())
+}
+
+/// Remembers the AppState as the saved version that later changes are compared against. Only call this while holding the write lock.
+fn mark_state_saved(app_state: &AppState) -> Result<(), String> {
+    let hash = persistent_state_hash(app_state)?;
+    *SAVED_STATE_HASH.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(hash);
+    Ok(())
+}
+
+fn saved_state_hash() -> Result<u64, String> {
+    match *SAVED_STATE_HASH.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) {
+        Some(hash) => Ok(hash),
+        None => persistent_state_hash(&AppState::default()),
+    }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Next State Revision
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Bumps the STATE_REVISION for a change and returns the new revision. Only call this while holding the write lock.
+fn next_state_revision() -> u64 {
+    STATE_REVISION.fetch_add(1, Ordering::SeqCst) + 1
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Emit App State Field
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+fn emit_app_state_field(app: &AppHandle, app_state_field: AppStateField, revision: u64) -> Result<(), String> {
+    let field_name = app_state_field.to_string();
+
+    // We have no choice but to match on all variants to extract the value. Such is Rust...
+    match app_state_field {
+        AppStateField::SaveFilePath { value } => emit_app_state_update(app, field_name, revision, value)?,
+        AppStateField::CsvFilePath { value } => emit_app_state_update(app, field_name, revision, value)?,
+        AppStateField::LoadCsvSettings { value } => emit_app_state_update(app, field_name, revision, value)?,
+        AppStateField::VideoFilePath { value } => emit_app_state_update(app, field_name, revision, value)?,
+        AppStateField::VideoStartTime { value } => emit_app_state_update(app, field_name, revision, value)?,
+        AppStateField::VideoTimeScale { value } => emit_app_state_update(app, field_name, revision, value)?,
+        AppStateField::VideoSyncPoints { value } => emit_app_state_update(app, field_name, revision, value)?,
+        AppStateField::VideoTimeZone { value } => emit_app_state_update(app, field_name, revision, value)?,
+        AppStateField::DisplayTimeZone { value } => emit_app_state_update(app, field_name, revision, value)?,
+        AppStateField::Annotations { value } => emit_app_state_update(app, field_name, revision, value)?,
+        AppStateField::VideoChapters { value } => emit_app_state_update(app, field_name, revision, value)?,
+        AppStateField::DetectionRules { value } => emit_app_state_update(app, field_name, revision, value)?,
+        AppStateField::ChannelFilters { value } => emit_app_state_update(app, field_name, revision, value)?,
+        AppStateField::TransferSettings { value } => emit_app_state_update(app, field_name, revision, value)?,
+        AppStateField::IsMultiwindow { value } => emit_app_state_update(app, field_name, revision, value)?,
+        AppStateField::IsModifiedSinceLastSave { value } => emit_app_state_update(app, field_name, revision, value)?,
+    };
+
+    Ok(())
+}
 
 // ---------------------------------------------------------------------------------------------------------------------------------------------
 // Emit App State Update
@@ -386,9 +1110,10 @@ pub async fn load_app_state_from_file<'a>(
 fn emit_app_state_update<T: Serialize + Clone>(
     app: &AppHandle,
     field_name: String,
-    event_payload: T,
+    revision: u64,
+    value: T,
 ) -> Result<(), String> {
-    app.emit(&format!("state-change--{}", field_name), event_payload)
+    app.emit(&format!("state-change--{}", field_name), StateChange { revision, value })
         .map_err(|err| format!("Function set_app_state in global_state.rs -- failed to emit state update event for {}: {}", field_name, err))?;
     Ok(())
 }
@@ -416,12 +1141,34 @@ where
     }
 }
 
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Naive Datetime Parser
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The same as nullable_naive_datetime, for datetimes that are required.
+fn naive_datetime<'de, D>(deserializer: D) -> Result<NaiveDateTime, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    nullable_naive_datetime(deserializer)?.ok_or_else(|| serde::de::Error::custom("datetime is required"))
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Default True
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Serde's default attribute needs a function, and there isn't one for true built in.
+fn default_true() -> bool {
+    true
+}
+
 // ---------------------------------------------------------------------------------------------------------------------------------------------
 // Broadcast Complete Global State Change
 // ---------------------------------------------------------------------------------------------------------------------------------------------
 
 /// Utility function to broadcast that the whole global state changed and the frontend needs to be refreshed. 
-fn broadcast_complete_global_state_change(app: &AppHandle, app_state: MutexGuard<'_, AppState>) -> Result<(), String> {
+fn broadcast_complete_global_state_change(app: &AppHandle, app_state: RwLockWriteGuard<'_, AppState>) -> Result<(), String> {
+    let revision = next_state_revision();
 
     for default_app_state_field in AppStateField::iter() {
         // That iterator gives a default implementation of that enum, so grab the real value from state
@@ -431,25 +1178,52 @@ fn broadcast_complete_global_state_change(app: &AppHandle, app_state: MutexGuard
 
         match true_app_state_field {
             AppStateField::SaveFilePath { value } => {
-                emit_app_state_update(app, field_name, value)?
+                emit_app_state_update(app, field_name, revision, value)?
             }
             AppStateField::CsvFilePath { value } => {
-                emit_app_state_update(app, field_name, value)?
+                emit_app_state_update(app, field_name, revision, value)?
             }
             AppStateField::LoadCsvSettings { value } => {
-                emit_app_state_update(app, field_name, value)?
+                emit_app_state_update(app, field_name, revision, value)?
             }
             AppStateField::VideoFilePath { value } => {
-                emit_app_state_update(app, field_name, value)?
+                emit_app_state_update(app, field_name, revision, value)?
             }
             AppStateField::VideoStartTime { value } => {
-                emit_app_state_update(app, field_name, value)?
+                emit_app_state_update(app, field_name, revision, value)?
+            }
+            AppStateField::VideoTimeScale { value } => {
+                emit_app_state_update(app, field_name, revision, value)?
+            }
+            AppStateField::VideoSyncPoints { value } => {
+                emit_app_state_update(app, field_name, revision, value)?
+            }
+            AppStateField::VideoTimeZone { value } => {
+                emit_app_state_update(app, field_name, revision, value)?
+            }
+            AppStateField::DisplayTimeZone { value } => {
+                emit_app_state_update(app, field_name, revision, value)?
+            }
+            AppStateField::Annotations { value } => {
+                emit_app_state_update(app, field_name, revision, value)?
+            }
+            AppStateField::VideoChapters { value } => {
+                emit_app_state_update(app, field_name, revision, value)?
+            }
+            AppStateField::DetectionRules { value } => {
+                emit_app_state_update(app, field_name, revision, value)?
+            }
+            AppStateField::ChannelFilters { value } => {
+                emit_app_state_update(app, field_name, revision, value)?
+            }
+            AppStateField::TransferSettings { value } => {
+                emit_app_state_update(app, field_name, revision, value)?
             }
             AppStateField::IsMultiwindow { value } => {
-                emit_app_state_update(app, field_name, value)?
+                emit_app_state_update(app, field_name, revision, value)?
             }
             AppStateField::IsModifiedSinceLastSave { value } => {
-                emit_app_state_update(app, field_name, value)?
+                emit_app_state_update(app, field_name, revision, value)?
             }
         };
     }
@@ -477,9 +1251,9 @@ fn to_json<T: Serialize>(payload: T, field_name: String) -> Result<Value, String
 // ---------------------------------------------------------------------------------------------------------------------------------------------
 
 /// Used to set and emit if the app state has been modified since the last save
-fn set_is_modified_since_last_save(app: &AppHandle, mut app_state: MutexGuard<'_, AppState>, value: bool) -> Result<(), String> {
-    app_state.set_field(AppStateField::IsModifiedSinceLastSave { value: IsModifiedSinceLastSave::from(true) });
-    emit_app_state_update(&app, AppStateField::IsModifiedSinceLastSave{ value: IsModifiedSinceLastSave::from(value) }.to_string() , value)?; // The value within the AppStateField doesn't matter
+fn set_is_modified_since_last_save(app: &AppHandle, mut app_state: RwLockWriteGuard<'_, AppState>, value: bool, revision: u64) -> Result<(), String> {
+    app_state.set_field(AppStateField::IsModifiedSinceLastSave { value: IsModifiedSinceLastSave::from(value) });
+    emit_app_state_update(&app, AppStateField::IsModifiedSinceLastSave{ value: IsModifiedSinceLastSave::from(value) }.to_string() , revision, value)?; // The value within the AppStateField doesn't matter
     Ok(())
 }
 
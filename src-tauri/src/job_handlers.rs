@@ -0,0 +1,209 @@
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tauri::{async_runtime, AppHandle, Emitter, Manager, State};
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Structs
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+/// Every job that is still running, by id. Managed by Tauri alongside the AppState.
+#[derive(Default)]
+pub struct JobRegistry {
+    next_id: AtomicU64,
+    /// Calling the function cancels the job
+    running: Mutex<HashMap<u64, Box<dyn Fn() + Send + Sync>>>,
+}
+
+/// Payload of the "job-progress" event. One is sent with a percent of 0 as soon as a job starts, which is how the frontend learns the id it
+/// needs to cancel it.
+#[derive(Serialize, Clone, Debug)]
+pub struct JobProgress {
+    job_id: u64,
+    /// Name of the command that started the job
+    kind: String,
+    message: String,
+    /// None while there's no way to tell how far along the job is, e.g. while Polars is parsing a CSV
+    percent: Option<f64>,
+    processed_rows: Option<usize>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Completed,
+    Cancelled,
+    Failed,
+}
+
+/// Payload of the "job-finished" event
+#[derive(Serialize, Clone, Debug)]
+pub struct JobFinished {
+    job_id: u64,
+    kind: String,
+    status: JobStatus,
+    error: Option<String>,
+}
+
+/// Handed to the work a job does, so it can report progress and notice when it has been cancelled.
+pub struct Job {
+    id: u64,
+    kind: String,
+    app: AppHandle,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Job {
+    /// Sends a "job-progress" event. A failed emit only costs the progress bar an update, so it isn't an error.
+    pub fn progress(&self, message: &str, percent: Option<f64>, processed_rows: Option<usize>) {
+        let _ = self.app.emit(
+            "job-progress",
+            JobProgress {
+                job_id: self.id,
+                kind: self.kind.clone(),
+                message: message.to_string(),
+                percent,
+                processed_rows,
+            },
+        );
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The app that started the job, for work that needs Tauri itself, e.g. to run a sidecar.
+    pub fn app(&self) -> &AppHandle {
+        &self.app
+    }
+
+    /// Call between the steps of a job. Returns an error once the job has been cancelled, so that `?` stops the work there.
+    pub fn checkpoint(&self) -> Result<(), String> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            Err(format!("Job {} was cancelled.", self.id))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Tauri Commands
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Cancel Job
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Cancels a running job. The command that started it returns an error straight away, and the work itself stops at its next checkpoint.
+/// Returns false if there is no job with that id, which usually means it finished before it could be cancelled.
+#[tauri::command]
+pub async fn cancel_job(registry: State<'_, JobRegistry>, job_id: u64) -> Result<bool, String> {
+    let running = registry.running.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    match running.get(&job_id) {
+        Some(cancel) => {
+            cancel();
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Utility Functions
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Run Job
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Runs blocking work (loading a CSV, writing an export, number crunching) on Tauri's blocking thread pool as a cancellable job, and waits
+/// for it. A "job-progress" event is sent when it starts and whenever the work reports progress, and a "job-finished" event when it ends,
+/// however it ends. The work has to own everything it uses, so commands take a snapshot_app_state before starting a job.
+pub async fn run_job<T, F>(app: &AppHandle, kind: &str, work: F) -> Result<T, String>
+where
+    T: Send + 'static,
+    F: FnOnce(&Job) -> Result<T, String> + Send + 'static,
+{
+    let registry = app.state::<JobRegistry>();
+    let job_id = registry.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+    let cancelled = Arc::new(AtomicBool::new(false));
+
+    // The work sends Some(result) when it's done and cancelling sends None, so whichever happens first is what the command returns
+    let (sender, mut receiver) = async_runtime::channel::<Option<Result<T, String>>>(2);
+
+    {
+        let cancelled = cancelled.clone();
+        let sender = sender.clone();
+        registry
+            .running
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(
+                job_id,
+                Box::new(move || {
+                    cancelled.store(true, Ordering::Relaxed);
+                    let _ = sender.try_send(None);
+                }),
+            );
+    }
+
+    let job = Job {
+        id: job_id,
+        kind: kind.to_string(),
+        app: app.clone(),
+        cancelled: cancelled.clone(),
+    };
+    job.progress("Started", Some(0.0), None);
+
+    async_runtime::spawn_blocking(move || {
+        // A panic would otherwise leave the command waiting forever, since the registry still holds a sender
+        let result = catch_unwind(AssertUnwindSafe(|| work(&job)))
+            .unwrap_or_else(|_| Err(format!("Job {} ({}) crashed.", job.id, job.kind)));
+        let _ = sender.blocking_send(Some(result));
+    });
+
+    let result = receiver
+        .recv()
+        .await
+        .flatten()
+        .unwrap_or_else(|| Err(format!("Job {} was cancelled.", job_id)));
+
+    registry
+        .running
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .remove(&job_id);
+
+    let (status, error) = match result {
+        Ok(_) => (JobStatus::Completed, None),
+        Err(ref error) if cancelled.load(Ordering::Relaxed) => (JobStatus::Cancelled, Some(error.clone())),
+        Err(ref error) => (JobStatus::Failed, Some(error.clone())),
+    };
+
+    app.emit(
+        "job-finished",
+        JobFinished {
+            job_id,
+            kind: kind.to_string(),
+            status,
+            error,
+        },
+    )
+    .map_err(|e| format!("Failed to emit event: {:?}", e))?;
+
+    result
+}
@@ -1,36 +1,183 @@
+mod alignment_handlers;
+mod annotation_handlers;
+mod channel_filters;
 mod dataframe_handlers;
+mod detection_handlers;
+mod export_handlers;
 mod global_state;
+mod job_handlers;
+mod mp4_clip;
+mod spectral_handlers;
+mod state_validation;
+mod statistics_handlers;
+mod time_zones;
+mod transfer_encoding;
+mod user_preferences;
 mod video_handlers;
+mod video_overlay;
+mod viewport_handlers;
 
-use dataframe_handlers::{get_csv_data, get_csv_schema};
+use alignment_handlers::find_alignment_offset;
+use annotation_handlers::import_event_log;
+use dataframe_handlers::{get_csv_data, get_csv_schema, stream_csv_data, CsvSchemaCache, LoadedDataCache};
+use detection_handlers::run_detection_rules;
+use export_handlers::{export_annotation_report, export_data};
 use global_state::{
-    clear_app_state, get_app_state_field, load_app_state_from_file, save_app_state_to_file,
-    set_app_state_field, AppState,
+    clear_app_state, get_app_state_field, get_app_state_snapshot, load_app_state_from_file, read_app_state,
+    save_app_state, save_app_state_to_file, set_app_state_field, set_app_state_fields, update_app_state_field,
+    write_app_state, AppState, AppStateField,
 };
-use std::sync::Mutex;
-use tauri::Manager;
-use video_handlers::emit_video_time_change;
+use job_handlers::{cancel_job, JobRegistry};
+use spectral_handlers::get_spectrum;
+use statistics_handlers::{get_column_statistics, get_window_statistics};
+use std::sync::RwLock;
+use tauri::{path::SafePathBuf, AppHandle, Manager, Window, WindowEvent};
+use tauri_plugin_dialog::{DialogExt, MessageDialogButtons, MessageDialogKind, MessageDialogResult};
+use user_preferences::{get_user_preferences, open_recent_session, update_user_preferences, UserPreferencesStore};
+use video_handlers::{
+    emit_video_time_change, export_overlay_video, export_video_clip, get_display_video_start_time,
+    set_video_sync_points,
+};
+use viewport_handlers::get_viewport_data;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_fs::init())
         .setup(|app| {
-            app.manage(Mutex::new(AppState::default()));
+            app.manage(RwLock::new(AppState::default()));
+            app.manage(JobRegistry::default());
+            app.manage(LoadedDataCache::default());
+            app.manage(CsvSchemaCache::default());
+            app.manage(UserPreferencesStore::load(app.app_handle()));
             Ok(())
         })
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_shell::init())
+        .on_window_event(|window, event| {
+            // Closing the main window ends the session, so give the user a chance to save first
+            if let WindowEvent::CloseRequested { api, .. } = event {
+                if window.label() == "main" && has_unsaved_changes(window.app_handle()) {
+                    api.prevent_close();
+                    prompt_to_save_before_close(window.clone());
+                }
+            }
+        })
         .invoke_handler(tauri::generate_handler![
             get_csv_schema,
             get_csv_data,
+            stream_csv_data,
+            get_viewport_data,
+            get_column_statistics,
+            get_window_statistics,
+            get_spectrum,
+            find_alignment_offset,
+            export_data,
+            export_annotation_report,
+            import_event_log,
+            run_detection_rules,
             emit_video_time_change,
+            get_display_video_start_time,
+            set_video_sync_points,
+            export_video_clip,
+            export_overlay_video,
             set_app_state_field,
+            set_app_state_fields,
             get_app_state_field,
+            get_app_state_snapshot,
             save_app_state_to_file,
             load_app_state_from_file,
             clear_app_state,
+            get_user_preferences,
+            update_user_preferences,
+            open_recent_session,
+            cancel_job,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Save Before Close
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+fn has_unsaved_changes(app: &AppHandle) -> bool {
+    read_app_state(&app.state::<RwLock<AppState>>())
+        .is_modified_since_last_save
+        .clone()
+        .into()
+}
+
+/// Asks whether to save before the window closes. Cancelling, or failing to save, leaves the window open.
+fn prompt_to_save_before_close(window: Window) {
+    window
+        .app_handle()
+        .dialog()
+        .message("There are unsaved changes. Do you want to save them before closing?")
+        .title("Unsaved Changes")
+        .kind(MessageDialogKind::Warning)
+        .buttons(MessageDialogButtons::YesNoCancelCustom(
+            "Save".to_string(),
+            "Don't Save".to_string(),
+            "Cancel".to_string(),
+        ))
+        .show_with_result(move |result| match result {
+            MessageDialogResult::Yes => save_then_close(window),
+            MessageDialogResult::Custom(label) if label == "Save" => save_then_close(window),
+            MessageDialogResult::No => close(&window),
+            MessageDialogResult::Custom(label) if label == "Don't Save" => close(&window),
+            _ => {}
+        });
+}
+
+/// Saves to the save file, asking where to put it first if the state has never been saved
+fn save_then_close(window: Window) {
+    let app = window.app_handle().clone();
+
+    if read_app_state(&app.state::<RwLock<AppState>>()).save_file_path.is_some() {
+        match save_app_state(&app, write_app_state(&app.state::<RwLock<AppState>>())) {
+            Ok(()) => close(&window),
+            Err(err) => show_save_error(&app, err),
+        }
+        return;
+    }
+
+    app.clone()
+        .dialog()
+        .file()
+        .add_filter("Chronolab Save File", &["crm"])
+        .save_file(move |file_path| {
+            let Some(path) = file_path.and_then(|file_path| file_path.into_path().ok()) else {
+                return;
+            };
+
+            let result = SafePathBuf::new(path)
+                .map_err(|err| err.to_string())
+                .and_then(|path| {
+                    update_app_state_field(
+                        &app,
+                        write_app_state(&app.state::<RwLock<AppState>>()),
+                        AppStateField::SaveFilePath { value: Some(path.into()) },
+                    )
+                })
+                .and_then(|()| save_app_state(&app, write_app_state(&app.state::<RwLock<AppState>>())));
+
+            match result {
+                Ok(()) => close(&window),
+                Err(err) => show_save_error(&app, err),
+            }
+        });
+}
+
+/// Closes the window without asking again. If that fails the window just stays open, and closing it again brings the prompt back.
+fn close(window: &Window) {
+    let _ = window.destroy();
+}
+
+fn show_save_error(app: &AppHandle, err: String) {
+    app.dialog()
+        .message(format!("The app state could not be saved, so the window was left open.\n\n{}", err))
+        .title("Save Failed")
+        .kind(MessageDialogKind::Error)
+        .show(|_| {});
+}
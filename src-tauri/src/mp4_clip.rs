@@ -0,0 +1,1177 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// MP4 Clip Extraction
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// Cuts a section out of an MP4 (or MOV) file without re-encoding anything. The sample tables of each track are read out of the moov box,
+// the samples covering the requested range are picked out, and a new file is written with a rebuilt moov box (at the front, so it can be
+// streamed) followed by one mdat holding the copied sample data.
+//
+// Video can only start on a keyframe, so the clip starts at the last keyframe at or before the requested start. It can end on any frame,
+// since in decode order every frame's references come before it. Other tracks (audio, GPS metadata, etc) are cut to match the video, and
+// edit lists are written so that every track starts playing at the chosen keyframe.
+//
+// Fragmented MP4s (the kind written for live streaming) store their sample tables differently and are not supported.
+
+/// The range of the source video, in seconds, that ended up in the clip.
+#[derive(Debug)]
+pub struct ClipBounds {
+    pub start_seconds: f64,
+    pub end_seconds: f64,
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Extract Clip
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Writes the part of the input video between start_seconds and end_seconds (measured from the start of the video) to the output path.
+pub fn extract_clip(
+    input_path: &Path,
+    output_path: &Path,
+    start_seconds: f64,
+    end_seconds: f64,
+) -> Result<ClipBounds, String> {
+    if !start_seconds.is_finite() || !end_seconds.is_finite() || start_seconds >= end_seconds {
+        return Err(format!(
+            "The clip start ({}s) has to be before the clip end ({}s).",
+            start_seconds, end_seconds
+        ));
+    }
+
+    let mut input = File::open(input_path).map_err(|e| format!("Failed to open video: {}", e))?;
+    let (ftyp, moov) = read_header_boxes(&mut input)?;
+
+    let moov_children = parse_boxes(&moov)?;
+
+    if moov_children.iter().any(|child| &child.kind == b"mvex") {
+        return Err("Fragmented MP4 files can't be clipped yet.".to_string());
+    }
+
+    let mvhd = moov_children
+        .iter()
+        .find(|child| &child.kind == b"mvhd")
+        .ok_or("Video is missing its movie header (mvhd box).")?;
+    let movie_timescale = read_timescale(mvhd)?;
+
+    let tracks = moov_children
+        .iter()
+        .filter(|child| &child.kind == b"trak")
+        .map(|trak| Track::parse(trak, movie_timescale))
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let video_index = tracks
+        .iter()
+        .position(|track| &track.handler == b"vide")
+        .ok_or("Video doesn't have a video track.")?;
+
+    // Pick the video samples first, since they decide where every other track is cut
+    let video = &tracks[video_index];
+    let (video_start, video_end) = video.select_from_keyframe(start_seconds, end_seconds)?;
+    let clip_start_seconds = video.presentation_time(video_start) as f64 / video.timescale as f64;
+    let clip_end_seconds = (video_start..=video_end)
+        .map(|i| video.presentation_time(i) + video.samples[i].duration as i64)
+        .max()
+        .unwrap_or_default() as f64
+        / video.timescale as f64;
+
+    let mut clip_tracks = Vec::new();
+    for (index, track) in tracks.iter().enumerate() {
+        let range = if index == video_index {
+            Some((video_start, video_end))
+        } else {
+            track.select_overlapping(clip_start_seconds, clip_end_seconds)
+        };
+
+        // Tracks with nothing in the clip (a timecode track with one sample for the whole video, for example) are left out
+        if let Some(range) = range {
+            clip_tracks.push(ClipTrack::new(index, track, range, clip_start_seconds, clip_end_seconds));
+        }
+    }
+
+    let clip = Clip {
+        ftyp: &ftyp,
+        mvhd: mvhd.raw,
+        udta: find_box(&moov_children, b"udta").map(|udta| udta.raw),
+        movie_timescale,
+        tracks: &tracks,
+        clip_tracks: &clip_tracks,
+    };
+
+    write_clip(&mut input, output_path, &clip)?;
+
+    Ok(ClipBounds {
+        start_seconds: clip_start_seconds,
+        end_seconds: clip_end_seconds,
+    })
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Reading
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+/// A box inside a buffer that has already been read into memory.
+struct Mp4Box<'a> {
+    kind: [u8; 4],
+    /// The whole box, header included
+    raw: &'a [u8],
+    /// Everything after the header
+    payload: &'a [u8],
+}
+
+/// Splits a buffer into the boxes it contains.
+fn parse_boxes(bytes: &[u8]) -> Result<Vec<Mp4Box<'_>>, String> {
+    let mut boxes = Vec::new();
+    let mut position = 0;
+
+    while position + 8 <= bytes.len() {
+        let size = read_u32(bytes, position)? as u64;
+        let kind: [u8; 4] = bytes[position + 4..position + 8].try_into().unwrap();
+
+        let (size, header_len) = match size {
+            0 => ((bytes.len() - position) as u64, 8),
+            1 => (read_u64(bytes, position + 8)?, 16),
+            _ => (size, 8),
+        };
+
+        let end = position
+            .checked_add(size as usize)
+            .filter(|end| *end <= bytes.len() && size >= header_len as u64)
+            .ok_or_else(|| format!("The {} box in the video is corrupt.", String::from_utf8_lossy(&kind)))?;
+
+        boxes.push(Mp4Box {
+            kind,
+            raw: &bytes[position..end],
+            payload: &bytes[position + header_len..end],
+        });
+
+        position = end;
+    }
+
+    Ok(boxes)
+}
+
+fn find_box<'a, 'b>(boxes: &'b [Mp4Box<'a>], kind: &[u8; 4]) -> Option<&'b Mp4Box<'a>> {
+    boxes.iter().find(|child| &child.kind == kind)
+}
+
+fn require_box<'a, 'b>(boxes: &'b [Mp4Box<'a>], kind: &[u8; 4]) -> Result<&'b Mp4Box<'a>, String> {
+    find_box(boxes, kind).ok_or_else(|| format!("Video is missing a {} box.", String::from_utf8_lossy(kind)))
+}
+
+/// Reads the ftyp and moov boxes from the top level of the file, skipping over everything else (mdat is usually most of the file).
+fn read_header_boxes(input: &mut File) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let file_len = input
+        .metadata()
+        .map_err(|e| format!("Failed to read video: {}", e))?
+        .len();
+
+    let mut ftyp = None;
+    let mut moov = None;
+    let mut position = 0;
+
+    while position + 8 <= file_len && (ftyp.is_none() || moov.is_none()) {
+        let mut header = [0u8; 16];
+        input
+            .seek(SeekFrom::Start(position))
+            .and_then(|_| input.read_exact(&mut header[..8]))
+            .map_err(|e| format!("Failed to read video: {}", e))?;
+
+        let kind: [u8; 4] = header[4..8].try_into().unwrap();
+        let size = match read_u32(&header, 0)? as u64 {
+            0 => file_len - position,
+            1 => {
+                input
+                    .read_exact(&mut header[8..16])
+                    .map_err(|e| format!("Failed to read video: {}", e))?;
+                read_u64(&header, 8)?
+            }
+            size => size,
+        };
+
+        if size < 8 || position.checked_add(size).filter(|end| *end <= file_len).is_none() {
+            return Err("The video doesn't look like an MP4 file, or it is corrupt.".to_string());
+        }
+
+        if &kind == b"ftyp" || &kind == b"moov" {
+            let mut raw = vec![0u8; size as usize];
+            input
+                .seek(SeekFrom::Start(position))
+                .and_then(|_| input.read_exact(&mut raw))
+                .map_err(|e| format!("Failed to read video: {}", e))?;
+
+            if &kind == b"ftyp" {
+                ftyp = Some(raw);
+            } else {
+                // Keep only the payload, which is what parse_boxes wants
+                let header_len = if read_u32(&raw, 0)? == 1 { 16 } else { 8 };
+                moov = Some(raw[header_len..].to_vec());
+            }
+        }
+
+        position += size;
+    }
+
+    let moov = moov.ok_or("Video is missing its moov box, so it may not be an MP4 file or it may not have finished recording.")?;
+
+    // Old QuickTime files don't always have an ftyp, so fall back to a plain one
+    let ftyp = ftyp.unwrap_or_else(|| {
+        let mut payload = b"isom".to_vec();
+        payload.extend_from_slice(&512u32.to_be_bytes());
+        payload.extend_from_slice(b"isomiso2mp41");
+        make_box(b"ftyp", &payload)
+    });
+
+    Ok((ftyp, moov))
+}
+
+/// Timescale out of an mvhd or mdhd box, which share the same layout up to the duration.
+fn read_timescale(header_box: &Mp4Box) -> Result<u32, String> {
+    let offset = if header_box.payload.first() == Some(&1) { 20 } else { 12 };
+    let timescale = read_u32(header_box.payload, offset)?;
+
+    if timescale == 0 {
+        return Err("Video has a timescale of 0.".to_string());
+    }
+
+    Ok(timescale)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Track
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+#[derive(Clone, Copy)]
+struct Sample {
+    offset: u64,
+    size: u32,
+    decode_time: u64,
+    duration: u32,
+    composition_offset: i64,
+    is_sync: bool,
+    description_index: u32,
+}
+
+/// One track of the source file, with its sample table unpacked into a list of samples.
+struct Track<'a> {
+    handler: [u8; 4],
+    timescale: u32,
+    tkhd: &'a [u8],
+    mdhd: &'a [u8],
+    hdlr: &'a [u8],
+    /// Every box in minf except stbl, which gets rebuilt
+    minf_boxes: Vec<&'a [u8]>,
+    stsd: &'a [u8],
+    /// Version and flags of the source ctts box, if it had one
+    ctts_header: Option<[u8; 4]>,
+    has_sync_table: bool,
+    samples: Vec<Sample>,
+    /// Added to decode time + composition offset to get the presentation time, from the source edit list
+    presentation_offset: i64,
+}
+
+impl<'a> Track<'a> {
+    fn parse(trak: &'a Mp4Box<'a>, movie_timescale: u32) -> Result<Self, String> {
+        let trak_boxes = parse_boxes(trak.payload)?;
+        let tkhd = require_box(&trak_boxes, b"tkhd")?;
+        let mdia = require_box(&trak_boxes, b"mdia")?;
+
+        let mdia_boxes = parse_boxes(mdia.payload)?;
+        let mdhd = require_box(&mdia_boxes, b"mdhd")?;
+        let hdlr = require_box(&mdia_boxes, b"hdlr")?;
+        let minf = require_box(&mdia_boxes, b"minf")?;
+
+        let timescale = read_timescale(mdhd)?;
+        let handler: [u8; 4] = hdlr
+            .payload
+            .get(8..12)
+            .and_then(|handler| handler.try_into().ok())
+            .ok_or("Video has a corrupt hdlr box.")?;
+
+        let minf_boxes = parse_boxes(minf.payload)?;
+        let stbl = require_box(&minf_boxes, b"stbl")?;
+        let stbl_boxes = parse_boxes(stbl.payload)?;
+
+        let ctts = find_box(&stbl_boxes, b"ctts");
+        let stss = find_box(&stbl_boxes, b"stss");
+
+        let samples = read_samples(&stbl_boxes)?;
+
+        let presentation_offset = match find_box(&trak_boxes, b"edts")
+            .map(|edts| parse_boxes(edts.payload))
+            .transpose()?
+            .as_deref()
+            .and_then(|edts_boxes| find_box(edts_boxes, b"elst"))
+        {
+            Some(elst) => read_edit_list_offset(elst, movie_timescale, timescale)?,
+            None => 0,
+        };
+
+        Ok(Track {
+            handler,
+            timescale,
+            tkhd: tkhd.raw,
+            mdhd: mdhd.raw,
+            hdlr: hdlr.raw,
+            minf_boxes: minf_boxes
+                .iter()
+                .filter(|child| &child.kind != b"stbl")
+                .map(|child| child.raw)
+                .collect(),
+            stsd: require_box(&stbl_boxes, b"stsd")?.raw,
+            ctts_header: ctts.and_then(|ctts| ctts.payload.get(0..4)).map(|header| header.try_into().unwrap()),
+            has_sync_table: stss.is_some(),
+            samples,
+            presentation_offset,
+        })
+    }
+
+    /// Presentation time of a sample in the track timescale.
+    fn presentation_time(&self, index: usize) -> i64 {
+        let sample = &self.samples[index];
+        sample.decode_time as i64 + sample.composition_offset + self.presentation_offset
+    }
+
+    /// For the video track. Returns the decode order range of samples from the last keyframe at or before the start, through the last
+    /// sample that is presented before the end.
+    fn select_from_keyframe(&self, start_seconds: f64, end_seconds: f64) -> Result<(usize, usize), String> {
+        let start = (start_seconds * self.timescale as f64).round() as i64;
+        let end = (end_seconds * self.timescale as f64).round() as i64;
+
+        let keyframes: Vec<usize> = (0..self.samples.len()).filter(|i| self.samples[*i].is_sync).collect();
+
+        let first = keyframes
+            .iter()
+            .copied()
+            .filter(|i| self.presentation_time(*i) <= start)
+            .max_by_key(|i| (self.presentation_time(*i), std::cmp::Reverse(*i)))
+            .or_else(|| keyframes.first().copied())
+            .ok_or("The video track doesn't have any keyframes.")?;
+
+        let last = (first..self.samples.len())
+            .filter(|i| self.presentation_time(*i) < end)
+            .max()
+            .unwrap_or(first);
+
+        Ok((first, last))
+    }
+
+    /// For every track other than the video. Returns the decode order range of samples that are presented at any point within the clip.
+    fn select_overlapping(&self, start_seconds: f64, end_seconds: f64) -> Option<(usize, usize)> {
+        let start = (start_seconds * self.timescale as f64).floor() as i64;
+        let end = (end_seconds * self.timescale as f64).ceil() as i64;
+
+        let overlapping = |i: &usize| {
+            let presentation_time = self.presentation_time(*i);
+            presentation_time < end && presentation_time + self.samples[*i].duration as i64 > start
+        };
+
+        let first = (0..self.samples.len()).find(overlapping)?;
+        let last = (0..self.samples.len()).rev().find(overlapping)?;
+
+        Some((first, last))
+    }
+}
+
+/// Unpacks the stts, ctts, stsz, stsc, stco/co64, and stss tables into one list of samples in decode order.
+fn read_samples(stbl_boxes: &[Mp4Box]) -> Result<Vec<Sample>, String> {
+    // Sizes
+    let stsz = find_box(stbl_boxes, b"stsz").ok_or_else(|| {
+        if find_box(stbl_boxes, b"stz2").is_some() {
+            "Videos with compact sample sizes (stz2) aren't supported.".to_string()
+        } else {
+            "Video is missing a stsz box.".to_string()
+        }
+    })?;
+    let constant_size = read_u32(stsz.payload, 4)?;
+    let sample_count = read_u32(stsz.payload, 8)? as usize;
+    let sizes: Vec<u32> = if constant_size != 0 {
+        vec![constant_size; sample_count]
+    } else {
+        (0..sample_count)
+            .map(|i| read_u32(stsz.payload, 12 + 4 * i))
+            .collect::<Result<_, _>>()?
+    };
+
+    // Decode times and durations
+    let stts = require_box(stbl_boxes, b"stts")?;
+    let mut durations = Vec::with_capacity(sample_count);
+    for entry in 0..read_u32(stts.payload, 4)? as usize {
+        let count = read_u32(stts.payload, 8 + 8 * entry)? as usize;
+        let delta = read_u32(stts.payload, 12 + 8 * entry)?;
+        let count = count.min(sample_count.saturating_sub(durations.len()));
+        durations.resize(durations.len() + count, delta);
+    }
+
+    // Composition offsets, which only show up when frames are stored out of order (B-frames)
+    let mut composition_offsets = vec![0i64; sample_count];
+    if let Some(ctts) = find_box(stbl_boxes, b"ctts") {
+        let signed = ctts.payload.first() == Some(&1);
+        let mut i = 0;
+        for entry in 0..read_u32(ctts.payload, 4)? as usize {
+            let count = read_u32(ctts.payload, 8 + 8 * entry)? as usize;
+            let raw = read_u32(ctts.payload, 12 + 8 * entry)?;
+            let offset = if signed { raw as i32 as i64 } else { raw as i64 };
+            for _ in 0..count {
+                if i < sample_count {
+                    composition_offsets[i] = offset;
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    // Keyframes. No stss box means every sample is a keyframe.
+    let sync_samples: Option<HashSet<usize>> = find_box(stbl_boxes, b"stss")
+        .map(|stss| {
+            // Sample numbers start at 1, so a 0 can only come from a corrupt file
+            (0..read_u32(stss.payload, 4)? as usize)
+                .map(|i| {
+                    read_u32(stss.payload, 8 + 4 * i)?
+                        .checked_sub(1)
+                        .map(|index| index as usize)
+                        .ok_or_else(|| "Video has a corrupt keyframe table (stss box).".to_string())
+                })
+                .collect::<Result<HashSet<_>, String>>()
+        })
+        .transpose()?;
+
+    // Chunk offsets
+    let chunk_offsets: Vec<u64> = if let Some(stco) = find_box(stbl_boxes, b"stco") {
+        (0..read_u32(stco.payload, 4)? as usize)
+            .map(|i| read_u32(stco.payload, 8 + 4 * i).map(|offset| offset as u64))
+            .collect::<Result<_, _>>()?
+    } else if let Some(co64) = find_box(stbl_boxes, b"co64") {
+        (0..read_u32(co64.payload, 4)? as usize)
+            .map(|i| read_u64(co64.payload, 8 + 8 * i))
+            .collect::<Result<_, _>>()?
+    } else {
+        return Err("Video is missing its chunk offsets (stco or co64 box).".to_string());
+    };
+
+    // Which samples are in which chunk
+    let stsc = require_box(stbl_boxes, b"stsc")?;
+    let stsc_entries: Vec<(usize, usize, u32)> = (0..read_u32(stsc.payload, 4)? as usize)
+        .map(|i| {
+            Ok((
+                read_u32(stsc.payload, 8 + 12 * i)? as usize,
+                read_u32(stsc.payload, 12 + 12 * i)? as usize,
+                read_u32(stsc.payload, 16 + 12 * i)?,
+            ))
+        })
+        .collect::<Result<_, String>>()?;
+
+    if durations.len() != sample_count {
+        return Err("Video sample tables don't agree on how many samples there are.".to_string());
+    }
+
+    let mut samples = Vec::with_capacity(sample_count);
+    let mut decode_time = 0u64;
+
+    for (entry_index, &(first_chunk, samples_per_chunk, description_index)) in stsc_entries.iter().enumerate() {
+        let next_first_chunk = stsc_entries
+            .get(entry_index + 1)
+            .map(|(next_first_chunk, _, _)| *next_first_chunk)
+            .unwrap_or(chunk_offsets.len() + 1);
+
+        for chunk in first_chunk..next_first_chunk {
+            let mut offset = *chunk_offsets
+                .get(chunk.wrapping_sub(1))
+                .ok_or("Video sample-to-chunk table points at a chunk that doesn't exist.")?;
+
+            for _ in 0..samples_per_chunk {
+                let i = samples.len();
+                if i >= sample_count {
+                    break;
+                }
+
+                samples.push(Sample {
+                    offset,
+                    size: sizes[i],
+                    decode_time,
+                    duration: durations[i],
+                    composition_offset: composition_offsets[i],
+                    is_sync: match sync_samples {
+                        Some(ref sync_samples) => sync_samples.contains(&i),
+                        None => true,
+                    },
+                    description_index,
+                });
+
+                offset += sizes[i] as u64;
+                decode_time += durations[i] as u64;
+            }
+        }
+    }
+
+    if samples.len() != sample_count {
+        return Err("Video sample tables don't agree on how many samples there are.".to_string());
+    }
+
+    Ok(samples)
+}
+
+/// Works out how the source edit list shifts the track, as an offset in the track timescale. Only the common cases are handled: leading
+/// empty edits (the track starts late) followed by one edit that skips the start of the media (encoder delay, B-frame reordering).
+fn read_edit_list_offset(elst: &Mp4Box, movie_timescale: u32, track_timescale: u32) -> Result<i64, String> {
+    let version = elst.payload.first().copied().unwrap_or(0);
+    let entry_size = if version == 1 { 20 } else { 12 };
+
+    let mut delay = 0i64;
+
+    for i in 0..read_u32(elst.payload, 4)? as usize {
+        let base = 8 + entry_size * i;
+        let (segment_duration, media_time) = if version == 1 {
+            (read_u64(elst.payload, base)? as i64, read_u64(elst.payload, base + 8)? as i64)
+        } else {
+            (read_u32(elst.payload, base)? as i64, read_u32(elst.payload, base + 4)? as i32 as i64)
+        };
+
+        if media_time == -1 {
+            delay += rescale(segment_duration as u64, movie_timescale, track_timescale) as i64;
+        } else {
+            return Ok(delay - media_time);
+        }
+    }
+
+    Ok(delay)
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Writing
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+/// The part of a track that goes into the clip.
+struct ClipTrack {
+    track_index: usize,
+    first: usize,
+    last: usize,
+    /// Empty time at the start of the clip before this track begins, in the track timescale
+    empty_duration: u64,
+    /// Where in the clipped media playback starts, in the track timescale
+    media_time: u64,
+    /// How long this track plays for, in the track timescale
+    segment_duration: u64,
+}
+
+impl ClipTrack {
+    fn new(
+        track_index: usize,
+        track: &Track,
+        (first, last): (usize, usize),
+        clip_start_seconds: f64,
+        clip_end_seconds: f64,
+    ) -> Self {
+        let timescale = track.timescale as f64;
+        let clip_start = (clip_start_seconds * timescale).round() as i64;
+        let clip_end = (clip_end_seconds * timescale).round() as i64;
+        let first_decode_time = track.samples[first].decode_time as i64;
+
+        // The edit list media time that puts the clip start at time 0. It is negative when this track starts after the clip does.
+        let media_time = clip_start - track.presentation_offset - first_decode_time;
+        let empty_duration = (-media_time).max(0) as u64;
+        let media_time = media_time.max(0) as u64;
+
+        let media_end = (first..=last)
+            .map(|i| track.presentation_time(i) + track.samples[i].duration as i64)
+            .max()
+            .unwrap_or_default()
+            .min(clip_end);
+        let segment_duration = (media_end - clip_start - empty_duration as i64).max(0) as u64;
+
+        ClipTrack {
+            track_index,
+            first,
+            last,
+            empty_duration,
+            media_time,
+            segment_duration,
+        }
+    }
+}
+
+/// Everything needed to write the output file.
+struct Clip<'a> {
+    ftyp: &'a [u8],
+    mvhd: &'a [u8],
+    udta: Option<&'a [u8]>,
+    movie_timescale: u32,
+    tracks: &'a [Track<'a>],
+    clip_tracks: &'a [ClipTrack],
+}
+
+/// A run of samples from one track that sit next to each other in the output mdat.
+struct Chunk {
+    samples: usize,
+    description_index: u32,
+    /// Offset from the start of the mdat payload
+    offset: u64,
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Write Clip
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+fn write_clip(input: &mut File, output_path: &Path, clip: &Clip) -> Result<(), String> {
+    // Interleave the tracks the same way the source file did, by always taking whichever track's next sample came first in the source.
+    // Each track stays in decode order no matter what.
+    let mut order: Vec<(usize, usize)> = Vec::new();
+    let mut next: Vec<usize> = clip.clip_tracks.iter().map(|clip_track| clip_track.first).collect();
+
+    loop {
+        let pick = clip
+            .clip_tracks
+            .iter()
+            .enumerate()
+            .filter(|(k, clip_track)| next[*k] <= clip_track.last)
+            .min_by_key(|(k, clip_track)| clip.tracks[clip_track.track_index].samples[next[*k]].offset)
+            .map(|(k, _)| k);
+
+        let Some(k) = pick else {
+            break;
+        };
+
+        order.push((k, next[k]));
+        next[k] += 1;
+    }
+
+    // Group runs of samples from the same track into chunks
+    let mut chunks: Vec<Vec<Chunk>> = clip.clip_tracks.iter().map(|_| Vec::new()).collect();
+    let mut mdat_len = 0u64;
+    let mut previous: Option<usize> = None;
+
+    for &(k, i) in &order {
+        let sample = &clip.tracks[clip.clip_tracks[k].track_index].samples[i];
+
+        match chunks[k].last_mut() {
+            Some(chunk) if previous == Some(k) && chunk.description_index == sample.description_index => chunk.samples += 1,
+            _ => chunks[k].push(Chunk {
+                samples: 1,
+                description_index: sample.description_index,
+                offset: mdat_len,
+            }),
+        }
+
+        previous = Some(k);
+        mdat_len += sample.size as u64;
+    }
+
+    // The chunk offsets depend on how big the moov is, and the moov gets bigger if the offsets need 64 bits
+    let mdat_header_len: u64 = if mdat_len + 8 > u32::MAX as u64 { 16 } else { 8 };
+    let mut use_co64 = false;
+    let mut moov = build_moov(clip, &chunks, 0, use_co64);
+    if clip.ftyp.len() as u64 + moov.len() as u64 + mdat_header_len + mdat_len > u32::MAX as u64 {
+        use_co64 = true;
+        moov = build_moov(clip, &chunks, 0, use_co64);
+    }
+    let mdat_start = clip.ftyp.len() as u64 + moov.len() as u64 + mdat_header_len;
+    let moov = build_moov(clip, &chunks, mdat_start, use_co64);
+
+    let output = File::create(output_path).map_err(|e| format!("Failed to create clip file: {}", e))?;
+    let mut writer = BufWriter::new(output);
+    let write_error = |e: std::io::Error| format!("Failed to write clip file: {}", e);
+
+    writer.write_all(clip.ftyp).map_err(write_error)?;
+    writer.write_all(&moov).map_err(write_error)?;
+
+    if mdat_header_len == 16 {
+        writer.write_all(&1u32.to_be_bytes()).map_err(write_error)?;
+        writer.write_all(b"mdat").map_err(write_error)?;
+        writer.write_all(&(mdat_len + 16).to_be_bytes()).map_err(write_error)?;
+    } else {
+        writer.write_all(&((mdat_len + 8) as u32).to_be_bytes()).map_err(write_error)?;
+        writer.write_all(b"mdat").map_err(write_error)?;
+    }
+
+    let mut reader = BufReader::new(input);
+    let mut buffer = Vec::new();
+
+    for &(k, i) in &order {
+        let sample = &clip.tracks[clip.clip_tracks[k].track_index].samples[i];
+
+        buffer.resize(sample.size as usize, 0);
+        reader
+            .seek(SeekFrom::Start(sample.offset))
+            .and_then(|_| reader.read_exact(&mut buffer))
+            .map_err(|e| format!("Failed to read video sample data: {}", e))?;
+
+        writer.write_all(&buffer).map_err(write_error)?;
+    }
+
+    writer.flush().map_err(write_error)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Build Moov
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+fn build_moov(clip: &Clip, chunks: &[Vec<Chunk>], mdat_start: u64, use_co64: bool) -> Vec<u8> {
+    let mut traks = Vec::new();
+    let mut movie_duration = 0u64;
+
+    for (clip_track, track_chunks) in clip.clip_tracks.iter().zip(chunks) {
+        let track = &clip.tracks[clip_track.track_index];
+        let samples = &track.samples[clip_track.first..=clip_track.last];
+
+        let to_movie = |duration: u64| rescale(duration, track.timescale, clip.movie_timescale);
+        let track_duration = to_movie(clip_track.empty_duration) + to_movie(clip_track.segment_duration);
+        movie_duration = movie_duration.max(track_duration);
+
+        let stbl = make_box(
+            b"stbl",
+            &[
+                track.stsd.to_vec(),
+                build_stts(samples),
+                track.ctts_header.map(|header| build_ctts(header, samples)).unwrap_or_default(),
+                if track.has_sync_table { build_stss(samples) } else { Vec::new() },
+                build_stsz(samples),
+                build_stsc(track_chunks),
+                build_chunk_offsets(track_chunks, mdat_start, use_co64),
+            ]
+            .concat(),
+        );
+
+        let minf = make_box(b"minf", &[track.minf_boxes.concat(), stbl].concat());
+
+        let media_duration: u64 = samples.iter().map(|sample| sample.duration as u64).sum();
+        let mdia = make_box(
+            b"mdia",
+            &[patch_duration(track.mdhd, media_duration, 24, 32), track.hdlr.to_vec(), minf].concat(),
+        );
+
+        let edts = make_box(b"edts", &build_elst(clip_track, &to_movie));
+
+        traks.push(make_box(
+            b"trak",
+            &[patch_duration(track.tkhd, track_duration, 28, 36), edts, mdia].concat(),
+        ));
+    }
+
+    make_box(
+        b"moov",
+        &[
+            patch_duration(clip.mvhd, movie_duration, 24, 32),
+            traks.concat(),
+            clip.udta.map(|udta| udta.to_vec()).unwrap_or_default(),
+        ]
+        .concat(),
+    )
+}
+
+fn build_stts(samples: &[Sample]) -> Vec<u8> {
+    let runs = run_lengths(samples.iter().map(|sample| sample.duration));
+    full_box(b"stts", 0, 0, &counted_pairs(&runs))
+}
+
+fn build_ctts(header: [u8; 4], samples: &[Sample]) -> Vec<u8> {
+    let runs = run_lengths(samples.iter().map(|sample| sample.composition_offset as i32 as u32));
+    make_box(b"ctts", &[header.to_vec(), counted_pairs(&runs)].concat())
+}
+
+fn build_stss(samples: &[Sample]) -> Vec<u8> {
+    let sync_numbers: Vec<u32> = samples
+        .iter()
+        .enumerate()
+        .filter(|(_, sample)| sample.is_sync)
+        .map(|(i, _)| i as u32 + 1)
+        .collect();
+
+    let mut payload = (sync_numbers.len() as u32).to_be_bytes().to_vec();
+    sync_numbers.iter().for_each(|number| payload.extend_from_slice(&number.to_be_bytes()));
+    full_box(b"stss", 0, 0, &payload)
+}
+
+fn build_stsz(samples: &[Sample]) -> Vec<u8> {
+    let first_size = samples[0].size;
+    let mut payload = Vec::new();
+
+    if samples.iter().all(|sample| sample.size == first_size) {
+        payload.extend_from_slice(&first_size.to_be_bytes());
+        payload.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+    } else {
+        payload.extend_from_slice(&0u32.to_be_bytes());
+        payload.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+        samples.iter().for_each(|sample| payload.extend_from_slice(&sample.size.to_be_bytes()));
+    }
+
+    full_box(b"stsz", 0, 0, &payload)
+}
+
+fn build_stsc(chunks: &[Chunk]) -> Vec<u8> {
+    let mut entries: Vec<(u32, u32, u32)> = Vec::new();
+
+    for (i, chunk) in chunks.iter().enumerate() {
+        let key = (chunk.samples as u32, chunk.description_index);
+        if entries.last().map(|(_, samples, description)| (*samples, *description)) != Some(key) {
+            entries.push((i as u32 + 1, key.0, key.1));
+        }
+    }
+
+    let mut payload = (entries.len() as u32).to_be_bytes().to_vec();
+    for (first_chunk, samples, description) in entries {
+        payload.extend_from_slice(&first_chunk.to_be_bytes());
+        payload.extend_from_slice(&samples.to_be_bytes());
+        payload.extend_from_slice(&description.to_be_bytes());
+    }
+    full_box(b"stsc", 0, 0, &payload)
+}
+
+fn build_chunk_offsets(chunks: &[Chunk], mdat_start: u64, use_co64: bool) -> Vec<u8> {
+    let mut payload = (chunks.len() as u32).to_be_bytes().to_vec();
+
+    for chunk in chunks {
+        let offset = mdat_start + chunk.offset;
+        if use_co64 {
+            payload.extend_from_slice(&offset.to_be_bytes());
+        } else {
+            payload.extend_from_slice(&(offset as u32).to_be_bytes());
+        }
+    }
+
+    full_box(if use_co64 { b"co64" } else { b"stco" }, 0, 0, &payload)
+}
+
+fn build_elst(clip_track: &ClipTrack, to_movie: &dyn Fn(u64) -> u64) -> Vec<u8> {
+    // (segment duration in the movie timescale, media time in the track timescale)
+    let mut entries: Vec<(u64, i64)> = Vec::new();
+    if clip_track.empty_duration > 0 {
+        entries.push((to_movie(clip_track.empty_duration), -1));
+    }
+    entries.push((to_movie(clip_track.segment_duration), clip_track.media_time as i64));
+
+    let needs_64_bits = entries
+        .iter()
+        .any(|(duration, media_time)| *duration > u32::MAX as u64 || *media_time > i32::MAX as i64);
+
+    let mut payload = (entries.len() as u32).to_be_bytes().to_vec();
+    for (duration, media_time) in entries {
+        if needs_64_bits {
+            payload.extend_from_slice(&duration.to_be_bytes());
+            payload.extend_from_slice(&media_time.to_be_bytes());
+        } else {
+            payload.extend_from_slice(&(duration as u32).to_be_bytes());
+            payload.extend_from_slice(&(media_time as i32).to_be_bytes());
+        }
+        // Playback rate of 1.0 as a 16.16 fixed point number
+        payload.extend_from_slice(&0x0001_0000u32.to_be_bytes());
+    }
+
+    full_box(b"elst", if needs_64_bits { 1 } else { 0 }, 0, &payload)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Byte Helpers
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, String> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|value| u32::from_be_bytes(value.try_into().unwrap()))
+        .ok_or_else(|| "Video header is truncated.".to_string())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, String> {
+    bytes
+        .get(offset..offset + 8)
+        .map(|value| u64::from_be_bytes(value.try_into().unwrap()))
+        .ok_or_else(|| "Video header is truncated.".to_string())
+}
+
+fn make_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(payload.len() + 8);
+    bytes.extend_from_slice(&(payload.len() as u32 + 8).to_be_bytes());
+    bytes.extend_from_slice(kind);
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+fn full_box(kind: &[u8; 4], version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+    let mut header = flags.to_be_bytes();
+    header[0] = version;
+    make_box(kind, &[header.as_slice(), payload].concat())
+}
+
+/// Copies an mvhd, mdhd, or tkhd box with a new duration. The duration sits at a different offset depending on the box version.
+fn patch_duration(raw: &[u8], duration: u64, version_0_offset: usize, version_1_offset: usize) -> Vec<u8> {
+    let mut bytes = raw.to_vec();
+
+    if bytes.get(8) == Some(&1) {
+        if let Some(field) = bytes.get_mut(version_1_offset..version_1_offset + 8) {
+            field.copy_from_slice(&duration.to_be_bytes());
+        }
+    } else if let Some(field) = bytes.get_mut(version_0_offset..version_0_offset + 4) {
+        field.copy_from_slice(&(duration.min(u32::MAX as u64) as u32).to_be_bytes());
+    }
+
+    bytes
+}
+
+/// Converts a duration from one timescale to another, rounding to the nearest tick.
+fn rescale(value: u64, from_timescale: u32, to_timescale: u32) -> u64 {
+    ((value as u128 * to_timescale as u128 + from_timescale as u128 / 2) / from_timescale as u128) as u64
+}
+
+/// Collapses repeated values into (count, value) runs, the way stts and ctts store them.
+fn run_lengths(values: impl Iterator<Item = u32>) -> Vec<(u32, u32)> {
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+
+    for value in values {
+        match runs.last_mut() {
+            Some((count, run_value)) if *run_value == value => *count += 1,
+            _ => runs.push((1, value)),
+        }
+    }
+
+    runs
+}
+
+fn counted_pairs(pairs: &[(u32, u32)]) -> Vec<u8> {
+    let mut payload = (pairs.len() as u32).to_be_bytes().to_vec();
+    for (a, b) in pairs {
+        payload.extend_from_slice(&a.to_be_bytes());
+        payload.extend_from_slice(&b.to_be_bytes());
+    }
+    payload
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Tests
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Samples in the synthetic video. Sample i is 10 + i bytes long, every byte set to i, so copied data can be checked.
+    const SAMPLE_COUNT: usize = 10;
+    const SAMPLES_PER_CHUNK: usize = 5;
+    /// Ticks per second of both the movie and the video track
+    const TIMESCALE: u32 = 1000;
+    const SAMPLE_DURATION: u32 = 100;
+    /// Sample numbers (starting at 1) of the keyframes
+    const KEYFRAMES: [u32; 2] = [1, 6];
+
+    /// A path in the temp directory that no other test uses
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("chronolab-mp4-clip-{}-{}.mp4", std::process::id(), name))
+    }
+
+    fn sample_data(i: usize) -> Vec<u8> {
+        vec![i as u8; 10 + i]
+    }
+
+    /// An mvhd or mdhd (version 0) with the timescale and duration filled in
+    fn header_box(kind: &[u8; 4], payload_len: usize, duration: u32) -> Vec<u8> {
+        let mut payload = vec![0u8; payload_len];
+        payload[8..12].copy_from_slice(&TIMESCALE.to_be_bytes());
+        payload[12..16].copy_from_slice(&duration.to_be_bytes());
+        full_box(kind, 0, 0, &payload)
+    }
+
+    fn table(kind: &[u8; 4], entries: &[Vec<u32>]) -> Vec<u8> {
+        let mut payload = (entries.len() as u32).to_be_bytes().to_vec();
+        entries.iter().flatten().for_each(|value| payload.extend_from_slice(&value.to_be_bytes()));
+        full_box(kind, 0, 0, &payload)
+    }
+
+    /// The stbl children for the synthetic video track, with chunks starting at the given offsets
+    fn sample_tables(chunk_offsets: &[u32], stss: Vec<u8>) -> Vec<u8> {
+        let mut stsz = vec![0, SAMPLE_COUNT as u32];
+        stsz.extend((0..SAMPLE_COUNT).map(|i| sample_data(i).len() as u32));
+
+        [
+            full_box(b"stsd", 0, 0, &0u32.to_be_bytes()),
+            table(b"stts", &[vec![SAMPLE_COUNT as u32, SAMPLE_DURATION]]),
+            stss,
+            full_box(b"stsz", 0, 0, &stsz.iter().flat_map(|value| value.to_be_bytes()).collect::<Vec<_>>()),
+            table(b"stsc", &[vec![1, SAMPLES_PER_CHUNK as u32, 1]]),
+            table(b"stco", &chunk_offsets.iter().map(|offset| vec![*offset]).collect::<Vec<_>>()),
+        ]
+        .concat()
+    }
+
+    fn keyframe_table(numbers: &[u32]) -> Vec<u8> {
+        table(b"stss", &numbers.iter().map(|number| vec![*number]).collect::<Vec<_>>())
+    }
+
+    /// A complete MP4 with one video track. The mdat comes before the moov, so the chunk offsets are known before the moov is built.
+    fn synthetic_mp4() -> Vec<u8> {
+        let ftyp = make_box(b"ftyp", b"isom\0\0\x02\0isomiso2mp41");
+        let mdat_payload: Vec<u8> = (0..SAMPLE_COUNT).flat_map(sample_data).collect();
+        let mdat = make_box(b"mdat", &mdat_payload);
+
+        let mdat_start = (ftyp.len() + 8) as u32;
+        let second_chunk = mdat_start + (0..SAMPLES_PER_CHUNK).map(|i| sample_data(i).len() as u32).sum::<u32>();
+        let duration = SAMPLE_COUNT as u32 * SAMPLE_DURATION;
+
+        let mut hdlr = vec![0u8; 21];
+        hdlr[4..8].copy_from_slice(b"vide");
+        let stbl = make_box(b"stbl", &sample_tables(&[mdat_start, second_chunk], keyframe_table(&KEYFRAMES)));
+        let minf = make_box(b"minf", &[full_box(b"vmhd", 0, 1, &[0u8; 8]), stbl].concat());
+        let mdia = make_box(
+            b"mdia",
+            &[header_box(b"mdhd", 20, duration), full_box(b"hdlr", 0, 0, &hdlr), minf].concat(),
+        );
+        let trak = make_box(b"trak", &[full_box(b"tkhd", 0, 3, &[0u8; 80]), mdia].concat());
+        let moov = make_box(b"moov", &[header_box(b"mvhd", 96, duration), trak].concat());
+
+        [ftyp, mdat, moov].concat()
+    }
+
+    /// Reads a file back in the same way extract_clip does, returning the parsed video track's samples and the file's bytes
+    fn read_video_samples(path: &Path) -> (Vec<Sample>, Vec<u8>) {
+        let mut file = File::open(path).unwrap();
+        let (_, moov) = read_header_boxes(&mut file).unwrap();
+        let moov_children = parse_boxes(&moov).unwrap();
+        let movie_timescale = read_timescale(find_box(&moov_children, b"mvhd").unwrap()).unwrap();
+        let trak = find_box(&moov_children, b"trak").unwrap();
+        let track = Track::parse(trak, movie_timescale).unwrap();
+
+        assert_eq!(&track.handler, b"vide");
+        assert_eq!(track.timescale, TIMESCALE);
+
+        (track.samples, std::fs::read(path).unwrap())
+    }
+
+    #[test]
+    fn parse_boxes_splits_nested_boxes() {
+        let bytes = [make_box(b"free", b"abc"), make_box(b"moov", &make_box(b"mvhd", &[1, 2]))].concat();
+        let boxes = parse_boxes(&bytes).unwrap();
+
+        assert_eq!(boxes.len(), 2);
+        assert_eq!(&boxes[0].kind, b"free");
+        assert_eq!(boxes[0].payload, b"abc");
+        assert_eq!(boxes[1].raw.len(), 18);
+
+        let children = parse_boxes(boxes[1].payload).unwrap();
+        assert_eq!(&children[0].kind, b"mvhd");
+        assert_eq!(children[0].payload, [1, 2]);
+    }
+
+    #[test]
+    fn parse_boxes_rejects_sizes_past_the_end() {
+        let mut bytes = make_box(b"free", b"abc");
+        bytes[0..4].copy_from_slice(&100u32.to_be_bytes());
+        assert!(parse_boxes(&bytes).is_err());
+
+        // A 64 bit size big enough to overflow the end position
+        let mut bytes = 1u32.to_be_bytes().to_vec();
+        bytes.extend_from_slice(b"free");
+        bytes.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert!(parse_boxes(&bytes).is_err());
+    }
+
+    #[test]
+    fn read_header_boxes_rejects_sizes_that_overflow() {
+        let path = temp_path("overflow");
+        let mut bytes = make_box(b"ftyp", b"isom");
+        bytes.extend_from_slice(&1u32.to_be_bytes());
+        bytes.extend_from_slice(b"mdat");
+        bytes.extend_from_slice(&u64::MAX.to_be_bytes());
+        std::fs::write(&path, bytes).unwrap();
+
+        let result = read_header_boxes(&mut File::open(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn read_samples_unpacks_the_sample_tables() {
+        let stbl = sample_tables(&[100, 200], keyframe_table(&KEYFRAMES));
+        let samples = read_samples(&parse_boxes(&stbl).unwrap()).unwrap();
+
+        assert_eq!(samples.len(), SAMPLE_COUNT);
+        assert_eq!(samples[0].offset, 100);
+        assert_eq!(samples[1].offset, 110);
+        assert_eq!(samples[5].offset, 200);
+        assert_eq!(samples[9].decode_time, 9 * SAMPLE_DURATION as u64);
+        assert_eq!(samples[9].size, 19);
+
+        let keyframes: Vec<usize> = (0..SAMPLE_COUNT).filter(|i| samples[*i].is_sync).collect();
+        assert_eq!(keyframes, [0, 5]);
+    }
+
+    #[test]
+    fn read_samples_rejects_a_keyframe_numbered_0() {
+        let stbl = sample_tables(&[100, 200], keyframe_table(&[0, 6]));
+        assert!(read_samples(&parse_boxes(&stbl).unwrap()).is_err());
+    }
+
+    #[test]
+    fn built_tables_read_back_the_same() {
+        let stbl = sample_tables(&[100, 200], keyframe_table(&KEYFRAMES));
+        let samples = read_samples(&parse_boxes(&stbl).unwrap()).unwrap();
+
+        let chunks = [
+            Chunk {
+                samples: 7,
+                description_index: 1,
+                offset: 0,
+            },
+            Chunk {
+                samples: 3,
+                description_index: 1,
+                offset: 500,
+            },
+        ];
+        let rebuilt = [
+            full_box(b"stsd", 0, 0, &0u32.to_be_bytes()),
+            build_stts(&samples),
+            build_stss(&samples),
+            build_stsz(&samples),
+            build_stsc(&chunks),
+            build_chunk_offsets(&chunks, 1000, true),
+        ]
+        .concat();
+        let reread = read_samples(&parse_boxes(&rebuilt).unwrap()).unwrap();
+
+        assert_eq!(reread.len(), samples.len());
+        for (original, reread) in samples.iter().zip(&reread) {
+            assert_eq!(reread.size, original.size);
+            assert_eq!(reread.decode_time, original.decode_time);
+            assert_eq!(reread.duration, original.duration);
+            assert_eq!(reread.is_sync, original.is_sync);
+        }
+        assert_eq!(reread[6].offset, 1000 + (10..16).sum::<u64>());
+        assert_eq!(reread[7].offset, 1500);
+    }
+
+    #[test]
+    fn extract_clip_starts_on_a_keyframe_and_copies_the_samples() {
+        let input_path = temp_path("input");
+        let output_path = temp_path("output");
+        std::fs::write(&input_path, synthetic_mp4()).unwrap();
+
+        let (source_samples, _) = read_video_samples(&input_path);
+        assert_eq!(source_samples.len(), SAMPLE_COUNT);
+
+        let bounds = extract_clip(&input_path, &output_path, 0.65, 0.85).unwrap();
+        let (samples, bytes) = read_video_samples(&output_path);
+        std::fs::remove_file(&input_path).unwrap();
+        std::fs::remove_file(&output_path).unwrap();
+
+        // Sample 5 is the last keyframe before 0.65s, and sample 8 is the last one that starts before 0.85s
+        assert_eq!(bounds.start_seconds, 0.5);
+        assert_eq!(bounds.end_seconds, 0.9);
+        assert_eq!(samples.len(), 4);
+        assert_eq!(samples.iter().map(|sample| sample.is_sync).collect::<Vec<_>>(), [true, false, false, false]);
+
+        for (k, sample) in samples.iter().enumerate() {
+            let start = sample.offset as usize;
+            assert_eq!(bytes[start..start + sample.size as usize], sample_data(5 + k));
+        }
+    }
+}
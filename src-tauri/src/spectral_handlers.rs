@@ -0,0 +1,399 @@
+use crate::dataframe_handlers::{column_samples, display_data_with_columns, median_sample_rate_hz, select_display_data};
+use crate::global_state::{snapshot_app_state, AppState, TimeBounds, TimeWindow};
+use crate::job_handlers::run_job;
+use rustfft::{num_complex::Complex, FftPlanner};
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::sync::RwLock;
+use tauri::{AppHandle, State};
+
+/// Upper limit on the number of points after resampling, so a tiny sample period over a long window can't eat all the memory.
+const MAX_SPECTRUM_SAMPLES: usize = 1 << 24;
+/// Fewest evenly spaced points a spectrum, or a Welch segment, can be computed from
+const MIN_SPECTRUM_SAMPLES: usize = 4;
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Structs
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SpectrumMethod {
+    /// Single sided amplitude spectrum of the whole window, in column units
+    Fft,
+    /// Welch's averaged periodogram, in column units squared per Hz
+    Welch,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowFunction {
+    Rectangular,
+    #[default]
+    Hann,
+    Hamming,
+    Blackman,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SpectrumSettings {
+    pub method: SpectrumMethod,
+    #[serde(default)]
+    pub window_function: WindowFunction,
+    /// Rate the column is resampled to before the FFT. Defaults to the median sample rate of the data in the window.
+    #[serde(default)]
+    pub sample_rate_hz: Option<f64>,
+    /// Points per Welch segment. Defaults to 256, or the whole window if it is shorter than that.
+    #[serde(default)]
+    pub segment_length: Option<usize>,
+    /// Fraction of each Welch segment that overlaps the next one. Defaults to 0.5.
+    #[serde(default)]
+    pub overlap: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Spectrum {
+    pub method: SpectrumMethod,
+    pub frequencies_hz: Vec<f64>,
+    pub values: Vec<f64>,
+    pub sample_rate_hz: f64,
+    /// Number of evenly spaced points the spectrum was computed from
+    pub sample_count: usize,
+    /// Welch only, the number of segments that were averaged
+    pub segment_count: Option<usize>,
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Tauri Commands
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Get Spectrum
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Computes the frequency spectrum of one column over a time window. The samples are linearly interpolated onto an evenly spaced grid first,
+/// since the CSV timestamps usually have some jitter and the FFT assumes they don't. The mean is removed before windowing so the DC bin
+/// doesn't swamp everything else.
+#[tauri::command]
+pub async fn get_spectrum(
+    app: AppHandle,
+    state: State<'_, RwLock<AppState>>,
+    window: TimeWindow,
+    column: String,
+    settings: SpectrumSettings,
+) -> Result<Spectrum, String> {
+    let state = snapshot_app_state(&state);
+    let job_app = app.clone();
+
+    run_job(&app, "get_spectrum", move |job| {
+        let datetime_index_col = state
+            .load_csv_settings
+            .as_ref()
+            .ok_or("CSV loading settings have not been set yet.")?
+            .datetime_index_col
+            .as_str();
+
+        let (start_time, end_time) = state.resolve_time_window(&window)?;
+        let time_bounds = TimeBounds {
+            start_time: Some(start_time),
+            end_time: Some(end_time),
+        };
+
+        let columns = [column];
+        let df = display_data_with_columns(&job_app, &state, &columns, job)?;
+        let df = select_display_data(&df, datetime_index_col, &columns, Some(&time_bounds))?;
+
+        job.checkpoint()?;
+        job.progress("Computing spectrum", Some(70.0), Some(df.height()));
+
+        let samples = column_samples(&df, datetime_index_col, &columns[0])?;
+
+        compute_spectrum(&samples, &settings)
+    })
+    .await
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Utility Functions
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Compute Spectrum
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+fn compute_spectrum(samples: &[(i64, f64)], settings: &SpectrumSettings) -> Result<Spectrum, String> {
+    if samples.len() < MIN_SPECTRUM_SAMPLES {
+        return Err(format!("At least {} samples are needed to compute a spectrum.", MIN_SPECTRUM_SAMPLES));
+    }
+
+    let sample_rate_hz = match settings.sample_rate_hz {
+        Some(sample_rate_hz) if sample_rate_hz > 0.0 && sample_rate_hz.is_finite() => sample_rate_hz,
+        Some(sample_rate_hz) => return Err(format!("Invalid sample rate: {} Hz", sample_rate_hz)),
+        None => median_sample_rate_hz(&samples.iter().map(|(time, _)| *time).collect::<Vec<_>>())
+            .ok_or("Could not work out the sample rate of the data.")?,
+    };
+
+    let mut values = resample_uniform(samples, sample_rate_hz)?;
+
+    // A low sample rate can leave fewer points after resampling than there were samples
+    if values.len() < MIN_SPECTRUM_SAMPLES {
+        return Err(format!(
+            "Resampled at {} Hz the window only has {} points, and at least {} are needed. Pick a higher sample rate or a longer window.",
+            sample_rate_hz,
+            values.len(),
+            MIN_SPECTRUM_SAMPLES
+        ));
+    }
+
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    values.iter_mut().for_each(|value| *value -= mean);
+
+    let sample_count = values.len();
+
+    let (frequencies_hz, spectrum_values, segment_count) = match settings.method {
+        SpectrumMethod::Fft => {
+            let (frequencies_hz, amplitudes) = amplitude_spectrum(&values, sample_rate_hz, settings.window_function);
+            (frequencies_hz, amplitudes, None)
+        }
+        SpectrumMethod::Welch => {
+            let segment_length = settings.segment_length.unwrap_or(256).min(sample_count);
+            let overlap = settings.overlap.unwrap_or(0.5);
+
+            if segment_length < MIN_SPECTRUM_SAMPLES {
+                return Err(format!("Welch segments need at least {} points.", MIN_SPECTRUM_SAMPLES));
+            }
+            if !(0.0..1.0).contains(&overlap) {
+                return Err(format!("Welch overlap must be at least 0 and less than 1, got {}", overlap));
+            }
+
+            let (frequencies_hz, psd, segment_count) =
+                welch_psd(&values, sample_rate_hz, settings.window_function, segment_length, overlap);
+            (frequencies_hz, psd, Some(segment_count))
+        }
+    };
+
+    Ok(Spectrum {
+        method: settings.method,
+        frequencies_hz,
+        values: spectrum_values,
+        sample_rate_hz,
+        sample_count,
+        segment_count,
+    })
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Amplitude Spectrum
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Single sided amplitude spectrum, scaled so a sine wave of amplitude A shows up as a peak of height A (for frequencies that land on a bin).
+fn amplitude_spectrum(values: &[f64], sample_rate_hz: f64, window_function: WindowFunction) -> (Vec<f64>, Vec<f64>) {
+    let window = window_coefficients(window_function, values.len());
+    let window_sum: f64 = window.iter().sum();
+
+    let bins = windowed_fft(values, &window);
+
+    let amplitudes = bins
+        .iter()
+        .enumerate()
+        .map(|(k, bin)| {
+            let amplitude = bin.norm() / window_sum;
+            // Everything except DC and Nyquist also has a mirror image in the negative frequencies
+            if k == 0 || 2 * k == values.len() {
+                amplitude
+            } else {
+                2.0 * amplitude
+            }
+        })
+        .collect();
+
+    (frequency_axis(values.len(), sample_rate_hz), amplitudes)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Welch PSD
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Averages the windowed periodograms of overlapping segments. The result is a single sided density in units squared per Hz, so summing
+/// it times the bin width gives back the variance of the signal.
+fn welch_psd(
+    values: &[f64],
+    sample_rate_hz: f64,
+    window_function: WindowFunction,
+    segment_length: usize,
+    overlap: f64,
+) -> (Vec<f64>, Vec<f64>, usize) {
+    let window = window_coefficients(window_function, segment_length);
+    let window_power: f64 = window.iter().map(|w| w * w).sum();
+    let step = ((segment_length as f64 * (1.0 - overlap)).round() as usize).max(1);
+
+    let mut psd = vec![0.0; segment_length / 2 + 1];
+    let mut segment_count = 0;
+
+    for segment_start in (0..=values.len() - segment_length).step_by(step) {
+        let segment = &values[segment_start..segment_start + segment_length];
+
+        // Each segment gets its own mean removed, otherwise slow drift leaks into the low frequency bins
+        let segment_mean = segment.iter().sum::<f64>() / segment_length as f64;
+        let segment: Vec<f64> = segment.iter().map(|value| value - segment_mean).collect();
+
+        for (k, bin) in windowed_fft(&segment, &window).iter().enumerate() {
+            psd[k] += bin.norm_sqr();
+        }
+        segment_count += 1;
+    }
+
+    let scale = 1.0 / (sample_rate_hz * window_power * segment_count as f64);
+
+    for (k, density) in psd.iter_mut().enumerate() {
+        *density *= scale;
+        if k != 0 && 2 * k != segment_length {
+            *density *= 2.0;
+        }
+    }
+
+    (frequency_axis(segment_length, sample_rate_hz), psd, segment_count)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Windowed FFT
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Multiplies the values by the window and returns the non-negative frequency half of the FFT.
+fn windowed_fft(values: &[f64], window: &[f64]) -> Vec<Complex<f64>> {
+    let mut buffer: Vec<Complex<f64>> = values
+        .iter()
+        .zip(window)
+        .map(|(value, w)| Complex::new(value * w, 0.0))
+        .collect();
+
+    FftPlanner::new().plan_fft_forward(buffer.len()).process(&mut buffer);
+
+    buffer.truncate(values.len() / 2 + 1);
+    buffer
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Window Coefficients
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Periodic form of each window, which is what you want for spectral analysis (as opposed to the symmetric form used for filter design).
+fn window_coefficients(window_function: WindowFunction, length: usize) -> Vec<f64> {
+    let n = length as f64;
+
+    (0..length)
+        .map(|i| {
+            let phase = 2.0 * PI * i as f64 / n;
+            match window_function {
+                WindowFunction::Rectangular => 1.0,
+                WindowFunction::Hann => 0.5 - 0.5 * phase.cos(),
+                WindowFunction::Hamming => 0.54 - 0.46 * phase.cos(),
+                WindowFunction::Blackman => 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos(),
+            }
+        })
+        .collect()
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Frequency Axis
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+fn frequency_axis(fft_length: usize, sample_rate_hz: f64) -> Vec<f64> {
+    (0..=fft_length / 2)
+        .map(|k| k as f64 * sample_rate_hz / fft_length as f64)
+        .collect()
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Resample Uniform
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Linearly interpolates the samples onto an evenly spaced grid starting at the first sample. Samples must be sorted by time.
+pub fn resample_uniform(samples: &[(i64, f64)], sample_rate_hz: f64) -> Result<Vec<f64>, String> {
+    if samples.len() < 2 {
+        return Err(format!("At least 2 samples are needed to resample, got {}.", samples.len()));
+    }
+
+    if sample_rate_hz <= 0.0 || !sample_rate_hz.is_finite() {
+        return Err(format!("Invalid sample rate: {} Hz", sample_rate_hz));
+    }
+
+    let (first_time, _) = samples[0];
+    let (last_time, _) = samples[samples.len() - 1];
+    let period_ms = 1000.0 / sample_rate_hz;
+
+    let point_count = ((last_time - first_time) as f64 / period_ms).floor() as usize + 1;
+
+    if point_count > MAX_SPECTRUM_SAMPLES {
+        return Err(format!(
+            "Resampling this window at {} Hz would need {} points, which is more than the limit of {}. Pick a shorter window or a lower sample rate.",
+            sample_rate_hz, point_count, MAX_SPECTRUM_SAMPLES
+        ));
+    }
+
+    let mut values = Vec::with_capacity(point_count);
+    let mut i = 0;
+
+    for point in 0..point_count {
+        let time = first_time as f64 + point as f64 * period_ms;
+
+        while i + 1 < samples.len() - 1 && (samples[i + 1].0 as f64) <= time {
+            i += 1;
+        }
+
+        let (t0, v0) = samples[i];
+        let (t1, v1) = samples[i + 1];
+
+        let value = if t1 == t0 {
+            v1
+        } else {
+            v0 + (v1 - v0) * (time - t0 as f64) / (t1 - t0) as f64
+        };
+
+        values.push(value);
+    }
+
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resample_uniform_interpolates_between_samples() {
+        let samples = [(0, 0.0), (1000, 10.0), (3000, 30.0)];
+
+        assert_eq!(
+            resample_uniform(&samples, 2.0).unwrap(),
+            vec![0.0, 5.0, 10.0, 15.0, 20.0, 25.0, 30.0]
+        );
+    }
+
+    #[test]
+    fn resample_uniform_stops_at_the_last_whole_period() {
+        let samples = [(0, 0.0), (250, 1.0)];
+
+        assert_eq!(resample_uniform(&samples, 10.0).unwrap(), vec![0.0, 0.4, 0.8]);
+    }
+
+    #[test]
+    fn resample_uniform_rejects_fewer_than_two_samples() {
+        assert!(resample_uniform(&[], 10.0).is_err());
+        assert!(resample_uniform(&[(0, 1.0)], 10.0).is_err());
+    }
+
+    #[test]
+    fn resample_uniform_rejects_invalid_sample_rates() {
+        let samples = [(0, 0.0), (1000, 1.0)];
+
+        assert!(resample_uniform(&samples, 0.0).is_err());
+        assert!(resample_uniform(&samples, f64::NAN).is_err());
+    }
+}
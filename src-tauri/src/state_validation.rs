@@ -0,0 +1,347 @@
+use crate::channel_filters::{check_filter_settings, filter_output_column};
+use crate::dataframe_handlers::cached_csv_schema;
+use crate::global_state::{AppState, AppStateField, ColumnPrecision, DetectionRuleKind, LoadCsvSettings};
+use crate::time_zones::parse_time_zone;
+use serde::Serialize;
+use std::path::Path;
+use strum::IntoEnumIterator;
+use tauri::AppHandle;
+
+/// Most decimal places a ScaledInteger column can have before 10^decimal_places stops fitting in an Int32
+const MAX_SCALED_DECIMAL_PLACES: u8 = 9;
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Structs
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+/// One problem with one field, so the frontend can show it next to the input it came from.
+#[derive(Serialize, Clone, Debug)]
+pub struct FieldError {
+    /// kebab-case name of the AppStateField, the same as in its "state-change--*" event
+    field: String,
+    /// Which part of the value is wrong, like "load_cols[2]" or "time_bounds". None when it's the value as a whole.
+    path: Option<String>,
+    message: String,
+}
+
+/// Error returned by set_app_state_field and set_app_state_fields. The message sums up everything in field_errors, so it can be shown as
+/// is. field_errors is empty when the request couldn't be read at all, or the state failed to update after passing validation.
+#[derive(Serialize, Debug)]
+pub struct ValidationError {
+    message: String,
+    field_errors: Vec<FieldError>,
+}
+
+impl From<String> for ValidationError {
+    fn from(message: String) -> Self {
+        ValidationError {
+            message,
+            field_errors: Vec::new(),
+        }
+    }
+}
+
+impl FieldError {
+    fn new(field: &AppStateField, path: Option<String>, message: impl Into<String>) -> Self {
+        FieldError {
+            field: field.to_string(),
+            path,
+            message: message.into(),
+        }
+    }
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Utility Functions
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Validate App State Fields
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Checks fields that are about to be set, given the state they would produce once they all are. Only the fields being set are checked,
+/// against the file system and the CSV's schema where that applies, so a change to one field never fails because of another field that was
+/// already stored. Setting the same field twice in one batch is refused since only the last one would count.
+pub fn validate_app_state_fields(
+    app: &AppHandle,
+    candidate: &AppState,
+    app_state_fields: &[AppStateField],
+) -> Result<(), ValidationError> {
+    let mut field_errors = Vec::new();
+
+    for (index, app_state_field) in app_state_fields.iter().enumerate() {
+        let field_name = app_state_field.to_string();
+        if app_state_fields[..index].iter().any(|earlier| earlier.to_string() == field_name) {
+            field_errors.push(FieldError::new(app_state_field, None, "The field was given more than once."));
+            continue;
+        }
+
+        field_errors.extend(validate_app_state_field(app, candidate, app_state_field));
+    }
+
+    if field_errors.is_empty() {
+        return Ok(());
+    }
+
+    let message = field_errors
+        .iter()
+        .map(|error| match error.path {
+            Some(ref path) => format!("{} ({}): {}", error.field, path, error.message),
+            None => format!("{}: {}", error.field, error.message),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    Err(ValidationError {
+        message: format!("Invalid app state:\n{}", message),
+        field_errors,
+    })
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Validate App State
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Checks every field of a whole AppState, like one read from a .crm file, the same way they would be checked if they were all set at once.
+pub fn validate_app_state(app: &AppHandle, app_state: &AppState) -> Result<(), ValidationError> {
+    let app_state_fields: Vec<AppStateField> = AppStateField::iter()
+        .map(|default_app_state_field| app_state.get_field(default_app_state_field))
+        .collect();
+
+    validate_app_state_fields(app, app_state, &app_state_fields)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Validate App State Field
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Everything wrong with one field. Fields with nothing that can be checked beyond deserializing are always fine.
+fn validate_app_state_field(app: &AppHandle, candidate: &AppState, app_state_field: &AppStateField) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    let mut error = |path: Option<String>, message: String| errors.push(FieldError::new(app_state_field, path, message));
+
+    match app_state_field {
+        AppStateField::SaveFilePath { value: Some(save_file_path) } => {
+            // The save file itself doesn't have to exist yet, only somewhere to put it
+            let path: &Path = save_file_path.as_ref();
+            if !path.parent().is_some_and(|parent| parent.as_os_str().is_empty() || parent.is_dir()) {
+                error(None, format!("The folder for {} doesn't exist.", path.display()));
+            }
+        }
+        AppStateField::CsvFilePath { value: Some(csv_file_path) } => {
+            let path: &Path = csv_file_path.as_ref();
+            if !path.is_file() {
+                error(None, format!("{} doesn't exist.", path.display()));
+            }
+        }
+        AppStateField::LoadCsvSettings { value: Some(load_csv_settings) } => {
+            for (path, message) in load_csv_settings_errors(app, candidate, load_csv_settings) {
+                error(path, message);
+            }
+        }
+        AppStateField::VideoFilePath { value: Some(video_file_path) } => {
+            let path: &Path = video_file_path.as_ref();
+            if !path.is_file() {
+                error(None, format!("{} doesn't exist.", path.display()));
+            }
+        }
+        AppStateField::VideoTimeScale { value: Some(video_time_scale) }
+            if !video_time_scale.is_finite() || *video_time_scale <= 0.0 =>
+        {
+            error(None, format!("The video time scale has to be above 0, not {}.", video_time_scale));
+        }
+        AppStateField::VideoTimeZone { value: Some(time_zone) } => {
+            if let Err(message) = parse_time_zone(time_zone.as_ref()) {
+                error(None, message);
+            }
+        }
+        AppStateField::DisplayTimeZone { value: Some(time_zone) } => {
+            if let Err(message) = parse_time_zone(time_zone.as_ref()) {
+                error(None, message);
+            }
+        }
+        AppStateField::Annotations { value } => {
+            for (index, annotation) in value.iter().enumerate() {
+                if annotation.end_time.is_some_and(|end_time| end_time < annotation.start_time) {
+                    error(Some(format!("[{}].end_time", index)), format!("\"{}\" ends before it starts.", annotation.title));
+                }
+                if value[..index].iter().any(|earlier| earlier.id == annotation.id) {
+                    error(Some(format!("[{}].id", index)), format!("Annotation id {} is used more than once.", annotation.id));
+                }
+            }
+        }
+        AppStateField::VideoSyncPoints { value } => {
+            for (index, sync_point) in value.iter().enumerate() {
+                if !sync_point.video_seconds.is_finite() || sync_point.video_seconds < 0.0 {
+                    error(
+                        Some(format!("[{}].video_seconds", index)),
+                        format!("The video time has to be 0 seconds or more, not {}.", sync_point.video_seconds),
+                    );
+                }
+                if let Some(earlier) = value[..index].iter().position(|earlier| earlier.video_seconds == sync_point.video_seconds) {
+                    error(
+                        Some(format!("[{}].video_seconds", index)),
+                        format!("Sync point {} is at the same time in the video as sync point {}.", index + 1, earlier + 1),
+                    );
+                }
+            }
+        }
+        AppStateField::DetectionRules { value } => {
+            for (index, rule) in value.iter().enumerate() {
+                if value[..index].iter().any(|earlier| earlier.id == rule.id) {
+                    error(Some(format!("[{}].id", index)), format!("Detection rule id {} is used more than once.", rule.id));
+                }
+
+                let numbers = match rule.kind {
+                    DetectionRuleKind::ThresholdCrossing { threshold, hysteresis, .. } => {
+                        vec![("threshold", threshold), ("hysteresis", hysteresis)]
+                    }
+                    DetectionRuleKind::RateOfChange { max_rate_per_second } => vec![("max_rate_per_second", max_rate_per_second)],
+                    DetectionRuleKind::HeldForDuration {
+                        threshold,
+                        min_duration_seconds,
+                        ..
+                    } => {
+                        if min_duration_seconds < 0.0 {
+                            error(
+                                Some(format!("[{}].kind.min_duration_seconds", index)),
+                                format!("\"{}\" can't have a negative minimum duration.", rule.name),
+                            );
+                        }
+                        vec![("threshold", threshold), ("min_duration_seconds", min_duration_seconds)]
+                    }
+                    DetectionRuleKind::StepChange { min_step } => vec![("min_step", min_step)],
+                };
+
+                for (name, number) in numbers {
+                    if !number.is_finite() {
+                        error(
+                            Some(format!("[{}].kind.{}", index, name)),
+                            format!("\"{}\" needs a number for {}, not {}.", rule.name, name, number),
+                        );
+                    }
+                }
+            }
+        }
+        AppStateField::ChannelFilters { value } => {
+            for (index, filter) in value.iter().enumerate() {
+                if let Err(message) = check_filter_settings(&filter.kind) {
+                    error(Some(format!("[{}].kind", index)), message);
+                }
+
+                // Disabled filters don't add a column, so their names can't clash with anything
+                if !filter.enabled {
+                    continue;
+                }
+
+                let output_column = filter_output_column(filter);
+
+                if value[..index]
+                    .iter()
+                    .any(|earlier| earlier.enabled && filter_output_column(earlier) == output_column)
+                {
+                    error(
+                        Some(format!("[{}].output_column", index)),
+                        format!("Another filter already outputs a channel named \"{}\".", output_column),
+                    );
+                } else if candidate.load_csv_settings.as_ref().is_some_and(|load_csv_settings| {
+                    load_csv_settings.datetime_index_col == output_column || load_csv_settings.load_cols.contains(&output_column)
+                }) {
+                    error(
+                        Some(format!("[{}].output_column", index)),
+                        format!("There is already a column named \"{}\".", output_column),
+                    );
+                }
+            }
+        }
+        AppStateField::TransferSettings { value } => {
+            for (column, precision) in &value.column_precision {
+                if let ColumnPrecision::ScaledInteger { decimal_places } = precision {
+                    if *decimal_places > MAX_SCALED_DECIMAL_PLACES {
+                        error(
+                            Some(format!("column_precision.{}", column)),
+                            format!("Scaled integers can have at most {} decimal places.", MAX_SCALED_DECIMAL_PLACES),
+                        );
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+
+    errors
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Load CSV Settings Errors
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Checks load settings against the CSV file they would be used with, which may be set in the same batch
+fn load_csv_settings_errors(
+    app: &AppHandle,
+    candidate: &AppState,
+    load_csv_settings: &LoadCsvSettings,
+) -> Vec<(Option<String>, String)> {
+    let mut errors = Vec::new();
+
+    if load_csv_settings.load_cols.contains(&load_csv_settings.datetime_index_col) {
+        errors.push((
+            Some("load_cols".to_string()),
+            format!("The datetime index column \"{}\" can't also be loaded as a data column.", load_csv_settings.datetime_index_col),
+        ));
+    }
+
+    if let Some(ref time_bounds) = load_csv_settings.time_bounds {
+        if let (Some(start_time), Some(end_time)) = (time_bounds.start_time, time_bounds.end_time) {
+            if start_time > end_time {
+                errors.push((Some("time_bounds".to_string()), "The start time is after the end time.".to_string()));
+            }
+        }
+    }
+
+    if let Some(ref resample) = load_csv_settings.resample {
+        if resample.period_ms == 0 {
+            errors.push((Some("resample.period_ms".to_string()), "The resampling period has to be above 0.".to_string()));
+        }
+    }
+
+    if let Some(ref time_zone) = load_csv_settings.time_zone {
+        if let Err(message) = parse_time_zone(time_zone) {
+            errors.push((Some("time_zone".to_string()), message));
+        }
+    }
+
+    let Some(ref csv_file_path) = candidate.csv_file_path else {
+        errors.push((None, "CSV loading settings can't be set without a CSV file path.".to_string()));
+        return errors;
+    };
+
+    let schema = match cached_csv_schema(app, csv_file_path.as_ref()) {
+        Ok(schema) => schema,
+        Err(message) => {
+            errors.push((None, format!("Couldn't read the CSV's columns: {}", message)));
+            return errors;
+        }
+    };
+
+    let in_schema = |column: &String| schema.iter().any(|field| &field.name == column);
+
+    if !in_schema(&load_csv_settings.datetime_index_col) {
+        errors.push((
+            Some("datetime_index_col".to_string()),
+            format!("Column \"{}\" isn't in the CSV.", load_csv_settings.datetime_index_col),
+        ));
+    }
+
+    for (index, column) in load_csv_settings.load_cols.iter().enumerate() {
+        if !in_schema(column) {
+            errors.push((Some(format!("load_cols[{}]", index)), format!("Column \"{}\" isn't in the CSV.", column)));
+        }
+    }
+
+    errors
+}
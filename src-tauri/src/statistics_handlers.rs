@@ -0,0 +1,377 @@
+use crate::dataframe_handlers::{
+    cached_display_data, column_samples, display_data_with_columns, filter_time_bounds, loaded_display_data, naive_datetime_from_millis,
+    scan_csv_data, scan_matches_display_data, select_display_data,
+};
+use crate::global_state::{snapshot_app_state, AppState, TimeBounds, TimeWindow};
+use crate::job_handlers::run_job;
+use chrono::NaiveDateTime;
+use polars::prelude::*;
+use serde::Serialize;
+use std::sync::RwLock;
+use tauri::{path::SafePathBuf, AppHandle, State};
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Structs
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+/// The quantiles reported for every column by get_column_statistics
+const STATISTICS_QUANTILES: [f64; 5] = [0.05, 0.25, 0.5, 0.75, 0.95];
+
+#[derive(Serialize)]
+pub struct Percentile {
+    quantile: f64,
+    value: Option<f64>,
+}
+
+/// Summary of one loaded column or filtered channel. Every value is None if the column has no data within the requested time window.
+#[derive(Serialize)]
+pub struct ColumnStatistics {
+    name: String,
+    /// Number of non-null values
+    count: usize,
+    null_count: usize,
+    min: Option<f64>,
+    max: Option<f64>,
+    mean: Option<f64>,
+    std: Option<f64>,
+    percentiles: Vec<Percentile>,
+    /// Timestamp of the first non-null value
+    first_time: Option<NaiveDateTime>,
+    /// Timestamp of the last non-null value
+    last_time: Option<NaiveDateTime>,
+    /// Average number of non-null values per second between first_time and last_time
+    sample_rate_hz: Option<f64>,
+}
+
+/// Measurements of a set of columns between two times, such as "average pressure while the valve was open".
+#[derive(Serialize)]
+pub struct WindowStatistics {
+    start_time: NaiveDateTime,
+    end_time: NaiveDateTime,
+    duration_seconds: f64,
+    columns: Vec<WindowColumnStatistics>,
+}
+
+/// Every value is None if the column has no data within the window.
+#[derive(Serialize)]
+pub struct WindowColumnStatistics {
+    name: String,
+    /// Number of non-null, finite values within the window
+    count: usize,
+    min: Option<f64>,
+    /// Timestamp of the first occurrence of the minimum
+    min_time: Option<NaiveDateTime>,
+    max: Option<f64>,
+    /// Timestamp of the first occurrence of the maximum
+    max_time: Option<NaiveDateTime>,
+    mean: Option<f64>,
+    /// Trapezoidal integral of the column over time, in the column's units multiplied by seconds
+    integral: Option<f64>,
+    /// Last value in the window minus the first value in the window
+    delta: Option<f64>,
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Tauri Commands
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Get Column Statistics
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Summarizes each loaded column and filtered channel without having to plot them. The statistics are taken from the display data (see
+/// cached_display_data), so they agree with get_window_statistics and the plot. They cover everything loaded, or only the given time_bounds
+/// within it. Everything is computed in a single lazy Polars query over that data. If the data hasn't been loaded yet and the CSV is used
+/// as it is (see scan_matches_display_data), the query runs on a scan of the file instead, so a large CSV never has to be read into memory.
+#[tauri::command]
+pub async fn get_column_statistics(
+    app: AppHandle,
+    state: State<'_, RwLock<AppState>>,
+    time_bounds: Option<TimeBounds>,
+) -> Result<Vec<ColumnStatistics>, String> {
+    let state = snapshot_app_state(&state);
+    let job_app = app.clone();
+
+    run_job(&app, "get_column_statistics", move |job| {
+        let load_csv_settings = state
+            .load_csv_settings
+            .as_ref()
+            .ok_or("CSV loading settings have not been set yet.")?;
+        let index_col = load_csv_settings.datetime_index_col.as_str();
+
+        let df = match loaded_display_data(&job_app, &state)? {
+            Some(df) => df,
+            None if scan_matches_display_data(&state) => {
+                let csv_file_path: SafePathBuf = state
+                    .csv_file_path
+                    .clone()
+                    .ok_or("CSV file path has not been set yet.")?
+                    .into();
+                let lf = scan_csv_data(
+                    csv_file_path,
+                    load_csv_settings,
+                    state.display_time_zone.as_ref().map(|time_zone| time_zone.as_ref()),
+                )?;
+
+                // This is a single Polars query, so there's nothing to report until it's done
+                job.progress("Computing statistics", Some(30.0), None);
+
+                return compute_column_statistics(
+                    filter_time_bounds(lf, index_col, time_bounds.as_ref()),
+                    index_col,
+                    &load_csv_settings.load_cols,
+                );
+            }
+            None => cached_display_data(&job_app, &state, job)?,
+        };
+
+        let columns: Vec<String> = df
+            .get_column_names()
+            .into_iter()
+            .filter(|name| name.as_str() != index_col)
+            .map(|name| name.to_string())
+            .collect();
+
+        let df = select_display_data(&df, index_col, &columns, time_bounds.as_ref())?;
+
+        job.checkpoint()?;
+        job.progress("Computing statistics", Some(70.0), Some(df.height()));
+
+        compute_column_statistics(df.lazy(), index_col, &columns)
+    })
+    .await
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Get Window Statistics
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Measures the given columns between two times, or across the time range of an annotation. The data is the display data from
+/// display_data_with_columns (timestamp policy, resampling, time zones, channel filters) so the numbers match what is plotted, and filtered
+/// channels can be measured like any other column. If no columns are given, the load_cols are used.
+#[tauri::command]
+pub async fn get_window_statistics(
+    app: AppHandle,
+    state: State<'_, RwLock<AppState>>,
+    window: TimeWindow,
+    columns: Vec<String>,
+) -> Result<WindowStatistics, String> {
+    let state = snapshot_app_state(&state);
+    let job_app = app.clone();
+
+    run_job(&app, "get_window_statistics", move |job| {
+        let load_csv_settings = state
+            .load_csv_settings
+            .as_ref()
+            .ok_or("CSV loading settings have not been set yet.")?;
+
+        let (start_time, end_time) = state.resolve_time_window(&window)?;
+        let time_bounds = TimeBounds {
+            start_time: Some(start_time),
+            end_time: Some(end_time),
+        };
+
+        let columns = if columns.is_empty() {
+            load_csv_settings.load_cols.clone()
+        } else {
+            columns
+        };
+
+        let df = display_data_with_columns(&job_app, &state, &columns, job)?;
+        let df = select_display_data(&df, &load_csv_settings.datetime_index_col, &columns, Some(&time_bounds))?;
+
+        job.checkpoint()?;
+        job.progress("Computing statistics", Some(70.0), Some(df.height()));
+
+        let columns = columns
+            .iter()
+            .map(|col_name| {
+                let samples = column_samples(&df, &load_csv_settings.datetime_index_col, col_name)?;
+                Ok(compute_window_column_statistics(col_name, &samples))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(WindowStatistics {
+            start_time,
+            end_time,
+            duration_seconds: (end_time - start_time).num_milliseconds() as f64 / 1000.0,
+            columns,
+        })
+    })
+    .await
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Utility Functions
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Compute Column Statistics
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Builds every statistic for every one of the columns into one lazy query, and unpacks the single row result.
+fn compute_column_statistics(lf: LazyFrame, index_col: &str, columns: &[String]) -> Result<Vec<ColumnStatistics>, String> {
+    // Every expression is keyed by the position of its column so that odd column names can't collide
+    let mut exprs = Vec::new();
+    for (i, col_name) in columns.iter().enumerate() {
+        let non_null_times = col(index_col).filter(col(col_name).is_not_null());
+
+        exprs.extend([
+            col(col_name).count().alias(format!("{i}_count")),
+            col(col_name).null_count().alias(format!("{i}_null_count")),
+            col(col_name).min().alias(format!("{i}_min")),
+            col(col_name).max().alias(format!("{i}_max")),
+            col(col_name).mean().alias(format!("{i}_mean")),
+            col(col_name).std(1).alias(format!("{i}_std")),
+            non_null_times.clone().min().alias(format!("{i}_first_time")),
+            non_null_times.max().alias(format!("{i}_last_time")),
+        ]);
+
+        for (j, quantile) in STATISTICS_QUANTILES.iter().enumerate() {
+            exprs.push(
+                col(col_name)
+                    .quantile(lit(*quantile), QuantileInterpolOptions::Linear)
+                    .alias(format!("{i}_quantile_{j}")),
+            );
+        }
+    }
+
+    let df = lf
+        .select(exprs)
+        .collect()
+        .map_err(|e| format!("Error computing column statistics: {}", e.to_string()))?;
+
+    let statistics = columns
+        .iter()
+        .enumerate()
+        .map(|(i, col_name)| {
+            let count = get_f64(&df, &format!("{i}_count")).unwrap_or(0.0) as usize;
+            let first_time = get_datetime(&df, &format!("{i}_first_time"));
+            let last_time = get_datetime(&df, &format!("{i}_last_time"));
+
+            ColumnStatistics {
+                name: col_name.clone(),
+                count,
+                null_count: get_f64(&df, &format!("{i}_null_count")).unwrap_or(0.0) as usize,
+                min: get_f64(&df, &format!("{i}_min")),
+                max: get_f64(&df, &format!("{i}_max")),
+                mean: get_f64(&df, &format!("{i}_mean")),
+                std: get_f64(&df, &format!("{i}_std")),
+                percentiles: STATISTICS_QUANTILES
+                    .iter()
+                    .enumerate()
+                    .map(|(j, quantile)| Percentile {
+                        quantile: *quantile,
+                        value: get_f64(&df, &format!("{i}_quantile_{j}")),
+                    })
+                    .collect(),
+                first_time,
+                last_time,
+                sample_rate_hz: sample_rate_hz(count, first_time, last_time),
+            }
+        })
+        .collect();
+
+    Ok(statistics)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Compute Window Column Statistics
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Computes everything in WindowColumnStatistics in a single pass over the (timestamp in milliseconds, value) samples of a column. NaN and
+/// infinite values are skipped like nulls, since one would otherwise make the mean, integral and possibly delta NaN or infinite, and NaN
+/// compares false with everything so the min and max would depend on where it fell.
+fn compute_window_column_statistics(name: &str, samples: &[(i64, f64)]) -> WindowColumnStatistics {
+    let samples: Vec<(i64, f64)> = samples.iter().copied().filter(|(_, value)| value.is_finite()).collect();
+
+    let mut min: Option<(i64, f64)> = None;
+    let mut max: Option<(i64, f64)> = None;
+    let mut sum = 0.0;
+    let mut integral = 0.0;
+
+    for (i, &(time, value)) in samples.iter().enumerate() {
+        if !matches!(min, Some((_, min_value)) if min_value <= value) {
+            min = Some((time, value));
+        }
+        if !matches!(max, Some((_, max_value)) if max_value >= value) {
+            max = Some((time, value));
+        }
+        sum += value;
+
+        if i > 0 {
+            let (previous_time, previous_value) = samples[i - 1];
+            integral += 0.5 * (value + previous_value) * (time - previous_time) as f64 / 1000.0;
+        }
+    }
+
+    let has_data = !samples.is_empty();
+
+    WindowColumnStatistics {
+        name: name.to_string(),
+        count: samples.len(),
+        min: min.map(|(_, value)| value),
+        min_time: min.and_then(|(time, _)| naive_datetime_from_millis(time)),
+        max: max.map(|(_, value)| value),
+        max_time: max.and_then(|(time, _)| naive_datetime_from_millis(time)),
+        mean: has_data.then(|| sum / samples.len() as f64),
+        integral: has_data.then_some(integral),
+        delta: samples
+            .first()
+            .zip(samples.last())
+            .map(|((_, first), (_, last))| last - first),
+    }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Get F64
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Pulls the first value out of a column of an aggregated DataFrame as an f64. Nulls, NaNs, and missing columns all come back as None.
+fn get_f64(df: &DataFrame, name: &str) -> Option<f64> {
+    df.column(name)
+        .ok()?
+        .get(0)
+        .ok()?
+        .extract::<f64>()
+        .filter(|value| !value.is_nan())
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Get Datetime
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Pulls the first value out of a millisecond Datetime column of an aggregated DataFrame.
+fn get_datetime(df: &DataFrame, name: &str) -> Option<NaiveDateTime> {
+    match df.column(name).ok()?.get(0).ok()? {
+        AnyValue::Datetime(milliseconds, TimeUnit::Milliseconds, _) => {
+            naive_datetime_from_millis(milliseconds)
+        }
+        _ => None,
+    }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Sample Rate Hz
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The effective sample rate is the number of sample intervals divided by the time they span.
+fn sample_rate_hz(
+    count: usize,
+    first_time: Option<NaiveDateTime>,
+    last_time: Option<NaiveDateTime>,
+) -> Option<f64> {
+    let span_seconds = (last_time? - first_time?).num_milliseconds() as f64 / 1000.0;
+
+    if count < 2 || span_seconds <= 0.0 {
+        return None;
+    }
+
+    Some((count - 1) as f64 / span_seconds)
+}
@@ -0,0 +1,108 @@
+use chrono::{LocalResult, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+use polars::prelude::*;
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Time Zone Handling
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// Time zones are strictly opt-in. Every datetime in the backend stays a NaiveDateTime, but if a data source (a CSV or the video) has been
+// given a time zone, its wall clock times are converted into the wall clock time of the display time zone before anything else touches them.
+// Sources without a time zone are left exactly as they are.
+
+/// The time zone that zoned sources are converted to when the user has not picked a display time zone.
+pub const DEFAULT_DISPLAY_TIME_ZONE: &str = "UTC";
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Parse Time Zone
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Parses an IANA time zone name, such as "America/New_York" or "UTC".
+pub fn parse_time_zone(name: &str) -> Result<Tz, String> {
+    name.parse::<Tz>()
+        .map_err(|e| format!("Unknown time zone \"{}\": {}", name, e))
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// To Display Time
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Converts a wall clock time recorded in the source time zone to the wall clock time in the display time zone.
+/// Times that happen twice during a DST fall back resolve to the earlier of the two. Times that are skipped by a DST spring forward are an error.
+pub fn to_display_time(
+    time: NaiveDateTime,
+    source_time_zone: Option<&str>,
+    display_time_zone: Option<&str>,
+) -> Result<NaiveDateTime, String> {
+    let Some(source_time_zone) = source_time_zone else {
+        return Ok(time);
+    };
+
+    let source_tz = parse_time_zone(source_time_zone)?;
+    let display_tz = parse_time_zone(display_time_zone.unwrap_or(DEFAULT_DISPLAY_TIME_ZONE))?;
+
+    let zoned_time = match source_tz.from_local_datetime(&time) {
+        LocalResult::Single(zoned_time) => zoned_time,
+        LocalResult::Ambiguous(earliest, _) => earliest,
+        LocalResult::None => {
+            return Err(format!(
+                "{} does not exist in the {} time zone because it falls in a daylight saving time gap.",
+                time, source_time_zone
+            ))
+        }
+    };
+
+    Ok(zoned_time.with_timezone(&display_tz).naive_local())
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// From Display Time
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The reverse of to_display_time, for turning a time the user picked off the plot back into the source's own wall clock time.
+pub fn from_display_time(
+    time: NaiveDateTime,
+    source_time_zone: Option<&str>,
+    display_time_zone: Option<&str>,
+) -> Result<NaiveDateTime, String> {
+    let Some(source_time_zone) = source_time_zone else {
+        return Ok(time);
+    };
+
+    to_display_time(
+        time,
+        Some(display_time_zone.unwrap_or(DEFAULT_DISPLAY_TIME_ZONE)),
+        Some(source_time_zone),
+    )
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// To Display Time Expression
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The Polars equivalent of to_display_time, for converting a whole naive Datetime column at once. The output column is still naive.
+pub fn to_display_time_expr(
+    expr: Expr,
+    source_time_zone: Option<&str>,
+    display_time_zone: Option<&str>,
+) -> Result<Expr, String> {
+    let Some(source_time_zone) = source_time_zone else {
+        return Ok(expr);
+    };
+
+    let display_time_zone = display_time_zone.unwrap_or(DEFAULT_DISPLAY_TIME_ZONE);
+
+    // Check the names up front, since the error from Polars doesn't show up until the query is collected
+    parse_time_zone(source_time_zone)?;
+    parse_time_zone(display_time_zone)?;
+
+    Ok(expr
+        .dt()
+        .replace_time_zone(Some(source_time_zone.into()), lit("earliest"), NonExistent::Raise)
+        .dt()
+        .convert_time_zone(display_time_zone.into())
+        .dt()
+        .replace_time_zone(None, lit("raise"), NonExistent::Raise))
+}
@@ -0,0 +1,150 @@
+use crate::global_state::{ColumnPrecision, TransferSettings};
+use polars::export::arrow::datatypes::{ArrowSchema, Metadata};
+use polars::export::arrow::io::ipc::write::{FileWriter, StreamWriter, WriteOptions};
+use polars::prelude::*;
+
+/// Arrow field metadata key holding the dtype the column has in the backend, before it was encoded for transfer
+const ORIGINAL_DTYPE_KEY: &str = "original_dtype";
+/// Arrow field metadata key holding the decimal places of a ScaledInteger column. Divide by 10^decimal_places to get the value back.
+const DECIMAL_PLACES_KEY: &str = "decimal_places";
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Utility Functions
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Write Transfer IPC
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Serializes a DataFrame to the Arrow IPC file format for sending to the frontend, encoding each column per the TransferSettings.
+/// Every field carries an "original_dtype" metadata entry, and ScaledInteger fields a "decimal_places" entry as well.
+pub fn write_transfer_ipc(df: &DataFrame, transfer_settings: &TransferSettings) -> Result<Vec<u8>, String> {
+    let (mut df, schema) = encode_for_transfer(df, transfer_settings)?;
+    let mut buffer = Vec::new();
+
+    // No compression, since the frontend's Arrow reader can't decompress IPC buffers
+    let mut writer = FileWriter::try_new(&mut buffer, Arc::new(schema), None, WriteOptions { compression: None })
+        .map_err(|e| format!("Error serializing DataFrame: {}", e.to_string()))?;
+
+    df.align_chunks();
+    for batch in df.iter_chunks(CompatLevel::newest(), true) {
+        writer
+            .write(&batch, None)
+            .map_err(|e| format!("Error serializing DataFrame: {}", e.to_string()))?;
+    }
+
+    writer
+        .finish()
+        .map_err(|e| format!("Error serializing DataFrame: {}", e.to_string()))?;
+
+    Ok(buffer)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Write Transfer IPC Stream
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Same as write_transfer_ipc, but in the Arrow IPC stream format, which is what stream_csv_data sends each chunk as.
+pub fn write_transfer_ipc_stream(df: &DataFrame, transfer_settings: &TransferSettings) -> Result<Vec<u8>, String> {
+    let (mut df, schema) = encode_for_transfer(df, transfer_settings)?;
+    let mut buffer = Vec::new();
+
+    let mut writer = StreamWriter::new(&mut buffer, WriteOptions { compression: None });
+
+    writer
+        .start(&schema, None)
+        .map_err(|e| format!("Error serializing DataFrame: {}", e.to_string()))?;
+
+    df.align_chunks();
+    for batch in df.iter_chunks(CompatLevel::newest(), true) {
+        writer
+            .write(&batch, None)
+            .map_err(|e| format!("Error serializing DataFrame: {}", e.to_string()))?;
+    }
+
+    writer
+        .finish()
+        .map_err(|e| format!("Error serializing DataFrame: {}", e.to_string()))?;
+
+    Ok(buffer)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Encode For Transfer
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Converts the columns listed in the column_precision to their transfer type, and builds the Arrow schema with the metadata describing
+/// what was done. Columns that are listed but weren't loaded are ignored, since the viewport often only asks for a few columns.
+fn encode_for_transfer(df: &DataFrame, transfer_settings: &TransferSettings) -> Result<(DataFrame, ArrowSchema), String> {
+    let mut columns = Vec::with_capacity(df.width());
+    let mut fields = Vec::with_capacity(df.width());
+
+    for column in df.get_columns() {
+        let precision = transfer_settings
+            .column_precision
+            .get(column.name().as_str())
+            .copied()
+            .unwrap_or_default();
+
+        let encoded = match precision {
+            ColumnPrecision::Float64 => column.clone(),
+            _ if column.dtype() != &DataType::Float64 => {
+                return Err(format!(
+                    "Column \"{}\" is {}, so its transfer precision can't be changed. Only Float64 columns can be.",
+                    column.name(),
+                    column.dtype()
+                ))
+            }
+            ColumnPrecision::Float32 => column
+                .cast(&DataType::Float32)
+                .map_err(|e| format!("Error converting \"{}\" to Float32: {}", column.name(), e.to_string()))?,
+            ColumnPrecision::ScaledInteger { decimal_places } => {
+                let scale = 10f64.powi(decimal_places as i32);
+                let values = column
+                    .f64()
+                    .map_err(|e| format!("Error reading column \"{}\": {}", column.name(), e.to_string()))?;
+
+                // NaN and infinity have no Int32 to stand for them, so they go as nulls like any other missing value. Anything else that
+                // doesn't fit would be silently lost the same way, so that is an error instead.
+                let scaled = values.apply_values(|value| (value * scale).round());
+                let out_of_range = scaled
+                    .iter()
+                    .flatten()
+                    .filter(|scaled| scaled.is_finite() && scaled.abs() > i32::MAX as f64)
+                    .count();
+                if out_of_range > 0 {
+                    return Err(format!(
+                        "{} values in column \"{}\" are too big to send with {} decimal places. Use fewer decimal places or Float32.",
+                        out_of_range,
+                        column.name(),
+                        decimal_places
+                    ));
+                }
+
+                scaled
+                    .iter()
+                    .map(|scaled| scaled.filter(|scaled| scaled.is_finite()).map(|scaled| scaled as i32))
+                    .collect::<Int32Chunked>()
+                    .with_name(column.name().clone())
+                    .into_series()
+            }
+        };
+
+        let mut field = encoded.field().to_arrow(CompatLevel::newest());
+        field.metadata = Metadata::from([(ORIGINAL_DTYPE_KEY.into(), column.dtype().to_string().into())]);
+        if let ColumnPrecision::ScaledInteger { decimal_places } = precision {
+            field
+                .metadata
+                .insert(DECIMAL_PLACES_KEY.into(), decimal_places.to_string().into());
+        }
+
+        columns.push(encoded);
+        fields.push(field);
+    }
+
+    let df = DataFrame::new(columns).map_err(|e| format!("Error encoding DataFrame: {}", e.to_string()))?;
+
+    Ok((df, ArrowSchema::from_iter(fields)))
+}
@@ -0,0 +1,304 @@
+use crate::global_state::{load_app_state, AppState, LoadCsvSettings};
+use crate::state_validation::ValidationError;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, RwLock},
+};
+use tauri::{path::SafePathBuf, AppHandle, Emitter, Manager, State};
+
+/// Most files kept in the recent files list. Opening one more drops the one opened longest ago.
+const MAX_RECENT_FILES: usize = 20;
+/// Name of the preferences file inside the app's config directory
+const PREFERENCES_FILE_NAME: &str = "preferences.json";
+/// Rows the plot asks get_viewport_data for until the user picks something else
+const DEFAULT_MAX_POINTS: usize = 4000;
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Structs
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+/// Settings that belong to the user rather than to a session. They are kept in the OS config directory instead of the .crm file, so they
+/// are there on every launch and clear_app_state doesn't touch them.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct UserPreferences {
+    /// Most recently opened first
+    #[serde(default)]
+    pub recent_files: Vec<RecentFile>,
+    #[serde(default)]
+    pub load_csv_templates: Vec<LoadCsvTemplate>,
+    /// Name of the template to fill the load settings in with when a CSV is picked. None leaves them to the user.
+    #[serde(default)]
+    pub default_load_csv_template: Option<String>,
+    #[serde(default)]
+    pub theme: Theme,
+    #[serde(default)]
+    pub downsampling: DownsamplingPreferences,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct RecentFile {
+    pub path: PathBuf,
+    pub kind: RecentFileKind,
+    pub last_opened: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecentFileKind {
+    /// A .crm save file
+    Session,
+    Csv,
+    Video,
+}
+
+/// LoadCsvSettings saved under a name, for CSVs that always come out of the same logger. They are only checked against a CSV when applied
+/// through set_app_state_fields.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct LoadCsvTemplate {
+    pub name: String,
+    pub settings: LoadCsvSettings,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Theme {
+    #[default]
+    Light,
+    Dark,
+}
+
+/// How the plot loads the data. With downsampling enabled it gets the whole time range from get_viewport_data, cut down to max_points rows.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct DownsamplingPreferences {
+    /// When false the plot gets every row from get_csv_data
+    pub enabled: bool,
+    pub max_points: usize,
+}
+
+impl Default for DownsamplingPreferences {
+    fn default() -> Self {
+        DownsamplingPreferences {
+            enabled: true,
+            max_points: DEFAULT_MAX_POINTS,
+        }
+    }
+}
+
+/// Holds the UserPreferences, managed by Tauri alongside the AppState. A Mutex rather than an RwLock since every change is written to disk
+/// while it's held, which keeps the file in step with what's in memory.
+pub struct UserPreferencesStore {
+    preferences: Mutex<UserPreferences>,
+}
+
+impl UserPreferencesStore {
+    /// Reads the preferences file, or starts from the defaults if there isn't one yet. A file that can't be read is moved aside to
+    /// preferences.json.bak rather than overwritten, so the user's templates aren't lost to a bad edit.
+    pub fn load(app: &AppHandle) -> Self {
+        let preferences = preferences_file_path(app)
+            .ok()
+            .filter(|path| path.is_file())
+            .map(|path| {
+                read_preferences_file(&path).unwrap_or_else(|_| {
+                    let _ = fs::rename(&path, path.with_extension("json.bak"));
+                    UserPreferences::default()
+                })
+            })
+            .unwrap_or_default();
+
+        UserPreferencesStore {
+            preferences: Mutex::new(preferences),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, UserPreferences> {
+        self.preferences.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Tauri Commands
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Get User Preferences
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+#[tauri::command]
+pub async fn get_user_preferences(store: State<'_, UserPreferencesStore>) -> Result<UserPreferences, String> {
+    Ok(store.lock().clone())
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Update User Preferences
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Changes some of the preferences. changes is an object with any of the keys of UserPreferences, and each one given replaces that
+/// preference whole, so e.g. {"recent_files": []} clears the recent files and {"theme": "dark"} leaves everything but the theme alone.
+/// The preferences are saved and sent to every window in a "user-preferences-change" event, and the updated preferences are returned.
+#[tauri::command]
+pub async fn update_user_preferences(
+    app: AppHandle,
+    store: State<'_, UserPreferencesStore>,
+    changes: Value,
+) -> Result<UserPreferences, String> {
+    let Value::Object(changes) = changes else {
+        return Err(format!("Preference changes have to be an object, not {}", changes));
+    };
+
+    let preferences = store.lock();
+
+    let mut merged = serde_json::to_value(&*preferences).map_err(|err| format!("Failed to serialize the preferences: {}", err))?;
+    if let Value::Object(ref mut merged) = merged {
+        for (key, value) in changes {
+            if !merged.contains_key(&key) {
+                return Err(format!("There is no preference called \"{}\".", key));
+            }
+            merged.insert(key, value);
+        }
+    }
+
+    let updated: UserPreferences =
+        serde_json::from_value(merged).map_err(|err| format!("Failed to deserialize the preferences: {}", err))?;
+    validate_user_preferences(&updated)?;
+
+    save_user_preferences(&app, preferences, updated)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Open Recent Session
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Loads a .crm file from the recent files, the same as load_app_state_from_file. If the file has been moved or deleted since, it's taken
+/// off the recent files and an error says so.
+#[tauri::command]
+pub async fn open_recent_session(
+    app: AppHandle,
+    state: State<'_, RwLock<AppState>>,
+    store: State<'_, UserPreferencesStore>,
+    file: SafePathBuf,
+) -> Result<(), ValidationError> {
+    let path: &Path = file.as_ref();
+
+    if !path.is_file() {
+        let preferences = store.lock();
+        let mut updated = preferences.clone();
+        updated.recent_files.retain(|recent_file| recent_file.path != path);
+        save_user_preferences(&app, preferences, updated)?;
+
+        return Err(format!("{} no longer exists, so it was removed from the recent files.", path.display()).into());
+    }
+
+    load_app_state(&app, &state, file)
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Utility Functions
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Remember Recent File
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Moves a file to the top of the recent files, adding it if it isn't there. Called whenever a session is loaded or saved and whenever a CSV
+/// or video file is set. The file has already been opened by then, so that isn't failed over the list not being saved.
+pub fn remember_recent_file(app: &AppHandle, kind: RecentFileKind, path: &Path) {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+    let store = app.state::<UserPreferencesStore>();
+    let preferences = store.lock();
+    let mut updated = preferences.clone();
+
+    updated.recent_files.retain(|recent_file| recent_file.path != path);
+    updated.recent_files.insert(
+        0,
+        RecentFile {
+            path,
+            kind,
+            last_opened: Utc::now(),
+        },
+    );
+    updated.recent_files.truncate(MAX_RECENT_FILES);
+
+    let _ = save_user_preferences(app, preferences, updated);
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Save User Preferences
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Writes the updated preferences to disk and only then replaces the ones in memory, so a failed write changes nothing. Then lets every
+/// window know.
+fn save_user_preferences(
+    app: &AppHandle,
+    mut preferences: MutexGuard<'_, UserPreferences>,
+    updated: UserPreferences,
+) -> Result<UserPreferences, String> {
+    let path = preferences_file_path(app)?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|err| format!("Failed to create {}: {}", parent.display(), err))?;
+    }
+
+    // Write to a temporary file first, so the preferences file is never left half written
+    let json = serde_json::to_string_pretty(&updated).map_err(|err| format!("Serialization error: {}", err))?;
+    let temporary_path = path.with_extension("json.tmp");
+    fs::write(&temporary_path, json).map_err(|err| format!("Failed to write to file: {}", err))?;
+    fs::rename(&temporary_path, &path).map_err(|err| format!("Failed to write to file: {}", err))?;
+
+    *preferences = updated.clone();
+
+    app.emit("user-preferences-change", updated.clone())
+        .map_err(|err| format!("Failed to emit the user preferences: {}", err))?;
+
+    Ok(updated)
+}
+
+fn validate_user_preferences(preferences: &UserPreferences) -> Result<(), String> {
+    for (index, template) in preferences.load_csv_templates.iter().enumerate() {
+        if template.name.trim().is_empty() {
+            return Err("CSV loading templates need a name.".to_string());
+        }
+        if preferences.load_csv_templates[..index].iter().any(|earlier| earlier.name == template.name) {
+            return Err(format!("There is more than one CSV loading template called \"{}\".", template.name));
+        }
+    }
+
+    if let Some(ref name) = preferences.default_load_csv_template {
+        if !preferences.load_csv_templates.iter().any(|template| &template.name == name) {
+            return Err(format!("There is no CSV loading template called \"{}\" to use as the default.", name));
+        }
+    }
+
+    if preferences.downsampling.max_points < 2 {
+        return Err("Downsampling has to keep at least 2 points.".to_string());
+    }
+
+    if preferences.recent_files.len() > MAX_RECENT_FILES {
+        return Err(format!("At most {} recent files are kept.", MAX_RECENT_FILES));
+    }
+
+    Ok(())
+}
+
+fn preferences_file_path(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_config_dir()
+        .map(|config_dir| config_dir.join(PREFERENCES_FILE_NAME))
+        .map_err(|err| format!("Failed to find the config directory: {}", err))
+}
+
+fn read_preferences_file(path: &Path) -> Result<UserPreferences, String> {
+    let json = fs::read_to_string(path).map_err(|err| format!("Failed to open file: {}", err))?;
+    serde_json::from_str(&json).map_err(|err| format!("Failed to deserialize JSON: {}", err))
+}
@@ -1,11 +1,400 @@
-use tauri::{AppHandle, Emitter};
+use crate::dataframe_handlers::{column_samples, display_data_with_columns, select_display_data};
+use crate::global_state::{
+    read_app_state, snapshot_app_state, update_app_state_fields, write_app_state, AppState, AppStateField, TimeBounds, TimeWindow,
+    VideoFilePath, VideoSyncPoint,
+};
+use crate::job_handlers::run_job;
+use crate::mp4_clip::extract_clip;
+use crate::time_zones::from_display_time;
+use crate::video_overlay::{
+    build_overlay_script, probe_video, render_overlay, OverlayCaption, OverlayChannel, OverlayOptions, OverlayTiming,
+};
+use chrono::{NaiveDateTime, TimeDelta};
+use serde::Serialize;
+use std::sync::RwLock;
+use tauri::{path::SafePathBuf, AppHandle, Emitter, State};
+
+/// Payload of the "video-time-change" event
+#[derive(Serialize, Clone, Debug)]
+pub struct VideoTimeChange {
+    video_seconds: f64,
+    /// In the display time zone, with the clock scale applied. None if the video start time hasn't been set.
+    display_time: Option<NaiveDateTime>,
+}
+
+/// How the sync points were turned into a video start time and clock scale.
+#[derive(Serialize, Debug)]
+pub struct VideoSyncSolution {
+    /// In the video's own time zone, the same as the video_start_time field
+    video_start_time: Option<NaiveDateTime>,
+    video_time_scale: Option<f64>,
+    /// For each sync point, how many seconds its data time is from where the solved model puts it. These are only non-zero with three or
+    /// more points, and a big one usually means a point was picked wrong.
+    residuals_seconds: Vec<f64>,
+}
+
+/// The part of the video that actually ended up in an exported clip. For a clip copied without re-encoding, the start is usually a little
+/// before what was asked for, since it has to start on a keyframe.
+#[derive(Serialize, Debug)]
+pub struct VideoClipResult {
+    start_time: NaiveDateTime,
+    end_time: NaiveDateTime,
+    start_video_seconds: f64,
+    end_video_seconds: f64,
+}
 
 /// This gets called by the video component when the video time has updated. The video is constantly polled to determine the current video time
 /// If the time has changed, this function is invoked on the front end. We will use a global emitter here since the Plotter component may be on a
-/// different window, so we need to be able to communicate with it regardless. The video time is delivered as a time in seconds from the beginning
-/// of the video.
+/// different window, so we need to be able to communicate with it regardless. The video time is given in seconds from the beginning of the
+/// video, and is sent on together with the display time it lines up with, so that listeners don't have to redo the time zone and clock scale
+/// conversion themselves.
+#[tauri::command]
+pub async fn emit_video_time_change(
+    app: AppHandle,
+    state: State<'_, RwLock<AppState>>,
+    video_time: f64,
+) -> Result<(), String> {
+    let display_time = read_app_state(&state).video_seconds_to_display_time(video_time)?;
+
+    app.emit(
+        "video-time-change",
+        VideoTimeChange {
+            video_seconds: video_time,
+            display_time,
+        },
+    )
+    .map_err(|e| format!("Failed to emit event: {:?}", e))
+}
+
+/// Returns the video start time converted to the display time zone, which is what needs to be lined up against the plotted data.
 #[tauri::command]
-pub async fn emit_video_time_change(app: AppHandle, video_time: f64) -> Result<(), String> {
-    app.emit("video-time-change", video_time)
-        .map_err(|e| format!("Failed to emit event: {:?}", e))
+pub async fn get_display_video_start_time(
+    state: State<'_, RwLock<AppState>>,
+) -> Result<Option<NaiveDateTime>, String> {
+    let state = read_app_state(&state);
+
+    state.display_video_start_time()
+}
+
+/// Stores the sync points and solves the video start time from them. One point only shifts the video, two or more also fit the clock
+/// scale between the camera and the data logger (by least squares when there are more than two). The points, start time, and scale are all
+/// updated together. With no points the current start time and scale are left as they are.
+#[tauri::command]
+pub async fn set_video_sync_points(
+    app: AppHandle,
+    state: State<'_, RwLock<AppState>>,
+    sync_points: Vec<VideoSyncPoint>,
+) -> Result<VideoSyncSolution, String> {
+    let state = write_app_state(&state);
+
+    if sync_points.is_empty() {
+        let solution = VideoSyncSolution {
+            video_start_time: state.video_start_time,
+            video_time_scale: state.video_time_scale,
+            residuals_seconds: Vec::new(),
+        };
+
+        update_app_state_fields(&app, state, vec![AppStateField::VideoSyncPoints { value: sync_points }])?;
+
+        return Ok(solution);
+    }
+
+    let (display_video_start_time, video_time_scale) = solve_video_sync(&sync_points)?;
+    let scale = video_time_scale.unwrap_or(1.0);
+
+    let residuals_seconds = sync_points
+        .iter()
+        .map(|sync_point| {
+            let data_seconds = (sync_point.data_time - display_video_start_time).num_milliseconds() as f64 / 1000.0;
+            data_seconds - sync_point.video_seconds * scale
+        })
+        .collect();
+
+    // The sync points are in display time, but the video start time is stored in the video's time zone
+    let video_start_time = from_display_time(
+        display_video_start_time,
+        state.video_time_zone.as_ref().map(|time_zone| time_zone.as_ref()),
+        state.display_time_zone.as_ref().map(|time_zone| time_zone.as_ref()),
+    )?;
+
+    update_app_state_fields(
+        &app,
+        state,
+        vec![
+            AppStateField::VideoSyncPoints { value: sync_points },
+            AppStateField::VideoStartTime { value: Some(video_start_time) },
+            AppStateField::VideoTimeScale { value: video_time_scale },
+        ],
+    )?;
+
+    Ok(VideoSyncSolution {
+        video_start_time: Some(video_start_time),
+        video_time_scale,
+        residuals_seconds,
+    })
+}
+
+/// Fits data_time = video_start_time + video_seconds * scale. Returns the start time in display time, and the scale if there was more than
+/// one point to fit it from. Times are taken relative to the first point so the fit isn't done on huge millisecond counts.
+fn solve_video_sync(sync_points: &[VideoSyncPoint]) -> Result<(NaiveDateTime, Option<f64>), String> {
+    let reference_time = sync_points[0].data_time;
+
+    let points: Vec<(f64, f64)> = sync_points
+        .iter()
+        .map(|sync_point| {
+            let data_seconds = (sync_point.data_time - reference_time).num_milliseconds() as f64 / 1000.0;
+            (sync_point.video_seconds, data_seconds)
+        })
+        .collect();
+
+    let count = points.len() as f64;
+    let mean_video = points.iter().map(|(video, _)| video).sum::<f64>() / count;
+    let mean_data = points.iter().map(|(_, data)| data).sum::<f64>() / count;
+
+    let video_time_scale = if points.len() == 1 {
+        None
+    } else {
+        let covariance: f64 = points.iter().map(|(video, data)| (video - mean_video) * (data - mean_data)).sum();
+        let variance: f64 = points.iter().map(|(video, _)| (video - mean_video).powi(2)).sum();
+
+        if variance < 1e-9 {
+            return Err("Sync points need to be at different times in the video to solve for clock drift.".to_string());
+        }
+
+        let scale = covariance / variance;
+
+        if scale <= 0.0 {
+            return Err(format!(
+                "The sync points solve to a clock scale of {}, which would run the video backwards. Check that the points are paired up correctly.",
+                scale
+            ));
+        }
+
+        Some(scale)
+    };
+
+    let start_seconds = mean_data - mean_video * video_time_scale.unwrap_or(1.0);
+
+    Ok((
+        reference_time + TimeDelta::milliseconds((start_seconds * 1000.0).round() as i64),
+        video_time_scale,
+    ))
+}
+
+/// Writes the part of the video covering a time range or annotation to a new MP4. Nothing is re-encoded, the samples are copied straight
+/// across, so this is fast and lossless but the clip has to start on a keyframe.
+#[tauri::command]
+pub async fn export_video_clip(
+    app: AppHandle,
+    state: State<'_, RwLock<AppState>>,
+    video_file_path: VideoFilePath,
+    window: TimeWindow,
+    output_path: SafePathBuf,
+) -> Result<VideoClipResult, String> {
+    let state = snapshot_app_state(&state);
+
+    run_job(&app, "export_video_clip", move |job| {
+        let (start_time, end_time) = state.resolve_time_window(&window)?;
+
+        let no_start_time = "The video start time has to be set before a clip can be exported.";
+        let start_video_seconds = state.display_time_to_video_seconds(start_time)?.ok_or(no_start_time)?;
+        let end_video_seconds = state.display_time_to_video_seconds(end_time)?.ok_or(no_start_time)?;
+
+        job.progress("Copying video samples", None, None);
+
+        let video_file_path = SafePathBuf::from(video_file_path);
+        let bounds = extract_clip(
+            video_file_path.as_ref(),
+            output_path.as_ref(),
+            start_video_seconds,
+            end_video_seconds,
+        )?;
+
+        Ok(VideoClipResult {
+            start_time: state.video_seconds_to_display_time(bounds.start_seconds)?.ok_or(no_start_time)?,
+            end_time: state.video_seconds_to_display_time(bounds.end_seconds)?.ok_or(no_start_time)?,
+            start_video_seconds: bounds.start_seconds,
+            end_video_seconds: bounds.end_seconds,
+        })
+    })
+    .await
+}
+
+/// Renders a copy of the video covering a time range or annotation (or the whole video if no window is given) with the data burned in: the
+/// timestamp and channel values, plus annotation captions and a scrolling plot if the options ask for them. The channels are the plotted ones,
+/// including the outputs of any channel filters, unless the options pick some. The frames are decoded, drawn on, and re-encoded by the ffmpeg
+/// sidecar. This runs as a job, with ffmpeg's progress reported as the job's, and cancelling it kills ffmpeg.
+#[tauri::command]
+pub async fn export_overlay_video(
+    app: AppHandle,
+    state: State<'_, RwLock<AppState>>,
+    video_file_path: VideoFilePath,
+    window: Option<TimeWindow>,
+    output_path: SafePathBuf,
+    options: OverlayOptions,
+) -> Result<VideoClipResult, String> {
+    // Works from a snapshot, since ffmpeg can take minutes and nothing else could touch the state in the meantime
+    let state = snapshot_app_state(&state);
+    let job_app = app.clone();
+
+    run_job(&app, "export_overlay_video", move |job| {
+        let no_start_time = "The video start time has to be set before an overlay video can be exported.";
+        let display_video_start_time = state.display_video_start_time()?.ok_or(no_start_time)?;
+        let video_time_scale = state.video_time_scale();
+
+        let window_video_seconds = match window {
+            Some(ref window) => {
+                let (start_time, end_time) = state.resolve_time_window(window)?;
+                Some((
+                    state.display_time_to_video_seconds(start_time)?.ok_or(no_start_time)?,
+                    state.display_time_to_video_seconds(end_time)?.ok_or(no_start_time)?,
+                ))
+            }
+            None => None,
+        };
+
+        let index_col = state
+            .load_csv_settings
+            .as_ref()
+            .ok_or("CSV loading settings have not been set yet.")?
+            .datetime_index_col
+            .as_str();
+
+        let captions: Vec<OverlayCaption> = state
+            .annotations
+            .iter()
+            .map(|annotation| OverlayCaption {
+                text: annotation.title.clone(),
+                start_millis: annotation.start_time.and_utc().timestamp_millis(),
+                end_millis: annotation.end_time.map(|end_time| end_time.and_utc().timestamp_millis()),
+            })
+            .collect();
+
+        job.progress("Reading video", None, None);
+
+        let video_file_path = SafePathBuf::from(video_file_path);
+        let probe = probe_video(job, video_file_path.as_ref())?;
+
+        let (start_video_seconds, end_video_seconds) = window_video_seconds.unwrap_or((0.0, probe.duration_seconds));
+        let start_video_seconds = start_video_seconds.clamp(0.0, probe.duration_seconds);
+        let end_video_seconds = end_video_seconds.clamp(0.0, probe.duration_seconds);
+
+        if end_video_seconds <= start_video_seconds {
+            return Err("The window doesn't overlap the video.".to_string());
+        }
+
+        let to_display_time = |video_seconds: f64| {
+            display_video_start_time + TimeDelta::milliseconds((video_seconds * video_time_scale * 1000.0).round() as i64)
+        };
+        let start_time = to_display_time(start_video_seconds);
+        let end_time = to_display_time(end_video_seconds);
+
+        job.checkpoint()?;
+        job.progress("Reading CSV", None, None);
+
+        // The whole display data is sliced rather than reading just the clip, so the filtered channels match the plot instead of having
+        // their own edge transients at the start of the clip
+        let df = display_data_with_columns(&job_app, &state, &options.columns, job)?;
+
+        let column_names: Vec<String> = if options.columns.is_empty() {
+            df.get_column_names()
+                .into_iter()
+                .filter(|name| name.as_str() != index_col)
+                .map(|name| name.to_string())
+                .collect()
+        } else {
+            options.columns.clone()
+        };
+
+        // The plot needs the data from before the start of the clip to fill its window on the first frame
+        let time_bounds = TimeBounds {
+            start_time: Some(start_time - TimeDelta::milliseconds((options.plot_window_seconds.max(0.0) * 1000.0).round() as i64)),
+            end_time: Some(end_time),
+        };
+        let df = select_display_data(&df, index_col, &column_names, Some(&time_bounds))?;
+
+        let channels = column_names
+            .into_iter()
+            .map(|name| {
+                let samples = column_samples(&df, index_col, &name)?;
+                Ok(OverlayChannel { name, samples })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let timing = OverlayTiming {
+            start_millis: start_time.and_utc().timestamp_millis(),
+            duration_seconds: end_video_seconds - start_video_seconds,
+            time_scale: video_time_scale,
+        };
+
+        let script = build_overlay_script(&probe, &timing, &channels, &captions, &options);
+
+        job.checkpoint()?;
+
+        render_overlay(
+            job,
+            video_file_path.as_ref(),
+            output_path.as_ref(),
+            &script,
+            start_video_seconds,
+            timing.duration_seconds,
+            options.crf,
+        )?;
+
+        Ok(VideoClipResult {
+            start_time,
+            end_time,
+            start_video_seconds,
+            end_video_seconds,
+        })
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(seconds: u32) -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+            + TimeDelta::seconds(seconds as i64)
+    }
+
+    fn sync_point(video_seconds: f64, data_seconds: u32) -> VideoSyncPoint {
+        VideoSyncPoint {
+            video_seconds,
+            data_time: time(data_seconds),
+        }
+    }
+
+    #[test]
+    fn one_point_only_sets_the_start_time() {
+        assert_eq!(solve_video_sync(&[sync_point(10.0, 20)]).unwrap(), (time(10), None));
+    }
+
+    #[test]
+    fn two_points_solve_the_start_time_and_scale_exactly() {
+        // 20 seconds of video cover 30 seconds of data, so the video clock runs at 1.5x and started 15 seconds before the first point
+        let (start_time, scale) = solve_video_sync(&[sync_point(10.0, 20), sync_point(30.0, 50)]).unwrap();
+
+        assert_eq!(start_time, time(5));
+        assert_eq!(scale, Some(1.5));
+    }
+
+    #[test]
+    fn points_on_a_line_solve_the_same_in_any_order() {
+        let (start_time, scale) = solve_video_sync(&[sync_point(30.0, 50), sync_point(10.0, 20), sync_point(50.0, 80)]).unwrap();
+
+        assert_eq!(start_time, time(5));
+        assert!((scale.unwrap() - 1.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn points_that_cant_be_solved_are_refused() {
+        assert!(solve_video_sync(&[sync_point(10.0, 20), sync_point(10.0, 50)]).is_err());
+        assert!(solve_video_sync(&[sync_point(10.0, 50), sync_point(30.0, 20)]).is_err());
+    }
 }
@@ -0,0 +1,597 @@
+use crate::export_handlers::value_at_time;
+use crate::job_handlers::Job;
+use serde::Deserialize;
+use std::{collections::VecDeque, fmt::Write, path::Path};
+use tauri_plugin_shell::{process::CommandEvent, ShellExt};
+
+// Burns a data overlay into a video. The overlay (timestamp, channel values, annotation captions, and a scrolling plot) is written out as an
+// ASS subtitle script, and the ffmpeg sidecar decodes the video, draws the script onto each frame with its subtitles filter, and encodes the
+// result. Doing the drawing through libass keeps the text rendering and the per-frame compositing inside ffmpeg, so nothing but the script
+// has to be built here.
+
+/// Name of the sidecar in the externalBin list of tauri.conf.json
+const FFMPEG_SIDECAR: &str = "binaries/ffmpeg";
+
+/// How long a caption stays up for an annotation that only marks a single point in time, in seconds of data time
+const POINT_ANNOTATION_CAPTION_SECONDS: f64 = 3.0;
+
+/// How many of the last lines ffmpeg wrote to stderr are kept for the error message if it fails
+const STDERR_TAIL_LINES: usize = 20;
+
+/// Trace colours for the plot and the channel values, the same as the default ECharts palette used by the plot in the app
+const TRACE_COLORS: [(u8, u8, u8); 9] = [
+    (0x54, 0x70, 0xc6),
+    (0x91, 0xcc, 0x75),
+    (0xfa, 0xc8, 0x58),
+    (0xee, 0x66, 0x66),
+    (0x73, 0xc0, 0xde),
+    (0x3b, 0xa2, 0x72),
+    (0xfc, 0x84, 0x52),
+    (0x9a, 0x60, 0xb4),
+    (0xea, 0x7c, 0xcc),
+];
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Structs
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+#[derive(Deserialize, Debug)]
+pub struct OverlayOptions {
+    /// Channels to draw, which can be any CSV column or filtered channel. Empty draws every loaded column, including filtered channels.
+    #[serde(default)]
+    pub columns: Vec<String>,
+    /// Show the title of any annotation covering the current time as a caption
+    #[serde(default)]
+    pub show_annotations: bool,
+    /// Draw a scrolling plot of the channels in the bottom right corner
+    #[serde(default)]
+    pub show_plot: bool,
+    /// How many seconds of data the plot shows, ending at the current time
+    #[serde(default = "default_plot_window_seconds")]
+    pub plot_window_seconds: f64,
+    /// How many times a second the values and plot are redrawn
+    #[serde(default = "default_update_rate_hz")]
+    pub update_rate_hz: f64,
+    #[serde(default = "default_decimal_places")]
+    pub decimal_places: usize,
+    /// x264 constant rate factor, lower is better quality and a bigger file
+    #[serde(default = "default_crf")]
+    pub crf: u8,
+}
+
+fn default_plot_window_seconds() -> f64 {
+    10.0
+}
+
+fn default_update_rate_hz() -> f64 {
+    10.0
+}
+
+fn default_decimal_places() -> usize {
+    3
+}
+
+fn default_crf() -> u8 {
+    20
+}
+
+/// What ffmpeg reported about the input video
+#[derive(Debug)]
+pub struct VideoProbe {
+    pub duration_seconds: f64,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// One channel to draw, with its samples over the clip (plus the plot window before it) as (milliseconds since the epoch, value)
+pub struct OverlayChannel {
+    pub name: String,
+    pub samples: Vec<(i64, f64)>,
+}
+
+/// A caption to show, in display time milliseconds. A None end is a point in time.
+pub struct OverlayCaption {
+    pub text: String,
+    pub start_millis: i64,
+    pub end_millis: Option<i64>,
+}
+
+/// Where the clip sits in data time. Output second t of the clip lines up with start_millis + t * time_scale * 1000.
+pub struct OverlayTiming {
+    pub start_millis: i64,
+    pub duration_seconds: f64,
+    pub time_scale: f64,
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Running FFmpeg
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Probe Video
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Asks ffmpeg for the duration and frame size of a video. Running ffmpeg with only an input prints the stream info and exits with an error
+/// because there is no output, so the exit code is ignored and the info is read from stderr.
+pub fn probe_video(job: &Job, video_file_path: &Path) -> Result<VideoProbe, String> {
+    let args = vec![
+        "-hide_banner".to_string(),
+        "-i".to_string(),
+        video_file_path.to_string_lossy().into_owned(),
+    ];
+
+    let (_, stderr) = run_ffmpeg(job, args, None, |_| {})?;
+
+    parse_probe_output(&stderr)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Render Overlay
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Re-encodes the part of the video from start_video_seconds for duration_seconds with the overlay script drawn on every frame, reporting
+/// the job's progress as ffmpeg works through it. If the job is cancelled, ffmpeg is killed and the half written output is deleted.
+pub fn render_overlay(
+    job: &Job,
+    video_file_path: &Path,
+    output_path: &Path,
+    script: &str,
+    start_video_seconds: f64,
+    duration_seconds: f64,
+    crf: u8,
+) -> Result<(), String> {
+    // Paths inside a filter graph need several layers of escaping (and Windows drive letters break it), so the script is given to the
+    // subtitles filter as a bare file name with ffmpeg running in the temp directory. That means the other paths have to be absolute. The
+    // name has the job id in it as well as the process id, so exports running at the same time don't overwrite each other's scripts.
+    let script_dir = std::env::temp_dir();
+    let script_name = format!("chronolab-overlay-{}-{}.ass", std::process::id(), job.id());
+    let script_path = script_dir.join(&script_name);
+
+    let absolute = |path: &Path| {
+        std::path::absolute(path).map_err(|e| format!("Failed to resolve {}: {}", path.display(), e))
+    };
+    let video_file_path = absolute(video_file_path)?;
+    let output_path = absolute(output_path)?;
+
+    std::fs::write(&script_path, script).map_err(|e| format!("Failed to write the overlay script: {}", e))?;
+
+    let args: Vec<String> = [
+        "-hide_banner",
+        "-loglevel",
+        "error",
+        "-nostats",
+        "-progress",
+        "pipe:1",
+        "-y",
+        "-ss",
+        &format!("{:.3}", start_video_seconds),
+        "-t",
+        &format!("{:.3}", duration_seconds),
+        "-i",
+        &video_file_path.to_string_lossy(),
+        "-vf",
+        &format!("subtitles={}", script_name),
+        "-c:v",
+        "libx264",
+        "-preset",
+        "medium",
+        "-crf",
+        &crf.to_string(),
+        "-pix_fmt",
+        "yuv420p",
+        "-c:a",
+        "aac",
+        "-b:a",
+        "192k",
+        "-movflags",
+        "+faststart",
+        &output_path.to_string_lossy(),
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect();
+
+    let result = run_ffmpeg(job, args, Some(&script_dir), |processed_seconds| {
+        let fraction = if duration_seconds > 0.0 {
+            (processed_seconds / duration_seconds).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        job.progress("Rendering", Some(fraction * 100.0), None);
+    });
+
+    let _ = std::fs::remove_file(&script_path);
+
+    if result.is_err() && job.checkpoint().is_err() {
+        let _ = std::fs::remove_file(&output_path);
+    }
+
+    match result? {
+        (true, _) => Ok(()),
+        (false, stderr) => Err(format!("ffmpeg failed to render the overlay video:\n{}", stderr.trim_end())),
+    }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Run FFmpeg
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Runs the ffmpeg sidecar to completion, blocking the job's thread while it does. Progress lines from "-progress pipe:1" on stdout are
+/// passed to on_progress as seconds of output written. Returns whether ffmpeg exited successfully and the last lines it wrote to stderr.
+/// The job is checked every time ffmpeg writes a line (with -progress that's at least twice a second), and ffmpeg is killed once it has
+/// been cancelled.
+fn run_ffmpeg(
+    job: &Job,
+    args: Vec<String>,
+    current_dir: Option<&Path>,
+    mut on_progress: impl FnMut(f64),
+) -> Result<(bool, String), String> {
+    let mut command = job
+        .app()
+        .shell()
+        .sidecar(FFMPEG_SIDECAR)
+        .map_err(|e| format!("Could not find the ffmpeg sidecar: {}", e))?
+        .args(args);
+
+    if let Some(current_dir) = current_dir {
+        command = command.current_dir(current_dir);
+    }
+
+    let (mut events, child) = command.spawn().map_err(|e| format!("Failed to start ffmpeg: {}", e))?;
+
+    let mut stderr_tail: VecDeque<String> = VecDeque::with_capacity(STDERR_TAIL_LINES);
+    let mut success = false;
+
+    while let Some(event) = events.blocking_recv() {
+        if let Err(cancelled) = job.checkpoint() {
+            let _ = child.kill();
+            return Err(cancelled);
+        }
+
+        match event {
+            CommandEvent::Stdout(line) => {
+                if let Some(processed_seconds) = parse_progress_line(&String::from_utf8_lossy(&line)) {
+                    on_progress(processed_seconds);
+                }
+            }
+            CommandEvent::Stderr(line) => {
+                if stderr_tail.len() == STDERR_TAIL_LINES {
+                    stderr_tail.pop_front();
+                }
+                stderr_tail.push_back(String::from_utf8_lossy(&line).trim_end().to_string());
+            }
+            CommandEvent::Error(error) => return Err(format!("Error running ffmpeg: {}", error)),
+            CommandEvent::Terminated(payload) => success = payload.code == Some(0),
+            _ => {}
+        }
+    }
+
+    Ok((success, Vec::from(stderr_tail).join("\n")))
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Parse FFmpeg Output
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Reads the duration ("Duration: 00:01:02.50, ...") and the frame size of the first video stream ("Stream #0:0: Video: h264 (High), yuv420p,
+/// 1920x1080 [SAR 1:1 DAR 16:9], ...") out of ffmpeg's description of an input.
+fn parse_probe_output(stderr: &str) -> Result<VideoProbe, String> {
+    let duration_seconds = stderr
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Duration: "))
+        .and_then(|rest| rest.split(',').next())
+        .and_then(|duration| {
+            let mut parts = duration.trim().split(':').map(|part| part.parse::<f64>().ok());
+            Some(parts.next()?? * 3600.0 + parts.next()?? * 60.0 + parts.next()??)
+        })
+        .ok_or_else(|| format!("ffmpeg couldn't read the length of the video:\n{}", stderr.trim_end()))?;
+
+    let (width, height) = stderr
+        .lines()
+        .filter_map(|line| line.split_once(" Video: ").map(|(_, description)| description))
+        .flat_map(|description| description.split(','))
+        .find_map(|part| {
+            let (width, height) = part.split_whitespace().next()?.split_once('x')?;
+            Some((width.parse::<u32>().ok()?, height.parse::<u32>().ok()?))
+        })
+        .ok_or_else(|| format!("ffmpeg didn't find a video stream in the file:\n{}", stderr.trim_end()))?;
+
+    Ok(VideoProbe {
+        duration_seconds,
+        width,
+        height,
+    })
+}
+
+/// Seconds of output written, from an "out_time_us=" line of ffmpeg's progress output. Older ffmpeg versions call it "out_time_ms", but it
+/// is in microseconds there too. The value is "N/A" until the first frame is written.
+fn parse_progress_line(line: &str) -> Option<f64> {
+    let (key, value) = line.trim().split_once('=')?;
+
+    match key {
+        "out_time_us" | "out_time_ms" => value.parse::<f64>().ok().map(|microseconds| microseconds / 1e6),
+        _ => None,
+    }
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Overlay Script
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Build Overlay Script
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Writes the ASS script for the overlay. The script resolution matches the video so that sizes are in pixels. The timestamp and channel
+/// values sit in the top left, captions at the top centre, and the plot in the bottom right. Each channel's plot is scaled to its own range
+/// over the clip so that channels with very different units can share it, and the scale doesn't jump around as the plot scrolls.
+pub fn build_overlay_script(
+    probe: &VideoProbe,
+    timing: &OverlayTiming,
+    channels: &[OverlayChannel],
+    captions: &[OverlayCaption],
+    options: &OverlayOptions,
+) -> String {
+    let (width, height) = (probe.width as f64, probe.height as f64);
+    let font_size = (height / 30.0).round().max(10.0);
+    let margin = (height / 40.0).round();
+
+    let mut script = String::new();
+
+    let _ = write!(
+        script,
+        "[Script Info]\n\
+         ScriptType: v4.00+\n\
+         PlayResX: {}\n\
+         PlayResY: {}\n\
+         WrapStyle: 2\n\
+         ScaledBorderAndShadow: yes\n\
+         \n\
+         [V4+ Styles]\n\
+         Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, \
+         StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n\
+         Style: Values,Arial,{},&H00FFFFFF,&H00FFFFFF,&H00000000,&H80000000,0,0,0,0,100,100,0,0,1,2,0,7,{},{},{},1\n\
+         Style: Caption,Arial,{},&H00FFFFFF,&H00FFFFFF,&H00000000,&H80000000,-1,0,0,0,100,100,0,0,1,2,0,8,{},{},{},1\n\
+         Style: Plot,Arial,{},&H00FFFFFF,&H00FFFFFF,&H00000000,&H00000000,0,0,0,0,100,100,0,0,1,0,0,7,0,0,0,1\n\
+         \n\
+         [Events]\n\
+         Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n",
+        probe.width,
+        probe.height,
+        font_size,
+        margin,
+        margin,
+        margin,
+        (font_size * 1.2).round(),
+        margin,
+        margin,
+        margin,
+        font_size,
+    );
+
+    // Every redraw lands on a whole centisecond, the resolution of ASS times, so consecutive events butt up against each other exactly
+    let total_centis = (timing.duration_seconds * 100.0).round() as u64;
+    let update_rate_hz = options.update_rate_hz.clamp(0.1, 100.0);
+    let tick_count = ((timing.duration_seconds * update_rate_hz).ceil() as u64).max(1);
+    let tick_centis = |tick: u64| ((tick as f64 * 100.0 / update_rate_hz).round() as u64).min(total_centis);
+    let data_millis = |centis: u64| timing.start_millis + (centis as f64 * 10.0 * timing.time_scale).round() as i64;
+
+    // Channel values, merged into one event while they don't change
+    let mut pending: Option<(u64, u64, String)> = None;
+
+    for tick in 0..tick_count {
+        let (start, end) = (tick_centis(tick), tick_centis(tick + 1));
+        if start >= end {
+            continue;
+        }
+
+        let time_millis = data_millis(start);
+        let text = values_text(time_millis, channels, options.decimal_places);
+
+        pending = match pending {
+            Some((pending_start, _, pending_text)) if pending_text == text => Some((pending_start, end, pending_text)),
+            Some((pending_start, pending_end, pending_text)) => {
+                write_event(&mut script, 1, pending_start, pending_end, "Values", &pending_text);
+                Some((start, end, text))
+            }
+            None => Some((start, end, text)),
+        };
+    }
+
+    if let Some((start, end, text)) = pending {
+        write_event(&mut script, 1, start, end, "Values", &text);
+    }
+
+    if options.show_annotations {
+        let to_centis = |millis: i64| {
+            let seconds = (millis - timing.start_millis) as f64 / 1000.0 / timing.time_scale;
+            ((seconds * 100.0).round().max(0.0) as u64).min(total_centis)
+        };
+
+        for caption in captions {
+            let end_millis = caption.end_millis.unwrap_or(
+                caption.start_millis + (POINT_ANNOTATION_CAPTION_SECONDS * 1000.0) as i64,
+            );
+            let (start, end) = (to_centis(caption.start_millis), to_centis(end_millis));
+
+            if start < end {
+                write_event(&mut script, 2, start, end, "Caption", &escape_text(&caption.text));
+            }
+        }
+    }
+
+    if options.show_plot && !channels.is_empty() {
+        let plot_width = (width * 0.4).round();
+        let plot_height = (height * 0.25).round();
+        let plot_x = width - margin - plot_width;
+        let plot_y = height - margin - plot_height;
+        let line_width = (height / 360.0).max(1.0);
+        let window_millis = ((options.plot_window_seconds.max(0.1)) * 1000.0).round() as i64;
+
+        // One point per two pixels is as much detail as the plot can show
+        let max_points = ((plot_width / 2.0) as usize).max(2);
+
+        write_event(
+            &mut script,
+            0,
+            0,
+            total_centis,
+            "Plot",
+            &format!(
+                "{{\\an7\\pos({},{})\\p1\\bord0\\shad0\\1c&H000000&\\1a&H60&}}m 0 0 l {} 0 {} {} 0 {}{{\\p0}}",
+                plot_x, plot_y, plot_width, plot_width, plot_height, plot_height
+            ),
+        );
+
+        for (index, channel) in channels.iter().enumerate() {
+            let (min, max) = channel
+                .samples
+                .iter()
+                .map(|(_, value)| *value)
+                .filter(|value| value.is_finite())
+                .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| (min.min(value), max.max(value)));
+
+            if min > max {
+                continue;
+            }
+
+            let color = ass_color(TRACE_COLORS[index % TRACE_COLORS.len()]);
+            let scale_y = |value: f64| {
+                if max > min {
+                    plot_height - (value - min) / (max - min) * plot_height
+                } else {
+                    plot_height / 2.0
+                }
+            };
+
+            for tick in 0..tick_count {
+                let (start, end) = (tick_centis(tick), tick_centis(tick + 1));
+                if start >= end {
+                    continue;
+                }
+
+                let window_end = data_millis(start);
+                let window_start = window_end - window_millis;
+
+                let first = channel.samples.partition_point(|(time, _)| *time < window_start);
+                let last = channel.samples.partition_point(|(time, _)| *time <= window_end);
+                let visible: Vec<&(i64, f64)> = channel.samples[first..last]
+                    .iter()
+                    .filter(|(_, value)| value.is_finite())
+                    .collect();
+
+                if visible.len() < 2 {
+                    continue;
+                }
+
+                // Thinned out to what the plot can show, always keeping the newest sample so the line reaches the right edge
+                let step = visible.len().div_ceil(max_points);
+                let mut kept: Vec<&(i64, f64)> = visible.iter().step_by(step).copied().collect();
+                if kept.last() != visible.last() {
+                    kept.extend(visible.last());
+                }
+
+                let points: Vec<(f64, f64)> = kept
+                    .iter()
+                    .map(|(time, value)| {
+                        let x = (time - window_start) as f64 / window_millis as f64 * plot_width;
+                        (x.round(), scale_y(*value).round())
+                    })
+                    .collect();
+
+                // ASS drawings are filled shapes, so the line is drawn as a shape that goes out along the points and back again. It has no
+                // area, so with a transparent fill only its outline shows, which traces the line.
+                let mut path = format!("m {} {} l", points[0].0, points[0].1);
+                for (x, y) in points.iter().skip(1).chain(points.iter().rev().skip(1)) {
+                    let _ = write!(path, " {} {}", x, y);
+                }
+
+                write_event(
+                    &mut script,
+                    1,
+                    start,
+                    end,
+                    "Plot",
+                    &format!(
+                        "{{\\an7\\pos({},{})\\p1\\bord{}\\shad0\\1a&HFF&\\3c{}}}{}{{\\p0}}",
+                        plot_x, plot_y, line_width, color, path
+                    ),
+                );
+            }
+        }
+    }
+
+    script
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Overlay Script Helpers
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The timestamp and the value of each channel at a point in time, one per line, each channel in its plot colour.
+fn values_text(time_millis: i64, channels: &[OverlayChannel], decimal_places: usize) -> String {
+    let timestamp = crate::dataframe_handlers::naive_datetime_from_millis(time_millis)
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S%.3f").to_string())
+        .unwrap_or_default();
+
+    let mut lines = vec![timestamp];
+
+    for (index, channel) in channels.iter().enumerate() {
+        let value = value_at_time(&channel.samples, time_millis)
+            .map(|value| format!("{:.*}", decimal_places, value))
+            .unwrap_or_else(|| "-".to_string());
+
+        lines.push(format!(
+            "{{\\c{}}}{}: {}{{\\c&HFFFFFF&}}",
+            ass_color(TRACE_COLORS[index % TRACE_COLORS.len()]),
+            escape_text(&channel.name),
+            value
+        ));
+    }
+
+    lines.join("\\N")
+}
+
+fn write_event(script: &mut String, layer: u32, start_centis: u64, end_centis: u64, style: &str, text: &str) {
+    let _ = writeln!(
+        script,
+        "Dialogue: {},{},{},{},,0,0,0,,{}",
+        layer,
+        ass_time(start_centis),
+        ass_time(end_centis),
+        style,
+        text
+    );
+}
+
+/// ASS times are H:MM:SS.cc
+fn ass_time(centis: u64) -> String {
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        centis / 360_000,
+        centis / 6000 % 60,
+        centis / 100 % 60,
+        centis % 100
+    )
+}
+
+/// ASS colours are written blue, green, red
+fn ass_color((red, green, blue): (u8, u8, u8)) -> String {
+    format!("&H{:02X}{:02X}{:02X}&", blue, green, red)
+}
+
+/// Braces start override tags and backslashes start escapes like \N, and ASS has no way to escape either, so they are swapped for
+/// look-alikes. Newlines become ASS line breaks.
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "/")
+        .replace('{', "(")
+        .replace('}', ")")
+        .replace("\r\n", "\\N")
+        .replace(['\r', '\n'], "\\N")
+}
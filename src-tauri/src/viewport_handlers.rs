@@ -0,0 +1,197 @@
+use crate::dataframe_handlers::cached_display_data;
+use crate::global_state::{snapshot_app_state, AppState, TimeBounds};
+use crate::job_handlers::run_job;
+use crate::transfer_encoding::write_transfer_ipc;
+use chrono::NaiveDateTime;
+use polars::prelude::*;
+use std::sync::RwLock;
+use tauri::{ipc::Response, AppHandle, State};
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Tauri Commands
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Get Viewport Data
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Returns the given columns between the time bounds, at most max_points rows in total, in the same Arrow IPC format as get_csv_data.
+/// This is what the plot asks for whenever it is panned or zoomed. The data comes from the LoadedDataCache, so the CSV is only read again if
+/// the load settings have changed since the last load. The rows inside the bounds are found by binary search on the datetime index, plus one
+/// row either side so the lines run to the edges of the plot. If that is more than max_points rows it's downsampled by keeping the rows
+/// holding the minimum and maximum of every column in each bucket, so spikes survive (see min_max_rows). Otherwise the raw rows are returned.
+/// If no columns are given, every loaded and filtered column is returned. Either end of the time bounds can be left open.
+#[tauri::command]
+pub async fn get_viewport_data(
+    app: AppHandle,
+    state: State<'_, RwLock<AppState>>,
+    columns: Vec<String>,
+    time_bounds: TimeBounds,
+    max_points: usize,
+) -> Result<Response, String> {
+    let state = snapshot_app_state(&state);
+    let job_app = app.clone();
+
+    run_job(&app, "get_viewport_data", move |job| {
+        let datetime_index_col = state
+            .load_csv_settings
+            .as_ref()
+            .ok_or("CSV loading settings have not been set yet.")?
+            .datetime_index_col
+            .clone();
+
+        let df = cached_display_data(&job_app, &state, job)?;
+
+        job.checkpoint()?;
+
+        let columns: Vec<String> = if columns.is_empty() {
+            df.get_column_names()
+                .into_iter()
+                .filter(|name| name.as_str() != datetime_index_col)
+                .map(|name| name.to_string())
+                .collect()
+        } else {
+            columns
+        };
+
+        let df = viewport_dataframe(&df, &datetime_index_col, &columns, &time_bounds, max_points)?;
+
+        Ok(Response::new(write_transfer_ipc(&df, &state.transfer_settings)?))
+    })
+    .await
+}
+
+// #############################################################################################################################################
+// #############################################################################################################################################
+// Utility Functions
+// #############################################################################################################################################
+// #############################################################################################################################################
+
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+// Viewport DataFrame
+// ---------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Slices a DataFrame sorted by its datetime index down to the time bounds and the given columns, downsampling it if there are more than
+/// max_points rows. The datetime index column is always the first column of the result. max_points is raised to 2 + 2 × the number of
+/// columns if it's below that, since downsampling can't keep less than the end rows and one minimum and maximum per column.
+pub fn viewport_dataframe(
+    df: &DataFrame,
+    datetime_index_col: &str,
+    columns: &[String],
+    time_bounds: &TimeBounds,
+    max_points: usize,
+) -> Result<DataFrame, String> {
+    let times = df
+        .column(datetime_index_col)
+        .and_then(|column| column.datetime())
+        .map_err(|e| format!("Error reading the datetime index column: {}", e.to_string()))?;
+
+    // One extra row either side, so the plotted lines don't stop short of the edges
+    let start = time_bounds
+        .start_time
+        .map_or(0, |start_time| first_row_at_or_after(times, start_time).saturating_sub(1));
+    let end = time_bounds.end_time.map_or(times.len(), |end_time| {
+        (first_row_after(times, end_time) + 1).min(times.len())
+    });
+
+    let selected = [&[datetime_index_col.to_string()], columns]
+        .concat()
+        .into_iter()
+        .map(|name| {
+            df.column(&name)
+                .cloned()
+                .map_err(|_| format!("Column \"{}\" has not been loaded.", name))
+        })
+        .collect::<Result<Vec<Series>, String>>()?;
+
+    let df = DataFrame::new(selected)
+        .map_err(|e| format!("Error selecting columns: {}", e.to_string()))?
+        .slice(start as i64, end.saturating_sub(start));
+
+    let max_points = max_points.max(2 + 2 * columns.len());
+
+    if df.height() <= max_points {
+        return Ok(df);
+    }
+
+    // Each bucket keeps up to two rows per column, and the first and last rows are always kept, so this many buckets stays within max_points
+    let bucket_count = (max_points - 2) / (2 * columns.len().max(1));
+    let rows = min_max_rows(&df, columns, bucket_count)?;
+
+    df.take(&IdxCa::from_vec("rows".into(), rows))
+        .map_err(|e| format!("Error downsampling data: {}", e.to_string()))
+}
+
+/// Picks the rows to keep when downsampling: the first and last rows, plus the rows holding the minimum and maximum of every column within
+/// each of the buckets. The buckets are the same for every column, so there are at most 2 + 2 × columns × bucket_count rows. Returned in order
+/// without repeats.
+fn min_max_rows(df: &DataFrame, columns: &[String], bucket_count: usize) -> Result<Vec<IdxSize>, String> {
+    let height = df.height();
+    let bucket_count = bucket_count.clamp(1, height);
+    let mut rows: Vec<IdxSize> = vec![0, (height - 1) as IdxSize];
+
+    for name in columns {
+        let values = df
+            .column(name)
+            .and_then(|column| column.f64())
+            .map_err(|e| format!("Error reading column \"{}\": {}", name, e.to_string()))?;
+
+        for bucket in 0..bucket_count {
+            let bucket_start = bucket * height / bucket_count;
+            let bucket_end = (bucket + 1) * height / bucket_count;
+
+            let mut min: Option<(usize, f64)> = None;
+            let mut max: Option<(usize, f64)> = None;
+
+            for (offset, value) in values.slice(bucket_start as i64, bucket_end - bucket_start).iter().enumerate() {
+                let Some(value) = value else {
+                    continue;
+                };
+                if min.filter(|&(_, min_value)| min_value <= value).is_none() {
+                    min = Some((bucket_start + offset, value));
+                }
+                if max.filter(|&(_, max_value)| max_value >= value).is_none() {
+                    max = Some((bucket_start + offset, value));
+                }
+            }
+
+            rows.extend(min.into_iter().chain(max).map(|(row, _)| row as IdxSize));
+        }
+    }
+
+    rows.sort_unstable();
+    rows.dedup();
+
+    Ok(rows)
+}
+
+/// Index of the first row whose timestamp is at or after the given time, or the number of rows if there isn't one. Null timestamps sort
+/// first, so they count as before every time.
+fn first_row_at_or_after(times: &DatetimeChunked, time: NaiveDateTime) -> usize {
+    let millis = time.and_utc().timestamp_millis();
+    partition_point(times.len(), |row| times.get(row).unwrap_or(i64::MIN) < millis)
+}
+
+/// Index of the first row whose timestamp is after the given time, or the number of rows if there isn't one
+fn first_row_after(times: &DatetimeChunked, time: NaiveDateTime) -> usize {
+    let millis = time.and_utc().timestamp_millis();
+    partition_point(times.len(), |row| times.get(row).unwrap_or(i64::MIN) <= millis)
+}
+
+/// Binary search for the first row in 0..len where is_before stops being true, like slice::partition_point but over row indices
+fn partition_point(len: usize, is_before: impl Fn(usize) -> bool) -> usize {
+    let (mut low, mut high) = (0, len);
+
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if is_before(middle) {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+
+    low
+}
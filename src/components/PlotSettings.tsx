@@ -198,7 +198,7 @@ function PlotSettingsForm({ columns, onSubmit, currentSettings }: PlotSettingsFo
 
 function PlotSettings() {
     const { selectCsvFile } = useFileOperations();
-    const { loadCsvSettings, setLoadCsvSettings, csvFilePath, setCsvFilePath } = useGlobalState({ csvFile: true, loadCsvSettings: true, setOnly: false })
+    const { loadCsvSettings, setLoadCsvSettings, csvFilePath } = useGlobalState({ csvFile: true, loadCsvSettings: true, setOnly: false })
     const [columns, setColumns] = useState<DataFrameColumn[] | undefined>(undefined);
 
     const handleFormSubmit = (settings: LoadCsvSettings) => {
@@ -243,7 +243,7 @@ function PlotSettings() {
                         <Button 
                             variant="contained" 
                             color="primary" 
-                            onClick={() => selectCsvFile(setCsvFilePath)}
+                            onClick={() => selectCsvFile()}
                         >
                             Select CSV File
                         </Button>
@@ -8,15 +8,20 @@ import { toZonedTime } from 'date-fns-tz';
 import { alpha, Box, FormControlLabel, Stack, Switch, TextField, useTheme } from '@mui/material';
 import useGlobalState from '../hooks/useGlobalState';
 import { listen } from '@tauri-apps/api/event';
+import { DownsamplingPreferences, UserPreferences } from '../types/userPreferences';
+import { VideoTimeChange } from '../types/appState';
+import { parseUtcString } from '../utils/datetimeHandlers';
+import { decodeTransferColumn } from '../utils/transferDecoding';
 
 function Plotter() {
   const theme = useTheme();
-  const { loadCsvSettings, videoStartTime } = useGlobalState({ loadCsvSettings: true, videoStartTime: true });
+  const { loadCsvSettings } = useGlobalState({ loadCsvSettings: true });
   const chartRef = useRef<HTMLDivElement>(null);
   const chartInstance = useRef<echarts.ECharts | null>(null);
   const [followVideo, setFollowVideo] = useState(true);
   const [timeBeforeVideo, setTimeBeforeVideo] = useState(10);
   const [timeAfterVideo, setTimeAfterVideo] = useState(10);
+  const [downsampling, setDownsampling] = useState<DownsamplingPreferences | null>(null);
 
   const handleTimeInputChange = (
     type: 'before' | 'after',
@@ -59,18 +64,41 @@ function Plotter() {
     return () => window.removeEventListener('resize', handleResize);
   }, []);
 
+  // Load the downsampling preference, and follow changes to it. Other preference changes (e.g. the recent files) don't reload the plot.
+  useEffect(() => {
+    const updateDownsampling = (preferences: UserPreferences) => {
+      setDownsampling((previous) =>
+        previous?.enabled === preferences.downsampling.enabled && previous?.max_points === preferences.downsampling.max_points
+          ? previous
+          : preferences.downsampling
+      );
+    };
+
+    invoke<UserPreferences>('get_user_preferences')
+      .then(updateDownsampling)
+      .catch((error) => console.error('Failed to load the downsampling preference:', error));
+
+    const unlisten = listen<UserPreferences>('user-preferences-change', (event) => updateDownsampling(event.payload));
+
+    return () => {
+      unlisten.then(f => f());
+    };
+  }, []);
+
   // Video time sync effect
   useEffect(() => {
     let cleanup: (() => void) | undefined;
 
     async function setupListener() {
       try {
-        const unlisten = await listen<number>('video-time-change', (event) => {
-          if (!videoStartTime || !followVideo || !chartInstance.current) {
+        // The backend has already turned the video time into display time, using the video and display time zones and the clock scale
+        const unlisten = await listen<VideoTimeChange>('video-time-change', (event) => {
+          const displayTime = parseUtcString(event.payload.display_time);
+          if (!displayTime || !chartInstance.current) {
             return;
           }
 
-          const currentTime = addSeconds(toZonedTime(videoStartTime, "UTC"), event.payload);
+          const currentTime = toZonedTime(displayTime, "UTC");
           const windowStart = addSeconds(currentTime, -timeBeforeVideo);
           const windowEnd = addSeconds(currentTime, timeAfterVideo);
 
@@ -89,7 +117,7 @@ function Plotter() {
       }
     }
 
-    if (videoStartTime && followVideo) {
+    if (followVideo) {
       setupListener();
     }
 
@@ -98,14 +126,23 @@ function Plotter() {
         cleanup();
       }
     };
-  }, [videoStartTime, followVideo, timeBeforeVideo, timeAfterVideo]);
+  }, [followVideo, timeBeforeVideo, timeAfterVideo]);
 
   // Load and plot data
   useEffect(() => {
     async function fetchData() {
       try {
-        if (loadCsvSettings) {
-          const parsedData = tableFromIPC(await invoke('get_csv_data'));
+        if (loadCsvSettings && downsampling) {
+          // With downsampling on, the whole time range is cut down to max_points rows, keeping each bucket's minimum and maximum
+          const parsedData = tableFromIPC(
+            downsampling.enabled
+              ? await invoke('get_viewport_data', {
+                  columns: [],
+                  timeBounds: { start_time: null, end_time: null },
+                  maxPoints: downsampling.max_points,
+                })
+              : await invoke('get_csv_data')
+          );
           
           const timestamps = Array.from(
             parsedData.getChild(loadCsvSettings?.datetime_index_col)?.toArray() ?? [], 
@@ -113,7 +150,7 @@ function Plotter() {
           );
 
           const series = parsedData.schema.fields.slice(1).map((field, _) => {
-            const values = parsedData.getChild(field.name)?.toArray() ?? [];
+            const values = decodeTransferColumn(field, parsedData.getChild(field.name));
             return {
               name: field.name,
               type: 'line',
@@ -235,7 +272,7 @@ function Plotter() {
     }
 
     fetchData();
-  }, [loadCsvSettings, theme]);
+  }, [loadCsvSettings, theme, downsampling]);
 
   function addSeconds(date: Date, seconds: number) {
     return new Date(date.getTime() + seconds * 1000);
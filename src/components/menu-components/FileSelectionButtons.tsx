@@ -8,8 +8,7 @@ import useGlobalState from "../../hooks/useGlobalState";
 import { useFileOperations } from '../../hooks/useFileOperations';
 
 export function FileSelectionButtons() {
-    const { setCsvFilePath, setVideoFilePath } = useGlobalState({
-        csvFile: true,
+    const { setVideoFilePath } = useGlobalState({
         videoFile: true,
         setOnly: true
     });
@@ -20,7 +19,7 @@ export function FileSelectionButtons() {
             <Tooltip title="Select CSV File">
                 <Button
                     color="inherit"
-                    onClick={() => selectCsvFile(setCsvFilePath)}
+                    onClick={() => selectCsvFile()}
                     startIcon={<CsvIcon />}
                     sx={{ borderRadius: 1 }}
                 >
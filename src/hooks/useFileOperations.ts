@@ -3,6 +3,8 @@ import { useCallback } from 'react';
 import { invoke } from '@tauri-apps/api/core';
 import { open, save } from '@tauri-apps/plugin-dialog';
 import { useToast } from './useToast';
+import { AppStateField, LoadCsvSettings } from '../types/appState';
+import { UserPreferences } from '../types/userPreferences';
 
 interface FileFilters {
     [key: string]: {
@@ -35,10 +37,12 @@ export function useFileOperations() {
     const getErrorMessage = (error: unknown): string => {
         if (error instanceof Error) return error.message;
         if (typeof error === 'string') return error;
+        // Commands that validate their input reject with a ValidationError object instead of a string
+        if (typeof error === 'object' && error !== null && 'message' in error) return String(error.message);
         return 'An unknown error occurred';
     };
 
-    const selectCsvFile = useCallback(async (setCsvFilePath: ((path: string) => Promise<void>) | undefined) => {
+    const selectCsvFile = useCallback(async () => {
         try {
             const file = await open({
                 multiple: false,
@@ -46,10 +50,36 @@ export function useFileOperations() {
                 filters: filters.csv
             });
 
-            if (file && setCsvFilePath) {
-                await setCsvFilePath(file);
-                showToast('CSV file selected successfully', 'success');
-            } else if (!file) {
+            if (file) {
+                // The old load settings belong to the old file, so replace them in the same update with the default template's, or clear
+                // them if there isn't one. Otherwise the plot would try to load the new file with them before the user has picked its columns.
+                const preferences = await invoke<UserPreferences>('get_user_preferences');
+                const template = preferences.load_csv_templates.find(
+                    (loadCsvTemplate) => loadCsvTemplate.name === preferences.default_load_csv_template
+                );
+                const setCsvFile = (loadCsvSettings: LoadCsvSettings | null) => {
+                    const appStateFields: AppStateField[] = [
+                        { csvFilePath: { value: file } },
+                        { loadCsvSettings: { value: loadCsvSettings } },
+                    ];
+                    return invoke('set_app_state_fields', { appStateFields });
+                };
+
+                if (!template) {
+                    await setCsvFile(null);
+                    showToast('CSV file selected successfully', 'success');
+                    return;
+                }
+
+                try {
+                    await setCsvFile(template.settings);
+                    showToast(`CSV file selected and loaded with the "${template.name}" template`, 'success');
+                } catch (error) {
+                    // The template doesn't fit this file, e.g. it names columns the file doesn't have
+                    await setCsvFile(null);
+                    showToast(`CSV file selected, but the "${template.name}" template doesn't fit it: ${getErrorMessage(error)}`, 'warning');
+                }
+            } else {
                 showToast('File selection cancelled', 'info');
             }
         } catch (error) {
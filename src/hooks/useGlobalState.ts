@@ -1,7 +1,7 @@
 import { listen } from "@tauri-apps/api/event";
 import { parseUtcString } from "../utils/datetimeHandlers";
 import { useGlobalStateAttributeHookFactory } from "./useGlobalStateAttributeHookFactory";
-import { LoadCsvSettings } from "../types/appState";
+import { LoadCsvSettings, StateChange } from "../types/appState";
 
 
 export function useSaveFilePath(setOnly: boolean = false) {
@@ -200,8 +200,8 @@ export default useGlobalState;
 // Helper function to wait for a global state update
 export function waitForGlobalStateUpdate(eventName: string): Promise<any> {
     return new Promise((resolve) => {
-        const unlistenPromise = listen(eventName, (event) => {
-            resolve(event.payload);
+        const unlistenPromise = listen<StateChange<any>>(eventName, (event) => {
+            resolve(event.payload.value);
             // Unlisten after the event is received
             unlistenPromise.then(unlisten => unlisten());
         });
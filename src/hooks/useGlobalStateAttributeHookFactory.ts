@@ -1,6 +1,7 @@
 import { useEffect, useState } from "react";
 import { useInvokeWithToast } from "./useInvokeWithToast";
 import { listen } from "@tauri-apps/api/event";
+import { StateChange } from "../types/appState";
 
 interface UseGlobalStateAttributeHookFactoryOptions<T> {
     fieldName: string;
@@ -57,8 +58,8 @@ export function useGlobalStateAttributeHookFactory<T>({
     useEffect(() => {
         if (setOnly) return;
 
-        const unlisten = listen<T>(eventName, (event) => {
-            setValue(parseValue(event.payload));
+        const unlisten = listen<StateChange<T>>(eventName, (event) => {
+            setValue(parseValue(event.payload.value));
         });
 
         return () => {
@@ -17,7 +17,12 @@ export function useInvokeWithToast() {
             }
             return result;
         } catch (error) {
-            const errorMessage = error instanceof Error ? error.message : String(error);
+            // Commands that validate their input reject with a ValidationError object instead of a string
+            const errorMessage = error instanceof Error
+                ? error.message
+                : (typeof error === 'object' && error !== null && 'message' in error)
+                    ? String(error.message)
+                    : String(error);
             showToast(`${errorPrefix}: ${errorMessage}`, 'error');
             console.error(`${errorPrefix}:`, error);
             return undefined;
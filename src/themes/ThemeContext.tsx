@@ -1,7 +1,10 @@
 import React, { createContext, useContext, useState, useEffect } from 'react';
 import { ThemeProvider as MUIThemeProvider } from '@mui/material/styles';
 import CssBaseline from '@mui/material/CssBaseline';
+import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
 import { createAppTheme } from './themes';
+import { UserPreferences } from '../types/userPreferences';
 
 interface ThemeContextType {
   mode: 'light' | 'dark';
@@ -16,18 +19,35 @@ const ThemeContext = createContext<ThemeContextType>({
 function ThemeProvider({ children }: { children: React.ReactNode }) {
   const [mode, setMode] = useState<'light' | 'dark'>('light');
 
-  // Load theme preference from localStorage on mount
+  // Load theme preference from the user preferences on mount, and follow changes made in other windows
   useEffect(() => {
-    const savedMode = localStorage.getItem('themeMode');
-    if (savedMode && (savedMode === 'light' || savedMode === 'dark')) {
-      setMode(savedMode);
-    }
+    // The theme used to be kept in localStorage. Move it over to the user preferences the first time this version runs.
+    const legacyMode = localStorage.getItem('themeMode');
+    const loadTheme = legacyMode === 'light' || legacyMode === 'dark'
+      ? invoke<UserPreferences>('update_user_preferences', { changes: { theme: legacyMode } })
+      : invoke<UserPreferences>('get_user_preferences');
+
+    loadTheme
+      .then((preferences) => {
+        localStorage.removeItem('themeMode');
+        setMode(preferences.theme);
+      })
+      .catch((error) => console.error('Failed to load the theme preference:', error));
+
+    const unlisten = listen<UserPreferences>('user-preferences-change', (event) => {
+      setMode(event.payload.theme);
+    });
+
+    return () => {
+      unlisten.then(f => f());
+    };
   }, []);
 
   function toggleTheme() {
     const newMode = mode === 'light' ? 'dark' : 'light';
     setMode(newMode);
-    localStorage.setItem('themeMode', newMode);
+    invoke('update_user_preferences', { changes: { theme: newMode } })
+      .catch((error) => console.error('Failed to save the theme preference:', error));
   }
 
   const theme = createAppTheme(mode);
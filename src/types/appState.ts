@@ -6,16 +6,187 @@ export type AppStateField =
     | { videoFilePath: { value: string | null } }
     | { isMultiwindow: { value: boolean } }
     | { videoStartTime: { value: Date | null } }
+    | { videoTimeScale: { value: number | null } }
+    | { videoSyncPoints: { value: VideoSyncPoint[] } }
+    | { videoTimeZone: { value: string | null } }
+    | { displayTimeZone: { value: string | null } }
+    | { annotations: { value: Annotation[] } }
+    | { videoChapters: { value: VideoChapter[] } }
+    | { detectionRules: { value: DetectionRule[] } }
+    | { channelFilters: { value: ChannelFilter[] } }
+    | { transferSettings: { value: TransferSettings } }
     | { isModifiedSinceLastSave: { value: boolean } };
 
+export type AppState = {
+    save_file_path: string | null;
+    csv_file_path: string | null;
+    load_csv_settings: LoadCsvSettings | null;
+    video_file_path: string | null;
+    video_start_time: string | null;
+    video_time_scale: number | null;
+    video_sync_points: VideoSyncPoint[];
+    video_time_zone: string | null;
+    display_time_zone: string | null;
+    annotations: Annotation[];
+    video_chapters: VideoChapter[];
+    detection_rules: DetectionRule[];
+    channel_filters: ChannelFilter[];
+    transfer_settings: TransferSettings;
+    is_multiwindow: boolean;
+    is_modified_since_last_save: boolean;
+}
+
+export type AppStateSnapshot = {
+    revision: number;
+    state: AppState;
+}
+
+export type StateChange<T> = {
+    revision: number;
+    value: T;
+}
+
+export type VideoTimeChange = {
+    video_seconds: number;
+    // In the display time zone, with the clock scale applied. Null if the video start time hasn't been set.
+    display_time: string | null;
+}
+
+export type FieldError = {
+    field: string;
+    path: string | null;
+    message: string;
+}
+
+export type ValidationError = {
+    message: string;
+    field_errors: FieldError[];
+}
+
 export type LoadCsvSettings = {
     datetime_index_col: string;
     datetime_parsing_format_string: string;
     load_cols: string[];
     time_bounds?: TimeBounds | null;
+    timestamp_policy?: TimestampPolicy;
+    resample?: ResampleSettings | null;
+    time_zone?: string | null;
+}
+
+export type TimestampPolicy = 'sort' | 'keep_first' | 'keep_last' | 'average_duplicates' | 'fail';
+
+export type ResampleAggregation = 'mean' | 'min' | 'max' | 'first' | 'last';
+
+export type GapFillStrategy = 'none' | 'forward_fill' | 'linear';
+
+export type ResampleSettings = {
+    period_ms: number;
+    default_aggregation?: ResampleAggregation;
+    column_aggregations?: Record<string, ResampleAggregation>;
+    gap_fill?: GapFillStrategy;
+    max_gap_ms?: number | null;
+}
+
+export type TimestampReport = {
+    out_of_order_count: number;
+    duplicate_count: number;
+    policy: TimestampPolicy;
 }
   
 export type TimeBounds = {
     start_time?: Date | null;
     end_time?: Date | null;
-}
\ No newline at end of file
+}
+
+export type Annotation = {
+    id: number;
+    start_time: Date;
+    end_time?: Date | null;
+    title: string;
+    description?: string;
+    category?: string | null;
+}
+
+export type VideoChapter = {
+    id: number;
+    start_time: Date;
+    title: string;
+}
+
+export type VideoSyncPoint = {
+    video_seconds: number;
+    data_time: Date;
+}
+
+export type CrossingDirection = 'rising' | 'falling' | 'either';
+
+export type ThresholdComparison = 'above' | 'below';
+
+export type DetectionRuleKind =
+    | { thresholdCrossing: { threshold: number; direction: CrossingDirection; hysteresis?: number } }
+    | { rateOfChange: { max_rate_per_second: number } }
+    | { heldForDuration: { threshold: number; comparison: ThresholdComparison; min_duration_seconds: number } }
+    | { stepChange: { min_step: number } };
+
+export type DetectionRule = {
+    id: number;
+    name: string;
+    column: string;
+    enabled?: boolean;
+    kind: DetectionRuleKind;
+}
+
+export type DetectionHit = {
+    rule_id: number;
+    rule_name: string;
+    column: string;
+    start_time: Date;
+    end_time: Date;
+}
+
+export type FilterKind =
+    | { butterworthLowPass: { cutoff_hz: number; order: number } }
+    | { butterworthHighPass: { cutoff_hz: number; order: number } }
+    | { butterworthBandPass: { low_cutoff_hz: number; high_cutoff_hz: number; order: number } }
+    | { notch: { center_hz: number; quality_factor: number } }
+    | { movingAverage: { window_size: number } }
+    | { median: { window_size: number } }
+    | { savitzkyGolay: { window_size: number; polynomial_order: number } };
+
+export type ChannelFilter = {
+    column: string;
+    output_column?: string | null;
+    enabled?: boolean;
+    zero_phase?: boolean;
+    kind: FilterKind;
+}
+
+export type ColumnPrecision =
+    | 'float64'
+    | 'float32'
+    | { scaled_integer: { decimal_places: number } };
+
+export type TransferSettings = {
+    column_precision?: Record<string, ColumnPrecision>;
+}
+
+export type TimeWindow =
+    | { range: { start_time: Date; end_time: Date } }
+    | { annotation: { id: number } };
+
+export type JobStatus = 'completed' | 'cancelled' | 'failed';
+
+export type JobProgress = {
+    job_id: number;
+    kind: string;
+    message: string;
+    percent: number | null;
+    processed_rows: number | null;
+}
+
+export type JobFinished = {
+    job_id: number;
+    kind: string;
+    status: JobStatus;
+    error: string | null;
+}
@@ -0,0 +1,29 @@
+import { LoadCsvSettings } from './appState';
+
+export type UserPreferences = {
+    recent_files: RecentFile[];
+    load_csv_templates: LoadCsvTemplate[];
+    default_load_csv_template: string | null;
+    theme: Theme;
+    downsampling: DownsamplingPreferences;
+}
+
+export type RecentFileKind = 'session' | 'csv' | 'video';
+
+export type RecentFile = {
+    path: string;
+    kind: RecentFileKind;
+    last_opened: string;
+}
+
+export type LoadCsvTemplate = {
+    name: string;
+    settings: LoadCsvSettings;
+}
+
+export type Theme = 'light' | 'dark';
+
+export type DownsamplingPreferences = {
+    enabled: boolean;
+    max_points: number;
+}
@@ -0,0 +1,17 @@
+import { Field, Vector } from "apache-arrow";
+
+// Arrow field metadata key the backend puts on ScaledInteger columns (see transfer_encoding.rs)
+const DECIMAL_PLACES_KEY = "decimal_places";
+
+// Returns the values of a column from get_csv_data or get_viewport_data as the numbers the backend holds. ScaledInteger columns arrive as
+// Int32s holding the value times 10^decimal_places, so they are divided back down, keeping nulls as nulls. Float64 and Float32 columns are
+// returned as they are.
+export function decodeTransferColumn(field: Field, column: Vector | null): ArrayLike<number | null> {
+    if (!column) return [];
+
+    const decimalPlaces = field.metadata.get(DECIMAL_PLACES_KEY);
+    if (decimalPlaces === undefined) return column.toArray();
+
+    const scale = 10 ** Number(decimalPlaces);
+    return Array.from(column, (value: number | null) => (value === null ? null : value / scale));
+}